@@ -0,0 +1,70 @@
+use gst::Element;
+use gst::prelude::*;
+use anyhow::Result;
+use tokio::runtime::Handle;
+
+use webrtcredux::webrtcredux::{RTCIceServer, WebRtcRedux};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    gst::init().unwrap();
+    webrtcredux::plugin_register_static().unwrap();
+
+    let pipeline = gst::Pipeline::new(None);
+
+    let webrtcredux = WebRtcRedux::default();
+
+    webrtcredux.set_tokio_runtime(Handle::current());
+
+    webrtcredux.add_ice_servers(vec![RTCIceServer {
+        urls: vec!["stun:stun.comrex.com:3478".to_string()],
+        ..Default::default()
+    }]);
+
+    pipeline
+        .add(webrtcredux.upcast_ref::<gst::Element>())
+        .expect("Failed to add webrtcredux to the pipeline");
+
+    let video_src = gst::ElementFactory::make("videotestsrc", None)?;
+
+    let video_encoder = gst::ElementFactory::make("x264enc", None)?;
+
+    video_encoder.set_property("threads", 12u32);
+    video_encoder.set_property("bitrate", 2048000_u32 / 1000);
+    video_encoder.set_property_from_str("tune", "zerolatency");
+    video_encoder.set_property_from_str("speed-preset", "ultrafast");
+    video_encoder.set_property("key-int-max", 2560u32);
+    video_encoder.set_property("b-adapt", false);
+    video_encoder.set_property("vbv-buf-capacity", 120u32);
+
+    pipeline.add_many(&[&video_src, &video_encoder])?;
+
+    Element::link_many(&[&video_src, &video_encoder])?;
+
+    video_encoder.link(webrtcredux.upcast_ref::<gst::Element>())?;
+
+    let audio_src = gst::ElementFactory::make("audiotestsrc", None)?;
+
+    audio_src.set_property_from_str("wave", "ticks");
+    audio_src.set_property_from_str("tick-interval", "500000000");
+
+    let audio_encoder = gst::ElementFactory::make("opusenc", None)?;
+
+    pipeline.add_many(&[&audio_src, &audio_encoder])?;
+
+    Element::link_many(&[&audio_src, &audio_encoder])?;
+
+    audio_encoder.link(webrtcredux.upcast_ref::<gst::Element>())?;
+
+    pipeline.set_state(gst::State::Playing)?;
+
+    // Every viewer gets its own peer connection, the encoders are shared
+    let addr = webrtcredux.whep_serve("127.0.0.1:8080".parse()?)?;
+    println!("WHEP endpoint listening on http://{}/whep", addr);
+
+    tokio::signal::ctrl_c().await?;
+
+    pipeline.set_state(gst::State::Null)?;
+
+    Ok(())
+}
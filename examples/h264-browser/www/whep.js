@@ -0,0 +1,72 @@
+let pc = null
+let resource = null
+
+let log = msg => {
+    document.getElementById('div').innerHTML += msg + '<br>'
+}
+
+window.startSession = async () => {
+    let endpoint = document.getElementById('endpoint').value
+
+    pc = new RTCPeerConnection({
+        iceServers: [
+            {
+                urls: 'stun:stun.comrex.com:3478'
+            }
+        ]
+    })
+
+    pc.ontrack = function (event) {
+        var el = document.createElement(event.track.kind)
+        el.srcObject = event.streams[0]
+        el.autoplay = true
+        el.controls = true
+
+        document.getElementById('remoteVideos').appendChild(el)
+    }
+
+    pc.oniceconnectionstatechange = e => log(pc.iceConnectionState)
+
+    pc.addTransceiver('video', {'direction': 'recvonly'})
+    pc.addTransceiver('audio', {'direction': 'recvonly'})
+
+    try {
+        await pc.setLocalDescription(await pc.createOffer())
+
+        // The server doesn't trickle, so the offer is sent once gathering is done
+        await new Promise(resolve => {
+            if (pc.iceGatheringState === 'complete') {
+                resolve()
+            } else {
+                pc.onicegatheringstatechange = () => pc.iceGatheringState === 'complete' && resolve()
+            }
+        })
+
+        let response = await fetch(endpoint, {
+            method: 'POST',
+            headers: {'Content-Type': 'application/sdp'},
+            body: pc.localDescription.sdp
+        })
+        if (response.status !== 201) {
+            return log('WHEP endpoint answered ' + response.status)
+        }
+
+        resource = new URL(response.headers.get('Location'), endpoint)
+        await pc.setRemoteDescription({type: 'answer', sdp: await response.text()})
+    } catch (e) {
+        log(e)
+    }
+}
+
+window.stopSession = async () => {
+    if (resource !== null) {
+        await fetch(resource, {method: 'DELETE'})
+        resource = null
+    }
+
+    if (pc !== null) {
+        pc.close()
+        pc = null
+    }
+    document.getElementById('remoteVideos').innerHTML = ''
+}
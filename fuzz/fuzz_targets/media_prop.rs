@@ -0,0 +1,12 @@
+#![no_main]
+
+use std::str::FromStr;
+
+use libfuzzer_sys::fuzz_target;
+use webrtcredux::sdp::MediaProp;
+
+fuzz_target!(|data: &str| {
+    if let Ok(prop) = MediaProp::from_str(data) {
+        let _ = prop.to_string();
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use webrtcredux::sdp::{LineEnding, ParseMode, SDP};
+
+fuzz_target!(|data: &str| {
+    for mode in [ParseMode::Strict, ParseMode::Lenient] {
+        if let Ok(sdp) = SDP::parse(data, mode) {
+            let _ = sdp.to_string(LineEnding::CRLF);
+        }
+    }
+});
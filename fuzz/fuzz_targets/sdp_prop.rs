@@ -0,0 +1,12 @@
+#![no_main]
+
+use std::str::FromStr;
+
+use libfuzzer_sys::fuzz_target;
+use webrtcredux::sdp::{LineEnding, SdpProp};
+
+fuzz_target!(|data: &str| {
+    if let Ok(prop) = SdpProp::from_str(data) {
+        let _ = prop.to_string(LineEnding::LF);
+    }
+});
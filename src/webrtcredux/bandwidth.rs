@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+
+use webrtc::rtcp::packet::Packet;
+use webrtc::rtcp::payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate;
+use webrtc::rtcp::receiver_report::ReceiverReport;
+use webrtc::rtcp::transport_feedbacks::transport_layer_cc::TransportLayerCc;
+
+pub const DEFAULT_MIN_BITRATE: u32 = 100_000;
+pub const DEFAULT_MAX_BITRATE: u32 = 8_000_000;
+pub const DEFAULT_START_BITRATE: u32 = 2_000_000;
+
+/// Changes smaller than this fraction of the current target are not reported, encoders don't like being poked constantly
+const REPORT_THRESHOLD: f64 = 0.05;
+
+/// Loss based sender side estimate (as in GCC) of one peer connection, capped by the receiver's REMB estimate when
+/// there is one
+#[derive(Debug)]
+struct BandwidthEstimator {
+    min_bitrate: u32,
+    max_bitrate: u32,
+    target_bitrate: f64,
+    reported_bitrate: u32,
+    remb_bitrate: Option<f64>,
+    /// `fb_pkt_count` of the newest transport-wide feedback used so far
+    twcc_fb_pkt_count: Option<u8>,
+}
+
+impl BandwidthEstimator {
+    fn new(min_bitrate: u32, max_bitrate: u32, start_bitrate: u32) -> Self {
+        BandwidthEstimator {
+            min_bitrate,
+            max_bitrate,
+            target_bitrate: start_bitrate as f64,
+            reported_bitrate: start_bitrate,
+            remb_bitrate: None,
+            twcc_fb_pkt_count: None,
+        }
+    }
+
+    fn target_bitrate(&self) -> u32 {
+        self.reported_bitrate
+    }
+
+    /// Feeds a batch of RTCP packets received by the video sender with SSRC `ssrc` into the estimate, returns the
+    /// new target if it changed enough to act upon.
+    ///
+    /// Compound packets reach every sender of the connection, only the report blocks about `ssrc` are used so one
+    /// receiver report doesn't count once per track. Transport-wide feedback is about the whole connection, each one
+    /// is only used the first time it shows up.
+    fn on_rtcp(&mut self, ssrc: u32, packets: &[Box<dyn Packet + Send + Sync>]) -> Option<u32> {
+        for packet in packets {
+            let packet = packet.as_any();
+
+            if let Some(remb) = packet.downcast_ref::<ReceiverEstimatedMaximumBitrate>() {
+                self.remb_bitrate = Some(remb.bitrate as f64);
+            } else if let Some(rr) = packet.downcast_ref::<ReceiverReport>() {
+                for report in rr.reports.iter().filter(|report| report.ssrc == ssrc) {
+                    self.on_loss(report.fraction_lost as f64 / 256.0);
+                }
+            } else if let Some(twcc) = packet.downcast_ref::<TransportLayerCc>() {
+                // The count wraps, anything up to half the range behind the newest one was already seen
+                let seen = self
+                    .twcc_fb_pkt_count
+                    .map_or(false, |newest| (twcc.fb_pkt_count.wrapping_sub(newest) as i8) <= 0);
+                if seen {
+                    continue;
+                }
+                self.twcc_fb_pkt_count = Some(twcc.fb_pkt_count);
+
+                if twcc.packet_status_count > 0 {
+                    let received = twcc.recv_deltas.len().min(twcc.packet_status_count as usize);
+                    self.on_loss(1.0 - received as f64 / twcc.packet_status_count as f64);
+                }
+            }
+        }
+
+        self.update()
+    }
+
+    fn on_loss(&mut self, loss: f64) {
+        if loss > 0.1 {
+            self.target_bitrate *= 1.0 - 0.5 * loss;
+        } else if loss < 0.02 {
+            self.target_bitrate *= 1.05;
+        }
+    }
+
+    fn update(&mut self) -> Option<u32> {
+        let mut target = self.target_bitrate;
+        if let Some(remb) = self.remb_bitrate {
+            target = target.min(remb);
+        }
+        target = target.clamp(self.min_bitrate as f64, self.max_bitrate.max(self.min_bitrate) as f64);
+        // Don't let the unconstrained estimate run away from what is actually used
+        self.target_bitrate = target;
+
+        let target = target.round() as u32;
+        let change = (target as f64 - self.reported_bitrate as f64).abs() / self.reported_bitrate.max(1) as f64;
+        if change < REPORT_THRESHOLD {
+            return None;
+        }
+
+        self.reported_bitrate = target;
+        Some(target)
+    }
+}
+
+/// Estimates of every peer connection of the element, by broadcast peer id (`None` for the main connection).
+/// The encoders are shared by all peers, so the element's target is the lowest estimate.
+#[derive(Debug)]
+pub struct BandwidthEstimates {
+    min_bitrate: u32,
+    max_bitrate: u32,
+    start_bitrate: u32,
+    connections: HashMap<Option<String>, BandwidthEstimator>,
+}
+
+impl Default for BandwidthEstimates {
+    fn default() -> Self {
+        BandwidthEstimates {
+            min_bitrate: DEFAULT_MIN_BITRATE,
+            max_bitrate: DEFAULT_MAX_BITRATE,
+            start_bitrate: DEFAULT_START_BITRATE,
+            connections: HashMap::new(),
+        }
+    }
+}
+
+impl BandwidthEstimates {
+    /// The lowest target of all connections, the start bitrate before any feedback
+    pub fn target_bitrate(&self) -> u32 {
+        self.connections
+            .values()
+            .map(BandwidthEstimator::target_bitrate)
+            .min()
+            .unwrap_or(self.start_bitrate)
+    }
+
+    pub fn connection_target_bitrate(&self, peer: Option<&str>) -> u32 {
+        self.connections
+            .get(&peer.map(str::to_string))
+            .map_or(self.start_bitrate, BandwidthEstimator::target_bitrate)
+    }
+
+    pub fn min_bitrate(&self) -> u32 {
+        self.min_bitrate
+    }
+
+    pub fn max_bitrate(&self) -> u32 {
+        self.max_bitrate
+    }
+
+    pub fn set_start_bitrate(&mut self, bitrate: u32) {
+        self.start_bitrate = bitrate;
+        for estimator in self.connections.values_mut() {
+            estimator.target_bitrate = bitrate as f64;
+            estimator.reported_bitrate = bitrate;
+        }
+    }
+
+    pub fn set_min_bitrate(&mut self, bitrate: u32) {
+        self.min_bitrate = bitrate;
+        self.connections.values_mut().for_each(|estimator| estimator.min_bitrate = bitrate);
+    }
+
+    pub fn set_max_bitrate(&mut self, bitrate: u32) {
+        self.max_bitrate = bitrate;
+        self.connections.values_mut().for_each(|estimator| estimator.max_bitrate = bitrate);
+    }
+
+    /// Feeds RTCP received by a video sender of a connection, returns the element's new target if it changed
+    pub fn on_rtcp(&mut self, peer: Option<&str>, ssrc: u32, packets: &[Box<dyn Packet + Send + Sync>]) -> Option<u32> {
+        let before = self.target_bitrate();
+
+        let (min_bitrate, max_bitrate, start_bitrate) = (self.min_bitrate, self.max_bitrate, self.start_bitrate);
+        self.connections
+            .entry(peer.map(str::to_string))
+            .or_insert_with(|| BandwidthEstimator::new(min_bitrate, max_bitrate, start_bitrate))
+            .on_rtcp(ssrc, packets)?;
+
+        let after = self.target_bitrate();
+        (after != before).then(|| after)
+    }
+
+    /// Forgets a broadcast peer, returns the element's new target if the peer was the one holding it down
+    pub fn remove_peer(&mut self, peer: &str) -> Option<u32> {
+        let before = self.target_bitrate();
+        self.connections.remove(&Some(peer.to_string()));
+
+        let after = self.target_bitrate();
+        (after != before).then(|| after)
+    }
+
+    /// Forgets every connection, the next session starts from the start bitrate again
+    pub fn reset(&mut self) {
+        self.connections.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use webrtc::rtcp::reception_report::ReceptionReport;
+
+    use super::*;
+
+    const SSRC: u32 = 1234;
+
+    fn remb(bitrate: f32) -> Vec<Box<dyn Packet + Send + Sync>> {
+        vec![Box::new(ReceiverEstimatedMaximumBitrate {
+            bitrate,
+            ssrcs: vec![SSRC],
+            ..Default::default()
+        })]
+    }
+
+    /// A receiver report with `fraction_lost` out of 256 for `ssrc`
+    fn receiver_report(ssrc: u32, fraction_lost: u8) -> Vec<Box<dyn Packet + Send + Sync>> {
+        vec![Box::new(ReceiverReport {
+            reports: vec![ReceptionReport {
+                ssrc,
+                fraction_lost,
+                ..Default::default()
+            }],
+            ..Default::default()
+        })]
+    }
+
+    #[test]
+    fn remb_caps_the_target() {
+        let mut estimates = BandwidthEstimates::default();
+
+        assert_eq!(estimates.on_rtcp(None, SSRC, &remb(500_000.0)), Some(500_000));
+        // No loss raises the loss based estimate, REMB still caps it
+        assert_eq!(estimates.on_rtcp(None, SSRC, &receiver_report(SSRC, 0)), None);
+        assert_eq!(estimates.target_bitrate(), 500_000);
+
+        assert_eq!(estimates.on_rtcp(None, SSRC, &remb(1_000_000.0)), None, "The estimate only grows with feedback");
+        assert_eq!(estimates.on_rtcp(None, SSRC, &receiver_report(SSRC, 0)), Some(525_000));
+    }
+
+    #[test]
+    fn loss_backs_off() {
+        let mut estimates = BandwidthEstimates::default();
+
+        // 25% loss halves a quarter of the rate away
+        assert_eq!(estimates.on_rtcp(None, SSRC, &receiver_report(SSRC, 64)), Some(1_750_000));
+        // Moderate loss holds the rate
+        assert_eq!(estimates.on_rtcp(None, SSRC, &receiver_report(SSRC, 13)), None);
+        assert_eq!(estimates.target_bitrate(), 1_750_000);
+        // Reports about other tracks don't count
+        assert_eq!(estimates.on_rtcp(None, SSRC, &receiver_report(SSRC + 1, 128)), None);
+        assert_eq!(estimates.target_bitrate(), 1_750_000);
+    }
+
+    #[test]
+    fn target_is_clamped() {
+        let mut estimates = BandwidthEstimates::default();
+        estimates.set_min_bitrate(300_000);
+        estimates.set_max_bitrate(2_100_000);
+
+        assert_eq!(estimates.on_rtcp(None, SSRC, &remb(10_000.0)), Some(300_000));
+
+        let mut estimates = BandwidthEstimates::default();
+        estimates.set_max_bitrate(2_100_000);
+        assert_eq!(estimates.on_rtcp(None, SSRC, &receiver_report(SSRC, 0)), Some(2_100_000));
+        assert_eq!(estimates.on_rtcp(None, SSRC, &receiver_report(SSRC, 0)), None);
+        assert_eq!(estimates.target_bitrate(), 2_100_000);
+    }
+
+    #[test]
+    fn twcc_counts_once_per_connection() {
+        let twcc = |fb_pkt_count: u8| -> Vec<Box<dyn Packet + Send + Sync>> {
+            vec![Box::new(TransportLayerCc {
+                fb_pkt_count,
+                packet_status_count: 4,
+                // Half of the packets never arrived
+                recv_deltas: vec![Default::default(); 2],
+                ..Default::default()
+            })]
+        };
+        let mut estimates = BandwidthEstimates::default();
+
+        // Two video senders of the same connection get the same feedback
+        assert_eq!(estimates.on_rtcp(None, SSRC, &twcc(255)), Some(1_500_000));
+        assert_eq!(estimates.on_rtcp(None, SSRC + 1, &twcc(255)), None);
+        assert_eq!(estimates.on_rtcp(None, SSRC, &twcc(0)), Some(1_125_000), "The count wraps around");
+        assert_eq!(estimates.on_rtcp(None, SSRC + 1, &twcc(255)), None, "Late copies of older feedback are ignored");
+        assert_eq!(estimates.on_rtcp(None, SSRC + 1, &twcc(0)), None);
+        assert_eq!(estimates.target_bitrate(), 1_125_000);
+    }
+
+    #[test]
+    fn slowest_peer_sets_the_target() {
+        let mut estimates = BandwidthEstimates::default();
+
+        assert_eq!(estimates.on_rtcp(None, SSRC, &remb(1_500_000.0)), Some(1_500_000));
+        assert_eq!(estimates.on_rtcp(Some("mobile"), SSRC, &remb(400_000.0)), Some(400_000));
+        assert_eq!(estimates.connection_target_bitrate(None), 1_500_000);
+        assert_eq!(estimates.connection_target_bitrate(Some("mobile")), 400_000);
+
+        // Another peer doing well changes nothing
+        assert_eq!(estimates.on_rtcp(Some("desktop"), SSRC, &remb(3_000_000.0)), None);
+        assert_eq!(estimates.target_bitrate(), 400_000);
+
+        assert_eq!(estimates.remove_peer("mobile"), Some(1_500_000));
+        assert_eq!(estimates.remove_peer("desktop"), None);
+    }
+}
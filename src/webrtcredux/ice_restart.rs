@@ -0,0 +1,127 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
+
+/// Restarts ICE of a connection that stays disconnected or failed for `ice-restart-timeout-ms`, shared by the main
+/// connection and the broadcast peers. Fed with every ICE connection state change of its connection.
+#[derive(Default)]
+pub(crate) struct IceRestartWatcher {
+    state: Mutex<WatcherState>,
+}
+
+#[derive(Default)]
+struct WatcherState {
+    connection_state: RTCIceConnectionState,
+    task: Option<JoinHandle<()>>,
+}
+
+fn is_down(connection_state: RTCIceConnectionState) -> bool {
+    matches!(connection_state, RTCIceConnectionState::Disconnected | RTCIceConnectionState::Failed)
+}
+
+impl IceRestartWatcher {
+    /// Schedules `restart` when the connection goes down, it runs after `timeout_ms` unless the connection recovered
+    /// in the meantime. A timeout of 0 disables automatic restarts.
+    pub(crate) fn on_ice_connection_state_change<F, Fut>(
+        self: &Arc<Self>,
+        handle: &Handle,
+        timeout_ms: u32,
+        connection_state: RTCIceConnectionState,
+        restart: F,
+    ) where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut state = self.state.lock().unwrap();
+        state.connection_state = connection_state;
+
+        match connection_state {
+            RTCIceConnectionState::Disconnected | RTCIceConnectionState::Failed => {
+                if timeout_ms == 0 || state.task.is_some() {
+                    return;
+                }
+
+                let watcher = Arc::downgrade(self);
+                let task = handle.spawn(async move {
+                    tokio::time::sleep(Duration::from_millis(timeout_ms as u64)).await;
+
+                    let down = match watcher.upgrade() {
+                        Some(watcher) => {
+                            let mut state = watcher.state.lock().unwrap();
+                            state.task = None;
+                            is_down(state.connection_state)
+                        }
+                        None => false,
+                    };
+
+                    if down {
+                        restart().await;
+                    }
+                });
+                state.task = Some(task);
+            }
+            RTCIceConnectionState::Connected | RTCIceConnectionState::Completed | RTCIceConnectionState::Closed => {
+                if let Some(task) = state.task.take() {
+                    task.abort();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Drops a pending restart, e.g. when the connection is torn down
+    pub(crate) fn cancel(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.connection_state = RTCIceConnectionState::default();
+        if let Some(task) = state.task.take() {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn watch(watcher: &Arc<IceRestartWatcher>, runtime: &tokio::runtime::Runtime, connection_state: RTCIceConnectionState, restarts: &Arc<AtomicUsize>) {
+        let restarts = restarts.clone();
+        watcher.on_ice_connection_state_change(runtime.handle(), 50, connection_state, move || async move {
+            restarts.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+
+    #[test]
+    fn restarts_once_while_down() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let watcher = Arc::new(IceRestartWatcher::default());
+        let restarts = Arc::new(AtomicUsize::new(0));
+
+        watch(&watcher, &runtime, RTCIceConnectionState::Disconnected, &restarts);
+        watch(&watcher, &runtime, RTCIceConnectionState::Failed, &restarts);
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(restarts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn recovery_cancels_the_restart() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let watcher = Arc::new(IceRestartWatcher::default());
+        let restarts = Arc::new(AtomicUsize::new(0));
+
+        watch(&watcher, &runtime, RTCIceConnectionState::Disconnected, &restarts);
+        watch(&watcher, &runtime, RTCIceConnectionState::Connected, &restarts);
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(restarts.load(Ordering::SeqCst), 0);
+
+        watch(&watcher, &runtime, RTCIceConnectionState::Disconnected, &restarts);
+        watcher.cancel();
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(restarts.load(Ordering::SeqCst), 0);
+    }
+}
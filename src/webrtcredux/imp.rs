@@ -1,17 +1,21 @@
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 use futures::Future;
 use futures::executor::block_on;
 use tokio::sync::{Mutex as AsyncMutex, oneshot};
 
 use anyhow::{Context, Error};
+use bytes::Bytes;
 use gst::{
     gst_debug as debug,
     gst_error as error,
     gst_info as info,
     gst_fixme as fixme,
+    gst_warning as warning,
     EventView, EventRef
 };
 use gst::{ErrorMessage, glib, prelude::*, traits::{ElementExt, GstObjectExt}};
@@ -20,27 +24,57 @@ use interceptor::registry::Registry;
 use once_cell::sync::Lazy;
 use strum_macros::EnumString;
 use tokio::runtime::{self, Handle};
+use tokio::task::JoinHandle;
 use webrtc::api::{API, APIBuilder};
-use webrtc::api::interceptor_registry::register_default_interceptors;
-use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_G722, MIME_TYPE_H264, MIME_TYPE_OPUS, MIME_TYPE_PCMA, MIME_TYPE_PCMU, MIME_TYPE_VP8, MIME_TYPE_VP9};
+use webrtc::api::interceptor_registry::{configure_nack, configure_rtcp_reports, configure_twcc, configure_twcc_receiver_only, configure_twcc_sender_only};
+pub use webrtc::data_channel::RTCDataChannel;
+pub use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+pub use webrtc::data_channel::data_channel_message::DataChannelMessage;
+pub use webrtc::dtls_transport::dtls_fingerprint::RTCDtlsFingerprint;
+pub use webrtc::api::setting_engine::SettingEngine;
+use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_AV1, MIME_TYPE_G722, MIME_TYPE_H264, MIME_TYPE_OPUS, MIME_TYPE_PCMA, MIME_TYPE_PCMU, MIME_TYPE_VP8, MIME_TYPE_VP9};
 pub use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+use webrtc::ice::mdns::MulticastDnsMode;
+use webrtc::ice::network_type::NetworkType;
+use webrtc::ice::udp_network::{EphemeralUDP, UDPNetwork};
+use webrtc::ice_transport::ice_candidate_type::RTCIceCandidateType;
 pub use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
 use webrtc::ice_transport::ice_gatherer::{OnLocalCandidateHdlrFn, OnICEGathererStateChangeHdlrFn};
 pub use webrtc::ice_transport::ice_gatherer_state::RTCIceGathererState;
 pub use webrtc::ice_transport::ice_server::RTCIceServer;
 use webrtc::peer_connection::configuration::RTCConfiguration;
+pub use webrtc::peer_connection::certificate::RTCCertificate;
 pub use webrtc::peer_connection::offer_answer_options::RTCAnswerOptions;
 pub use webrtc::peer_connection::offer_answer_options::RTCOfferOptions;
-use webrtc::peer_connection::{RTCPeerConnection, OnNegotiationNeededHdlrFn, OnICEConnectionStateChangeHdlrFn, OnPeerConnectionStateChangeHdlrFn};
+use webrtc::peer_connection::{RTCPeerConnection, OnDataChannelHdlrFn, OnNegotiationNeededHdlrFn, OnICEConnectionStateChangeHdlrFn, OnPeerConnectionStateChangeHdlrFn};
 pub use webrtc::peer_connection::policy::bundle_policy::RTCBundlePolicy;
+pub use webrtc::peer_connection::policy::ice_transport_policy::RTCIceTransportPolicy;
 pub use webrtc::peer_connection::policy::sdp_semantics::RTCSdpSemantics;
 pub use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
-use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+use webrtc::rtcp::packet::Packet;
+use webrtc::rtcp::payload_feedbacks::full_intra_request::FullIntraRequest;
+use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use webrtc::rtp::packetizer::Payloader;
+pub use webrtc::rtp_transceiver::RTCPFeedback;
+use webrtc::rtp_transceiver::rtp_sender::RTCRtpSender;
+pub use webrtc::rtp_transceiver::rtp_codec::{RTCRtpCodecCapability, RTCRtpCodecParameters, RTCRtpHeaderExtensionCapability};
 use webrtc::track::track_local::TrackLocal;
+use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
 use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+use webrtc::track::track_remote::TrackRemote;
+pub use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
 use crate::sdp::LineEnding;
-use crate::webrtcredux::sender::WebRtcReduxSender;
+use crate::webrtcredux::payloader::{Av1Payloader, H265Payloader};
+use crate::webrtcredux::peer::PeerHandle;
+use crate::webrtcredux::stats::{self, ReceptionStats, Stats};
+use crate::webrtcredux::whip::WhipClient;
+#[cfg(feature = "whep")]
+use crate::webrtcredux::whep::WhepServer;
+use crate::webrtcredux::ice_restart::IceRestartWatcher;
+use crate::webrtcredux::bandwidth::{BandwidthEstimates, DEFAULT_MAX_BITRATE, DEFAULT_MIN_BITRATE, DEFAULT_START_BITRATE};
+use crate::webrtcredux::sender::{SenderTrack, WebRtcReduxSender};
+use crate::webrtcredux::udp_mux::UdpMuxHandle;
 
 use super::sdp::SDP;
 
@@ -59,6 +93,155 @@ static RUNTIME: Lazy<runtime::Runtime> = Lazy::new(|| {
         .unwrap()
 });
 
+/// Registers the codecs webrtc-rs doesn't have defaults for, payload types are picked to not clash with the defaults
+fn register_extra_codecs(media_engine: &mut MediaEngine) -> Result<(), webrtc::Error> {
+    let video_rtcp_feedback = vec![
+        RTCPFeedback { typ: "goog-remb".to_owned(), parameter: "".to_owned() },
+        RTCPFeedback { typ: "ccm".to_owned(), parameter: "fir".to_owned() },
+        RTCPFeedback { typ: "nack".to_owned(), parameter: "".to_owned() },
+        RTCPFeedback { typ: "nack".to_owned(), parameter: "pli".to_owned() },
+    ];
+
+    for (mime_type, sdp_fmtp_line, payload_type) in [
+        // webrtc-rs only knows (constrained) baseline and high, main is what many hardware encoders produce
+        (MIME_TYPE_H264, "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=4d001f", 35),
+        (MIME_TYPE_H264, "level-asymmetry-allowed=1;packetization-mode=0;profile-level-id=4d001f", 36),
+        // Main profile, level 3.1, which is what Safari offers
+        (MIME_TYPE_H265, "level-id=93;profile-id=1;tier-flag=0;tx-mode=SRST", 104),
+        (MIME_TYPE_AV1, "profile=0;level-idx=5;tier=0", 41),
+    ] {
+        media_engine.register_codec(
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: mime_type.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: sdp_fmtp_line.to_owned(),
+                    rtcp_feedback: video_rtcp_feedback.clone(),
+                },
+                payload_type,
+                ..Default::default()
+            },
+            RTPCodecType::Video,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Interceptors registered with the media engine, the default is what webrtc-rs' `register_default_interceptors` sets up
+/// plus the TWCC sender, without it the remote peer has nothing to send transport-wide feedback about.
+///
+/// REMB is signalled through the `rtcp_feedback` of every codec, so it is enabled or disabled with `set_codecs`.
+/// webrtc-rs has no RTX support, so RTX is never offered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interceptors {
+    /// Answers NACKs by retransmitting, adds "nack" and "nack pli" feedback to every video codec
+    pub nack: bool,
+    /// Sender and receiver reports
+    pub rtcp_reports: bool,
+    /// Adds transport-wide sequence numbers to outgoing packets, so the remote peer can send TWCC feedback
+    pub twcc_sender: bool,
+    /// Sends TWCC feedback about incoming packets
+    pub twcc_receiver: bool,
+}
+
+impl Default for Interceptors {
+    fn default() -> Self {
+        Interceptors {
+            nack: true,
+            rtcp_reports: true,
+            twcc_sender: true,
+            twcc_receiver: true,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ApiSettings {
+    /// Replaces the default codecs when set
+    codecs: Option<Vec<RTCRtpCodecParameters>>,
+    header_extensions: Vec<(RTCRtpHeaderExtensionCapability, RTPCodecType)>,
+    interceptors: Interceptors,
+    setting_engine: SettingEngine,
+}
+
+impl ApiSettings {
+    fn build_api(self) -> Result<API, ErrorMessage> {
+        let mut media_engine = MediaEngine::default();
+
+        match self.codecs {
+            Some(codecs) => {
+                for codec in codecs {
+                    let mime_type = codec.capability.mime_type.to_lowercase();
+                    let codec_type = if mime_type.starts_with("video/") {
+                        RTPCodecType::Video
+                    } else if mime_type.starts_with("audio/") {
+                        RTPCodecType::Audio
+                    } else {
+                        return Err(gst::error_msg!(
+                            gst::ResourceError::Settings,
+                            [&format!("Codec {} is neither audio nor video", codec.capability.mime_type)]
+                        ));
+                    };
+
+                    media_engine.register_codec(codec, codec_type).map_err(media_engine_error)?;
+                }
+            }
+            None => {
+                media_engine.register_default_codecs().map_err(media_engine_error)?;
+                register_extra_codecs(&mut media_engine).map_err(media_engine_error)?;
+            }
+        }
+
+        for (extension, codec_type) in self.header_extensions {
+            media_engine.register_header_extension(extension, codec_type, None).map_err(media_engine_error)?;
+        }
+
+        let interceptors = self.interceptors;
+        let mut registry = Registry::new();
+        if interceptors.nack {
+            registry = configure_nack(registry, &mut media_engine);
+        }
+        if interceptors.rtcp_reports {
+            registry = configure_rtcp_reports(registry);
+        }
+        registry = match (interceptors.twcc_sender, interceptors.twcc_receiver) {
+            (true, true) => configure_twcc(registry, &mut media_engine),
+            (true, false) => configure_twcc_sender_only(registry, &mut media_engine),
+            (false, true) => configure_twcc_receiver_only(registry, &mut media_engine),
+            (false, false) => Ok(registry),
+        }
+        .map_err(media_engine_error)?;
+
+        Ok(APIBuilder::new()
+            .with_media_engine(media_engine)
+            .with_interceptor_registry(registry)
+            .with_setting_engine(self.setting_engine)
+            .build())
+    }
+}
+
+fn media_engine_error(e: webrtc::Error) -> ErrorMessage {
+    gst::error_msg!(
+        gst::ResourceError::Settings,
+        [&format!("Failed to configure media engine: {:?}", e)]
+    )
+}
+
+/// Generates a self-signed ECDSA P-256 certificate, the kind webrtc-rs creates when none is configured.
+pub fn generate_certificate() -> Result<RTCCertificate, ErrorMessage> {
+    rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256)
+        .map_err(webrtc::Error::from)
+        .and_then(RTCCertificate::from_key_pair)
+        .map_err(|e| {
+            gst::error_msg!(
+                gst::ResourceError::Settings,
+                [&format!("Failed to generate certificate: {:?}", e)]
+            )
+        })
+}
+
 pub type OnAllTracksAddedFn = Box<dyn FnMut() -> Pin<Box<dyn Future<Output = ()> + Send + 'static>> + Send + Sync>;
 
 #[derive(Debug, PartialEq, Eq, EnumString, Clone, Copy)]
@@ -69,10 +252,14 @@ enum MediaType {
     serialize = "video/x-h264"
     )]
     H264,
-    #[strum(ascii_case_insensitive, serialize = "video/x-vp8")]
+    #[strum(ascii_case_insensitive, serialize = "video/VP8", serialize = "video/x-vp8")]
     VP8,
-    #[strum(ascii_case_insensitive, serialize = "video/x-vp9")]
+    #[strum(ascii_case_insensitive, serialize = "video/VP9", serialize = "video/x-vp9")]
     VP9,
+    #[strum(ascii_case_insensitive, serialize = "video/H265", serialize = "video/x-h265")]
+    H265,
+    #[strum(ascii_case_insensitive, serialize = "video/AV1", serialize = "video/x-av1")]
+    AV1,
     #[strum(
     ascii_case_insensitive,
     serialize = "audio/opus",
@@ -101,18 +288,151 @@ impl MediaType {
             MediaType::H264 => MIME_TYPE_H264,
             MediaType::VP8 => MIME_TYPE_VP8,
             MediaType::VP9 => MIME_TYPE_VP9,
+            MediaType::H265 => MIME_TYPE_H265,
+            MediaType::AV1 => MIME_TYPE_AV1,
             MediaType::Opus => MIME_TYPE_OPUS,
             MediaType::G722 => MIME_TYPE_G722,
             MediaType::Mulaw => MIME_TYPE_PCMU,
             MediaType::Alaw => MIME_TYPE_PCMA,
         }
     }
+
+    /// webrtc-rs can't packetize these itself, so samples go through our own payloader
+    fn payloader(self) -> Option<Box<dyn Payloader + Send + Sync>> {
+        match self {
+            MediaType::H265 => Some(Box::new(H265Payloader::default())),
+            MediaType::AV1 => Some(Box::new(Av1Payloader::default())),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+/// fmtp line matching what the encoder produces, so webrtc-rs binds the track to the right H.264 payload type.
+/// RTP caps from a payloader already carry `profile-level-id` and `packetization-mode`.
+fn h264_fmtp_line(structure: &gst::StructureRef) -> String {
+    let profile_level_id = structure
+        .get::<String>("profile-level-id")
+        .unwrap_or_else(|_| {
+            h264_profile_level_id(
+                &structure.get::<String>("profile").unwrap_or_default(),
+                structure.get::<String>("level").ok().as_deref(),
+            )
+        });
+    let packetization_mode = structure
+        .get::<String>("packetization-mode")
+        .unwrap_or_else(|_| "1".to_string());
+
+    format!("level-asymmetry-allowed=1;packetization-mode={};profile-level-id={}", packetization_mode, profile_level_id)
+}
+
+/// https://datatracker.ietf.org/doc/html/rfc6184#section-8.1
+fn h264_profile_level_id(profile: &str, level: Option<&str>) -> String {
+    let (profile_idc, mut constraints) = match profile {
+        "baseline" => (0x42, 0x00),
+        "main" => (0x4d, 0x00),
+        "high" => (0x64, 0x00),
+        // Constrained baseline is what browsers default to
+        _ => (0x42, 0xe0),
+    };
+
+    let level_idc = match level {
+        // Level 1b is signalled with constraint_set3_flag outside of the high profiles
+        Some("1b") if profile_idc == 0x64 => 9,
+        Some("1b") => {
+            constraints |= 0x10;
+            11
+        }
+        Some(level) => {
+            let mut parts = level.splitn(2, '.');
+            let major = parts.next().and_then(|major| major.parse::<u8>().ok());
+            let minor = parts.next().map_or(Some(0), |minor| minor.parse::<u8>().ok());
+            match (major, minor) {
+                (Some(major), Some(minor)) => major.checked_mul(10).and_then(|level| level.checked_add(minor)).unwrap_or(31),
+                _ => 31,
+            }
+        }
+        None => 31,
+    };
+
+    format!("{:02x}{:02x}{:02x}", profile_idc, constraints, level_idc)
+}
+
+/// webrtc-rs has no constant for H.265
+const MIME_TYPE_H265: &str = "video/H265";
+
+/// Picture Loss Indication and Full Intra Request both mean the remote decoder needs a new keyframe
+fn is_keyframe_request(packet: &(dyn Packet + Send + Sync)) -> bool {
+    let packet = packet.as_any();
+    packet.downcast_ref::<PictureLossIndication>().is_some()
+        || packet.downcast_ref::<FullIntraRequest>().is_some()
+}
+
+fn set_encoder_bitrate(encoder: &gst::Element, property: &str, bitrate: u64) {
+    let pspec = match encoder.find_property(property) {
+        Some(pspec) => pspec,
+        None => {
+            error!(CAT, obj: encoder, "Encoder has no property '{}' to apply the target bitrate to", property);
+            return;
+        }
+    };
+
+    let value = match pspec.value_type() {
+        t if t == u32::static_type() => (bitrate.min(u32::MAX as u64) as u32).to_value(),
+        t if t == i32::static_type() => (bitrate.min(i32::MAX as u64) as i32).to_value(),
+        t if t == u64::static_type() => bitrate.to_value(),
+        t if t == i64::static_type() => (bitrate.min(i64::MAX as u64) as i64).to_value(),
+        t => {
+            error!(CAT, obj: encoder, "Unsupported type {} for bitrate property '{}'", t, property);
+            return;
+        }
+    };
+
+    encoder.set_property_from_value(property, &value);
+}
+
+/// Parsers, queues and capsfilters usually sit between the encoder and the sink pad,
+/// so the first upstream element with the bitrate property or an encoder klass is used
+fn upstream_encoder(sink_pad: &gst::GhostPad, property: &str) -> Option<gst::Element> {
+    let mut pad = sink_pad.peer()?;
+    // Bounded in case of a loop in a broken pipeline
+    for _ in 0..16 {
+        let element = pad.parent_element()?;
+        let is_encoder = element
+            .factory()
+            .and_then(|factory| factory.metadata("klass").map(|klass| klass.contains("Encoder")))
+            .unwrap_or(false);
+        if is_encoder || element.find_property(property).is_some() {
+            return Some(element);
+        }
+
+        // Only a single upstream branch can be followed
+        let mut sink_pads = element.sink_pads().into_iter();
+        pad = match (sink_pads.next(), sink_pads.next()) {
+            (Some(sink), None) => sink.peer()?,
+            _ => return None,
+        };
+    }
+
+    None
+}
+
+pub(crate) fn parse_sdp(sdp: &str) -> Result<SDP, ErrorMessage> {
+    SDP::from_str(sdp).map_err(|e| {
+        gst::error_msg!(
+            gst::ResourceError::Failed,
+            ["Failed to parse SDP: {}", e]
+        )
+    })
+}
+
+#[derive(Clone)]
 struct InputStream {
     sink_pad: gst::GhostPad,
     sender: Option<WebRtcReduxSender>,
+    track: Option<Arc<dyn TrackLocal + Send + Sync>>,
+    rtp_sender: Option<Arc<RTCRtpSender>>,
+    /// Senders of the same track on the broadcast peers, by peer id
+    peer_senders: HashMap<String, Arc<RTCRtpSender>>,
 }
 
 pub fn make_element(element: &str, name: Option<&str>) -> Result<gst::Element, Error> {
@@ -149,29 +469,148 @@ impl InputStream {
     }
 }
 
-struct WebRtcState {
-    api: API,
-    peer_connection: Option<RTCPeerConnection>
+/// Shortest time between two keyframe requests forwarded upstream for the same pad
+const MIN_KEYFRAME_REQUEST_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Buffers of a data pad kept until its channel opens, later ones are dropped
+const MAX_PENDING_DATA_BUFFERS: usize = 256;
+
+#[derive(Clone)]
+struct DataInputStream {
+    sink_pad: gst::GhostPad,
+    appsink: Option<gst_app::AppSink>,
+    channel: Option<Arc<RTCDataChannel>>,
 }
 
-impl Default for WebRtcState {
-    fn default() -> Self {
-        let mut media_engine = MediaEngine::default();
-        media_engine.register_default_codecs().expect("Failed to register default codecs");
-        let mut registry = Registry::new();
-        registry = register_default_interceptors(registry, &mut media_engine)
-            .expect("Failed to register default interceptors");
+impl DataInputStream {
+    fn prepare(&mut self, element: &super::WebRtcRedux, channel: Arc<RTCDataChannel>, handle: Handle) -> Result<(), Error> {
+        let appsink = make_element("appsink", None)?
+            .dynamic_cast::<gst_app::AppSink>()
+            .unwrap();
+        appsink.set_sync(false);
+
+        // Sending fails until SCTP is up, data arriving before that is queued and flushed in order once the channel opens
+        let pending = Arc::new(AsyncMutex::new(Some(Vec::<Bytes>::new())));
+
+        let send_channel = channel.clone();
+        let send_pending = pending.clone();
+        let send_handle = handle.clone();
+        let element_weak = element.downgrade();
+        appsink.set_callbacks(
+            gst_app::AppSinkCallbacks::builder()
+                .new_sample(move |appsink| {
+                    let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                    let buffer = sample.buffer().ok_or(gst::FlowError::Error)?;
+                    let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+                    let bytes = Bytes::copy_from_slice(map.as_slice());
+
+                    let channel = send_channel.clone();
+                    let pending = send_pending.clone();
+                    let element_weak = element_weak.clone();
+                    let handle = send_handle.clone();
+                    let inner = handle.clone();
+                    block_on(async move {
+                        handle.spawn_blocking(move || {
+                            inner.block_on(async move {
+                                if let Some(queue) = pending.lock().await.as_mut() {
+                                    if queue.len() < MAX_PENDING_DATA_BUFFERS {
+                                        queue.push(bytes);
+                                    } else if let Some(element) = element_weak.upgrade() {
+                                        warning!(CAT, obj: &element, "Data channel {} is not open yet, dropping buffer", channel.label());
+                                    }
+                                    return;
+                                }
+
+                                if let Err(e) = channel.send(&bytes).await {
+                                    if let Some(element) = element_weak.upgrade() {
+                                        warning!(CAT, obj: &element, "Failed to send on data channel {}: {:?}", channel.label(), e);
+                                    }
+                                }
+                            })
+                        }).await
+                    })
+                    .map_err(|_| gst::FlowError::Error)?;
 
-        WebRtcState {
-            api: APIBuilder::new()
-                .with_media_engine(media_engine)
-                .with_interceptor_registry(registry)
+                    Ok(gst::FlowSuccess::Ok)
+                })
                 .build(),
-            peer_connection: Default::default()
+        );
+
+        let open_channel = channel.clone();
+        let element_weak = element.downgrade();
+        // webrtc-rs spawns the handler right away on the current runtime if the channel is already open
+        let _guard = handle.enter();
+        channel.on_open(Box::new(move || {
+            Box::pin(async move {
+                let mut pending = pending.lock().await;
+                for bytes in pending.take().unwrap_or_default() {
+                    if let Err(e) = open_channel.send(&bytes).await {
+                        if let Some(element) = element_weak.upgrade() {
+                            warning!(CAT, obj: &element, "Failed to send on data channel {}: {:?}", open_channel.label(), e);
+                        }
+                    }
+                }
+            })
+        }));
+
+        element.add(&appsink).expect("Failed to add data channel appsink");
+
+        element
+            .sync_children_states()
+            .with_context(|| format!("Linking data stream {}", self.sink_pad.name()))?;
+
+        self.sink_pad
+            .set_target(Some(&appsink.static_pad("sink").unwrap()))
+            .unwrap();
+
+        self.appsink = Some(appsink);
+        self.channel = Some(channel);
+
+        Ok(())
+    }
+
+    fn unprepare(&mut self, element: &super::WebRtcRedux, handle: &Handle) {
+        self.sink_pad.set_target(None::<&gst::Pad>).unwrap();
+
+        if let Some(appsink) = self.appsink.take() {
+            element.remove(&appsink).unwrap();
+            appsink.set_state(gst::State::Null).unwrap();
+        }
+
+        if let Some(channel) = self.channel.take() {
+            handle.spawn(async move { channel.close().await });
         }
     }
 }
 
+#[derive(Debug, Clone)]
+struct OutputStream {
+    src_pad: gst::GhostPad,
+    appsrc: gst_app::AppSrc,
+}
+
+impl OutputStream {
+    fn teardown(&self, element: &super::WebRtcRedux) {
+        let _ = self.appsrc.end_of_stream();
+        self.src_pad.set_target(None::<&gst::Pad>).unwrap();
+        self.appsrc.set_state(gst::State::Null).unwrap();
+        element.remove(&self.appsrc).unwrap();
+        let _ = self.src_pad.set_active(false);
+        element.remove_pad(&self.src_pad).unwrap();
+    }
+}
+
+#[derive(Default)]
+struct WebRtcState {
+    /// Built from the media settings at NullToReady
+    api: Option<API>,
+    peer_connection: Option<RTCPeerConnection>,
+    /// Kept around to create broadcast peers with the same configuration as the main connection
+    config: Option<RTCConfiguration>,
+    peers: HashMap<String, Arc<RTCPeerConnection>>,
+    udp_mux: Option<UdpMuxHandle>,
+}
+
 #[derive(Default)]
 struct State {
     video_state: HashMap<usize, String>,
@@ -179,29 +618,245 @@ struct State {
     audio_state: HashMap<usize, String>,
     next_audio_pad_id: usize,
     streams: HashMap<String, InputStream>,
+    next_recv_video_pad_id: usize,
+    next_recv_audio_pad_id: usize,
+    outputs: HashMap<String, OutputStream>,
+    next_data_pad_id: usize,
+    next_recv_data_pad_id: usize,
+    data_streams: HashMap<String, DataInputStream>,
     handle: Option<Handle>,
     on_all_tracks_added_send: Option<oneshot::Sender<()>>,
     on_all_tracks_added: Option<oneshot::Receiver<()>>,
-    tracks: usize
+    stats_task: Option<JoinHandle<()>>,
+    /// When a keyframe was last requested upstream, by sink pad name
+    last_keyframe_requests: HashMap<String, Instant>,
+    /// The next offer restarts ICE
+    ice_restart: bool,
 }
 
+/// x264enc, the encoder used throughout the examples, takes its bitrate in kbit/s
+const DEFAULT_ENCODER_BITRATE_DIVISOR: u32 = 1000;
+
 struct WebRtcSettings {
     config: Option<RTCConfiguration>,
+    /// Taken together with the configuration at NullToReady
+    api: Option<ApiSettings>,
+    stun_server: Option<String>,
+    turn_server: Option<String>,
+    encoder_bitrate_property: Option<String>,
+    encoder_bitrate_divisor: u32,
+    stats_interval_ms: u32,
+    min_port: u32,
+    max_port: u32,
+    nat_1to1_ips: Option<String>,
+    nat_1to1_candidate_type: Option<String>,
+    udp_mux_port: u32,
+    ice_restart_timeout_ms: u32,
+    network_types: Option<String>,
+    interface_filter: Option<String>,
+    ip_filter: Option<String>,
+    mdns_mode: Option<String>,
+    ice_disconnected_timeout_ms: u32,
+    ice_failed_timeout_ms: u32,
+    ice_keepalive_interval_ms: u32,
 }
 
 impl Default for WebRtcSettings {
     fn default() -> Self {
         WebRtcSettings {
             config: Some(RTCConfiguration::default()),
+            api: Some(ApiSettings::default()),
+            stun_server: None,
+            turn_server: None,
+            encoder_bitrate_property: None,
+            encoder_bitrate_divisor: DEFAULT_ENCODER_BITRATE_DIVISOR,
+            stats_interval_ms: 0,
+            min_port: 0,
+            max_port: 0,
+            nat_1to1_ips: None,
+            nat_1to1_candidate_type: None,
+            udp_mux_port: 0,
+            ice_restart_timeout_ms: 0,
+            network_types: None,
+            interface_filter: None,
+            ip_filter: None,
+            mdns_mode: None,
+            ice_disconnected_timeout_ms: 0,
+            ice_failed_timeout_ms: 0,
+            ice_keepalive_interval_ms: 0,
+        }
+    }
+}
+
+impl WebRtcSettings {
+    /// ICE servers configured through the `stun-server` and `turn-server` properties
+    fn property_ice_servers(&self) -> Vec<RTCIceServer> {
+        let mut ice_servers = Vec::new();
+
+        if let Some(stun_server) = &self.stun_server {
+            ice_servers.push(RTCIceServer {
+                urls: vec![format!("stun:{}", stun_server.trim_start_matches("stun://"))],
+                ..Default::default()
+            });
+        }
+
+        if let Some(turn_server) = &self.turn_server {
+            // Same format as webrtcbin, turn(s)://username:password@host:port
+            let (scheme, rest) = turn_server.split_once("://").unwrap_or(("turn", turn_server));
+            let (username, credential, host) = match rest.rsplit_once('@') {
+                Some((userinfo, host)) => {
+                    let (username, credential) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+                    (username.to_string(), credential.to_string(), host)
+                }
+                None => (String::new(), String::new(), rest),
+            };
+
+            ice_servers.push(RTCIceServer {
+                urls: vec![format!("{}:{}", scheme, host)],
+                username,
+                credential,
+                ..Default::default()
+            });
+        }
+
+        ice_servers
+    }
+
+    /// Applies the network properties on top of the user's setting engine, returns the shared UDP mux if one is used.
+    /// Every property is checked before the setting engine is touched, so a failed attempt leaves it as it was.
+    fn apply_network_properties(&self, setting_engine: &mut SettingEngine) -> Result<Option<UdpMuxHandle>, ErrorMessage> {
+        let invalid = |property: &str, value: &str| {
+            gst::error_msg!(
+                gst::ResourceError::Settings,
+                [&format!("Invalid {} value {}", property, value)]
+            )
+        };
+
+        let ephemeral = if self.min_port != 0 || self.max_port != 0 {
+            let max_port = if self.max_port == 0 { u16::MAX } else { self.max_port as u16 };
+            Some(EphemeralUDP::new(self.min_port as u16, max_port).map_err(|e| {
+                gst::error_msg!(
+                    gst::ResourceError::Settings,
+                    [&format!("Invalid port range {}-{}: {:?}", self.min_port, max_port, e)]
+                )
+            })?)
+        } else {
+            None
+        };
+
+        let nat_1to1_ips = comma_separated(&self.nat_1to1_ips);
+        let nat_1to1_candidate_type = match self.nat_1to1_candidate_type.as_deref().unwrap_or("host") {
+            "host" => RTCIceCandidateType::Host,
+            "srflx" => RTCIceCandidateType::Srflx,
+            other => return Err(invalid("nat-1to1-candidate-type", other)),
+        };
+
+        let network_types = comma_separated(&self.network_types)
+            .iter()
+            .map(|network_type| match network_type.as_str() {
+                "udp4" => Ok(NetworkType::Udp4),
+                "udp6" => Ok(NetworkType::Udp6),
+                // webrtc-rs 0.6 only gathers UDP candidates, asking for TCP would silently gather nothing
+                "tcp4" | "tcp6" => Err(gst::error_msg!(
+                    gst::ResourceError::Settings,
+                    [&format!("Invalid network-types value {}, webrtc-rs 0.6 ICE cannot gather TCP candidates", network_type)]
+                )),
+                other => Err(invalid("network-types", other)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let interfaces = comma_separated(&self.interface_filter);
+
+        let local_ips = comma_separated(&self.ip_filter)
+            .iter()
+            .map(|ip| IpAddr::from_str(ip).map_err(|_| invalid("ip-filter", ip)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mdns_mode = match self.mdns_mode.as_deref() {
+            None => None,
+            Some("disabled") => Some(MulticastDnsMode::Disabled),
+            Some("query-only") => Some(MulticastDnsMode::QueryOnly),
+            Some("query-and-gather") => Some(MulticastDnsMode::QueryAndGather),
+            Some(other) => return Err(invalid("mdns-mode", other)),
+        };
+
+        // The mux lives on the plugin's runtime, it is shared with elements that may use other runtimes
+        let udp_mux = if self.udp_mux_port != 0 {
+            Some(UdpMuxHandle::acquire(self.udp_mux_port as u16, RUNTIME.handle())?)
+        } else {
+            None
+        };
+
+        if let Some(ephemeral) = ephemeral {
+            setting_engine.set_udp_network(UDPNetwork::Ephemeral(ephemeral));
+        }
+
+        if !nat_1to1_ips.is_empty() {
+            setting_engine.set_nat_1to1_ips(nat_1to1_ips, nat_1to1_candidate_type);
+        }
+
+        if !network_types.is_empty() {
+            setting_engine.set_network_types(network_types);
+        }
+
+        if !interfaces.is_empty() {
+            setting_engine.set_interface_filter(Box::new(move |interface| interfaces.iter().any(|allowed| allowed == interface)));
+        }
+
+        if !local_ips.is_empty() {
+            setting_engine.set_ip_filter(Box::new(move |ip| local_ips.contains(&ip)));
+        }
+
+        if let Some(mode) = mdns_mode {
+            setting_engine.set_ice_multicast_dns_mode(mode);
+        }
+
+        // webrtc-rs sets the three together, the ones left at 0 keep its defaults
+        if self.ice_disconnected_timeout_ms != 0 || self.ice_failed_timeout_ms != 0 || self.ice_keepalive_interval_ms != 0 {
+            let timeout = |ms: u32| (ms != 0).then(|| Duration::from_millis(ms as u64));
+            setting_engine.set_ice_timeouts(
+                timeout(self.ice_disconnected_timeout_ms),
+                timeout(self.ice_failed_timeout_ms),
+                timeout(self.ice_keepalive_interval_ms),
+            );
+        }
+
+        // Takes precedence over the port range
+        if let Some(udp_mux) = &udp_mux {
+            setting_engine.set_udp_network(UDPNetwork::Muxed(udp_mux.mux()));
         }
+
+        Ok(udp_mux)
     }
 }
 
+/// Trimmed, non-empty entries of a comma separated property
+fn comma_separated(value: &Option<String>) -> Vec<String> {
+    value
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
 #[derive(Default)]
 pub struct WebRtcRedux {
     state: Mutex<State>,
     webrtc_state: Arc<AsyncMutex<WebRtcState>>,
     webrtc_settings: Mutex<WebRtcSettings>,
+    on_data_channel: AsyncMutex<Option<OnDataChannelHdlrFn>>,
+    on_ice_candidate: AsyncMutex<Option<OnLocalCandidateHdlrFn>>,
+    on_negotiation_needed: AsyncMutex<Option<OnNegotiationNeededHdlrFn>>,
+    on_ice_connection_state_change: AsyncMutex<Option<OnICEConnectionStateChangeHdlrFn>>,
+    bandwidth: Mutex<BandwidthEstimates>,
+    reception_stats: Mutex<HashMap<u32, ReceptionStats>>,
+    /// Automatic ICE restarts of the main connection
+    ice_restart_watcher: Arc<IceRestartWatcher>,
+    whip_client: Mutex<Option<WhipClient>>,
+    #[cfg(feature = "whep")]
+    whep_server: Mutex<Option<WhepServer>>,
 }
 
 impl WebRtcRedux {
@@ -215,9 +870,37 @@ impl WebRtcRedux {
             .iter_mut()
             .try_for_each(|(_, stream)| stream.prepare(element))?;
 
+        let data_names = self.state.lock().unwrap().data_streams.keys().cloned().collect::<Vec<_>>();
+        for name in data_names {
+            self.prepare_data_stream(element, &name)?;
+        }
+
         Ok(())
     }
 
+    fn prepare_data_stream(&self, element: &super::WebRtcRedux, name: &str) -> Result<(), Error> {
+        let webrtc_state = self.webrtc_state.clone();
+        let handle = self.runtime_handle();
+        let inner = handle.clone();
+        let label = name.to_string();
+        let channel = block_on(async move {
+            handle.spawn_blocking(move || {
+                inner.block_on(async move {
+                    webrtc_state.lock().await.peer_connection.as_ref().unwrap().create_data_channel(&label, None).await
+                })
+            }).await
+        })?.with_context(|| format!("Creating data channel {}", name))?;
+
+        let handle = self.runtime_handle();
+        self.state
+            .lock()
+            .unwrap()
+            .data_streams
+            .get_mut(name)
+            .unwrap()
+            .prepare(element, channel, handle)
+    }
+
     fn unprepare(&self, element: &super::WebRtcRedux) -> Result<(), Error> {
         info!(CAT, obj: element, "unpreparing");
 
@@ -227,6 +910,12 @@ impl WebRtcRedux {
             .streams
             .iter_mut()
             .for_each(|(_, stream)| stream.unprepare(element));
+
+        let handle = state.handle.as_ref().unwrap_or(RUNTIME.handle()).clone();
+        state
+            .data_streams
+            .iter_mut()
+            .for_each(|(_, stream)| stream.unprepare(element, &handle));
         Ok(())
     }
 
@@ -257,29 +946,154 @@ impl WebRtcRedux {
     }
 
     pub fn set_sdp_semantics(&self, sdp_semantics: RTCSdpSemantics) {
+        if sdp_semantics != RTCSdpSemantics::UnifiedPlan {
+            error!(CAT, "Only unified plan SDP semantics are supported, ignoring {}", sdp_semantics);
+        }
+    }
+
+    pub fn set_codecs(&self, codecs: Vec<RTCRtpCodecParameters>) {
         let mut webrtc_settings = self.webrtc_settings.lock().unwrap();
 
-        match webrtc_settings.config {
-            Some(ref mut config) => {
-                config.sdp_semantics = sdp_semantics;
+        match webrtc_settings.api {
+            Some(ref mut api) => {
+                api.codecs = Some(codecs);
+            }
+            None => {
+                error!(CAT, "Trying to set codecs after starting");
+            }
+        }
+    }
+
+    pub fn set_header_extensions(&self, header_extensions: Vec<(RTCRtpHeaderExtensionCapability, RTPCodecType)>) {
+        let mut webrtc_settings = self.webrtc_settings.lock().unwrap();
+
+        match webrtc_settings.api {
+            Some(ref mut api) => {
+                api.header_extensions = header_extensions;
             }
             None => {
-                error!(CAT, "Trying to set sdp semantics after starting");
+                error!(CAT, "Trying to set header extensions after starting");
             }
         }
     }
 
+    pub fn set_setting_engine(&self, setting_engine: SettingEngine) {
+        let mut webrtc_settings = self.webrtc_settings.lock().unwrap();
+
+        match webrtc_settings.api {
+            Some(ref mut api) => {
+                api.setting_engine = setting_engine;
+            }
+            None => {
+                error!(CAT, "Trying to set setting engine after starting");
+            }
+        }
+    }
+
+    pub fn set_interceptors(&self, interceptors: Interceptors) {
+        let mut webrtc_settings = self.webrtc_settings.lock().unwrap();
+
+        match webrtc_settings.api {
+            Some(ref mut api) => {
+                api.interceptors = interceptors;
+            }
+            None => {
+                error!(CAT, "Trying to set interceptors after starting");
+            }
+        }
+    }
+
+    pub fn set_certificates(&self, certificates: Vec<RTCCertificate>) -> Result<(), ErrorMessage> {
+        let mut webrtc_settings = self.webrtc_settings.lock().unwrap();
+
+        match webrtc_settings.config {
+            Some(ref mut config) => {
+                config.certificates = certificates;
+                Ok(())
+            }
+            None => Err(gst::error_msg!(
+                gst::ResourceError::Settings,
+                ["Certificates can only be set before the element goes to Ready"]
+            )),
+        }
+    }
+
+    pub fn set_certificate_pem(&self, key_pem: &str, cert_pem: &str) -> Result<(), ErrorMessage> {
+        let invalid = |message: String| gst::error_msg!(gst::ResourceError::Settings, [&message]);
+
+        let key_pair = rcgen::KeyPair::from_pem(key_pem)
+            .map_err(|e| invalid(format!("Failed to parse private key: {:?}", e)))?;
+        let certs = pem::parse_many(cert_pem)
+            .map_err(|e| invalid(format!("Failed to parse certificate: {:?}", e)))?
+            .into_iter()
+            .filter(|block| block.tag == "CERTIFICATE")
+            .collect::<Vec<_>>();
+        let leaf = certs.first().ok_or_else(|| invalid("No certificate in the PEM".to_string()))?;
+
+        let (_, x509) = x509_parser::parse_x509_certificate(&leaf.contents)
+            .map_err(|e| invalid(format!("Failed to parse certificate: {:?}", e)))?;
+        if x509.public_key().subject_public_key.data != key_pair.public_key_raw() {
+            return Err(invalid("The private key does not belong to the certificate".to_string()));
+        }
+        // webrtc-rs refuses to create peer connections once this has passed
+        let expires = SystemTime::UNIX_EPOCH + Duration::from_secs(x509.validity().not_after.timestamp().max(0) as u64);
+        if expires <= SystemTime::now() {
+            return Err(invalid("The certificate has expired".to_string()));
+        }
+
+        // The layout webrtc-rs parses, a PKCS#8 key tagged PRIVATE_KEY followed by the chain
+        let mut blocks = vec![pem::Pem {
+            tag: "PRIVATE_KEY".to_string(),
+            contents: key_pair.serialize_der(),
+        }];
+        blocks.extend(certs);
+        let dtls_certificate = webrtc::dtls::crypto::Certificate::from_pem(&pem::encode_many(&blocks))
+            .map_err(|e| invalid(format!("Unsupported certificate: {:?}", e)))?;
+
+        self.set_certificates(vec![RTCCertificate::from_existing(dtls_certificate, expires)])
+    }
+
+    /// Fingerprints of the certificate every peer connection of the element presents, written like
+    /// the `a=fingerprint` lines of its descriptions
+    pub async fn local_fingerprints(&self) -> Result<Vec<RTCDtlsFingerprint>, ErrorMessage> {
+        let webrtc_state = self.webrtc_state.lock().await;
+
+        match &webrtc_state.config {
+            Some(config) => Ok(config
+                .certificates
+                .iter()
+                .flat_map(|certificate| certificate.get_fingerprints())
+                .map(|fingerprint| RTCDtlsFingerprint {
+                    value: fingerprint.value.to_uppercase(),
+                    ..fingerprint
+                })
+                .collect()),
+            None => Err(gst::error_msg!(
+                gst::ResourceError::Failed,
+                ["The certificate is only known once the element is ready"]
+            )),
+        }
+    }
+
     fn sink_event(&self, pad: &gst::Pad, element: &super::WebRtcRedux, event: gst::Event) -> bool {
         match event.view() {
             EventView::Caps(caps) => {
-                self.create_track(&pad.name(), &caps);
+                if !self.create_track(element, &pad.name(), &caps) {
+                    return false;
+                }
                 pad.event_default(Some(element), event)
             },
             _ => pad.event_default(Some(element), event)
         }
     }
 
-    fn create_track(&self, name: &str, caps: &gst::event::Caps<&EventRef>) {
+    /// Returns false if the caps can't be sent, in which case an error has been posted
+    fn create_track(&self, element: &super::WebRtcRedux, name: &str, caps: &gst::event::Caps<&EventRef>) -> bool {
+        if self.state.lock().unwrap().streams.get(name).map_or(true, |stream| stream.rtp_sender.is_some()) {
+            fixme!(CAT, obj: element, "Ignoring caps change on {}, the track has already been created", name);
+            return true;
+        }
+
         let name_parts = name.split('_').collect::<Vec<_>>();
         let id: usize = name_parts[1].parse().unwrap();
 
@@ -287,8 +1101,14 @@ impl WebRtcRedux {
         let structure = caps.structure(0).unwrap();
         let mime = structure.name();
         let duration = if name.starts_with("video") {
-            let framerate = structure.get::<gst::Fraction>("framerate").unwrap().0;
-            Some(gst::ClockTime::from_mseconds(((*framerate.denom() as f64 / *framerate.numer() as f64)  * 1000.0).round() as u64))
+            match structure.get::<gst::Fraction>("framerate") {
+                Ok(framerate) if *framerate.0.numer() > 0 && *framerate.0.denom() > 0 => {
+                    let framerate = framerate.0;
+                    Some(gst::ClockTime::from_nseconds(((*framerate.denom() as f64 / *framerate.numer() as f64) * 1_000_000_000.0).round() as u64))
+                }
+                // Variable framerate (0/1) or no framerate at all, the sender times samples from buffer PTS
+                _ => None,
+            }
         } else {
             None
         };
@@ -314,32 +1134,87 @@ impl WebRtcRedux {
             }
         };
 
-        let track  = Arc::new(TrackLocalStaticSample::new(
-            RTCRtpCodecCapability {
-                mime_type: MediaType::from_str(mime).expect("Failed to parse mime type").webrtc_mime().to_string(),
-                ..RTCRtpCodecCapability::default()
-            }, 
-            name_parts[0].to_string(), 
-            stream_id
-        ));
+        // RTP caps carry the codec in encoding-name, which matches the second half of the webrtc-rs mime type
+        let is_rtp = mime == "application/x-rtp";
+        let codec = if is_rtp {
+            let encoding_name = structure.get::<String>("encoding-name").unwrap_or_default();
+            MediaType::from_str(&format!("{}/{}", name_parts[0], encoding_name))
+        } else {
+            MediaType::from_str(mime)
+        };
+        let codec = match codec {
+            Ok(codec) => codec,
+            Err(_) => {
+                gst::element_error!(element, gst::StreamError::Format, ["Unsupported caps on {}: {}", name, caps]);
+                return false;
+            }
+        };
+
+        let capability = RTCRtpCodecCapability {
+            mime_type: codec.webrtc_mime().to_string(),
+            clock_rate: structure.get::<i32>("clock-rate").map_or(0, |clock_rate| clock_rate as u32),
+            sdp_fmtp_line: if codec == MediaType::H264 { h264_fmtp_line(structure) } else { String::new() },
+            ..RTCRtpCodecCapability::default()
+        };
+
+        let track = match (is_rtp, codec.payloader()) {
+            (true, _) => SenderTrack::Rtp(Arc::new(TrackLocalStaticRTP::new(capability, name_parts[0].to_string(), stream_id))),
+            (false, Some(payloader)) => SenderTrack::Packetized(
+                Arc::new(TrackLocalStaticRTP::new(capability, name_parts[0].to_string(), stream_id)),
+                payloader,
+            ),
+            (false, None) => SenderTrack::Sample(Arc::new(TrackLocalStaticSample::new(capability, name_parts[0].to_string(), stream_id))),
+        };
 
         let webrtc_state = self.webrtc_state.clone();
-        let track_arc = track.clone();
+        let track_arc = track.track_local();
         let handle = self.runtime_handle();
         let inner = handle.clone();
-        let rtp_sender = block_on(async move {
+        // The lock is held until the track is stored so add_peer can't miss it or add it twice
+        let (webrtc_guard, senders) = block_on(async move {
             handle.spawn_blocking(move || {
                 inner.block_on(async move {
-                    webrtc_state.lock().await.peer_connection.as_ref().unwrap().add_track(Arc::clone(&track_arc) as Arc<dyn TrackLocal + Send + Sync>).await
+                    let webrtc_state = webrtc_state.lock_owned().await;
+                    let senders = async {
+                        let rtp_sender = webrtc_state.peer_connection.as_ref().unwrap().add_track(Arc::clone(&track_arc)).await?;
+
+                        // Broadcast peers share the track, so it is only packetized once
+                        let mut peer_senders = HashMap::new();
+                        for (id, peer) in &webrtc_state.peers {
+                            peer_senders.insert(id.clone(), peer.add_track(Arc::clone(&track_arc)).await?);
+                        }
+
+                        webrtc::error::Result::Ok((rtp_sender, peer_senders))
+                    }.await;
+
+                    (webrtc_state, senders)
                 })
             }).await
-        }).unwrap().unwrap();
+        }).unwrap();
+        let (rtp_sender, peer_senders) = match senders {
+            Ok(senders) => senders,
+            Err(e) => {
+                drop(webrtc_guard);
+                gst::element_error!(element, gst::ResourceError::Failed, ["Failed to add the track of {}: {:?}", name, e]);
+                return false;
+            }
+        };
 
-        self.runtime_handle().spawn(async move {
-            let mut rtcp_buf = vec![0u8; 1500];
-            while let Ok((_, _)) = rtp_sender.read(&mut rtcp_buf).await {}
-            anyhow::Result::<()>::Ok(())
-        });
+        let sink_pad = self.state.lock().unwrap().streams.get(name).unwrap().sink_pad.clone();
+        let is_video = name_parts[0] == "video";
+        self.spawn_rtcp_loop(element, &sink_pad, is_video, rtp_sender.clone(), None);
+        for (id, peer_sender) in &peer_senders {
+            self.spawn_rtcp_loop(element, &sink_pad, is_video, peer_sender.clone(), Some(id.clone()));
+        }
+
+        {
+            let mut state = self.state.lock().unwrap();
+            let stream = state.streams.get_mut(name).unwrap();
+            stream.track = Some(track.track_local());
+            stream.rtp_sender = Some(rtp_sender);
+            stream.peer_senders = peer_senders;
+        }
+        drop(webrtc_guard);
 
         let media_type = match name_parts[0] {
             "video" => crate::webrtcredux::sender::MediaType::Video,
@@ -351,13 +1226,281 @@ impl WebRtcRedux {
         let handle = self.runtime_handle();
         self.state.lock().unwrap().streams.get(name).unwrap().sender.as_ref().unwrap().add_info(track, handle, media_type, duration);
 
-        self.state.lock().unwrap().tracks += 1;
+        self.check_all_tracks_added();
+        true
+    }
+
+    /// Reads the RTCP of a sender of the main connection (`peer` is `None`) or of a broadcast peer
+    fn spawn_rtcp_loop(&self, element: &super::WebRtcRedux, sink_pad: &gst::GhostPad, is_video: bool, rtp_sender: Arc<RTCRtpSender>, peer: Option<String>) {
+        let sink_pad = sink_pad.clone();
+        let element_weak = element.downgrade();
+        self.runtime_handle().spawn(async move {
+            let ssrc = rtp_sender.get_parameters().await.encodings.first().map_or(0, |encoding| encoding.ssrc);
+            while let Ok((packets, _)) = rtp_sender.read_rtcp().await {
+                match element_weak.upgrade() {
+                    Some(element) => {
+                        let this = WebRtcRedux::from_instance(&element);
+                        if is_video && packets.iter().any(|packet| is_keyframe_request(packet.as_ref())) {
+                            this.request_keyframe(&sink_pad);
+                        }
+                        stats::on_rtcp(&mut this.reception_stats.lock().unwrap(), &packets);
+                        // The estimate is for video, audio is comparatively tiny
+                        if is_video {
+                            this.handle_bandwidth_feedback(&element, peer.as_deref(), ssrc, &packets);
+                        }
+                    }
+                    None => break,
+                }
+            }
+            anyhow::Result::<()>::Ok(())
+        });
+    }
+
+    /// Forwards a remote keyframe request upstream. Every peer of a broadcast asks for one when it joins or loses
+    /// packets, so requests for a pad within `MIN_KEYFRAME_REQUEST_INTERVAL` of the last forwarded one are dropped.
+    fn request_keyframe(&self, sink_pad: &gst::GhostPad) {
+        let now = Instant::now();
         {
             let mut state = self.state.lock().unwrap();
-            if state.tracks == state.next_audio_pad_id + state.next_video_pad_id {
-                state.on_all_tracks_added_send.take().unwrap().send(()).unwrap();
+            let last_request = state.last_keyframe_requests.insert(sink_pad.name().to_string(), now);
+            if let Some(last_request) = last_request {
+                if now.duration_since(last_request) < MIN_KEYFRAME_REQUEST_INTERVAL {
+                    // Keep the time of the request that was forwarded
+                    state.last_keyframe_requests.insert(sink_pad.name().to_string(), last_request);
+                    debug!(CAT, obj: sink_pad, "Ignoring keyframe request, one was forwarded {:?} ago", now.duration_since(last_request));
+                    return;
+                }
+            }
+        }
+
+        debug!(CAT, obj: sink_pad, "Remote requested a keyframe, forwarding upstream");
+        let event = gst_video::UpstreamForceKeyUnitEvent::builder()
+            .all_headers(true)
+            .build();
+        sink_pad.push_event(event);
+    }
+
+    fn check_all_tracks_added(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.streams.values().all(|stream| stream.rtp_sender.is_some()) {
+            // Only the first time matters, later pads are added after the application stopped waiting
+            if let Some(sender) = state.on_all_tracks_added_send.take() {
+                let _ = sender.send(());
+            }
+        }
+    }
+
+    /// Removes a stream requested at runtime, webrtc-rs fires negotiation-needed once the track is gone
+    fn remove_stream(&self, element: &super::WebRtcRedux, name: &str) {
+        let stream = self.state.lock().unwrap().streams.remove(name);
+        let mut stream = match stream {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        stream.unprepare(element);
+
+        let rtp_sender = stream.rtp_sender.take();
+        let peer_senders = std::mem::take(&mut stream.peer_senders);
+        let webrtc_state = self.webrtc_state.clone();
+        let res = self.run_blocking(async move {
+            let webrtc_state = webrtc_state.lock().await;
+            if let (Some(peer_connection), Some(rtp_sender)) = (webrtc_state.peer_connection.as_ref(), rtp_sender) {
+                peer_connection.remove_track(&rtp_sender).await?;
+            }
+
+            for (id, peer_sender) in peer_senders {
+                if let Some(peer) = webrtc_state.peers.get(&id) {
+                    peer.remove_track(&peer_sender).await?;
+                }
+            }
+
+            webrtc::error::Result::Ok(())
+        });
+
+        if let Err(e) = res {
+            error!(CAT, obj: element, "Failed to remove track for {}: {:?}", name, e);
+        }
+
+        let _ = stream.sink_pad.set_active(false);
+        let _ = element.remove_pad(&stream.sink_pad);
+
+        // The pad may have been the last one the application was waiting for
+        self.check_all_tracks_added();
+    }
+
+    fn remove_data_stream(&self, element: &super::WebRtcRedux, name: &str) {
+        let stream = self.state.lock().unwrap().data_streams.remove(name);
+        if let Some(mut stream) = stream {
+            stream.unprepare(element, &self.runtime_handle());
+            let _ = stream.sink_pad.set_active(false);
+            let _ = element.remove_pad(&stream.sink_pad);
+        }
+    }
+
+    async fn add_remote_track(&self, element: &super::WebRtcRedux, track: Arc<TrackRemote>) -> Result<(), Error> {
+        let codec = track.codec().await;
+
+        let name = {
+            let mut state = self.state.lock().unwrap();
+            match track.kind() {
+                RTPCodecType::Video => {
+                    let name = format!("recv_video_{}", state.next_recv_video_pad_id);
+                    state.next_recv_video_pad_id += 1;
+                    name
+                }
+                RTPCodecType::Audio => {
+                    let name = format!("recv_audio_{}", state.next_recv_audio_pad_id);
+                    state.next_recv_audio_pad_id += 1;
+                    name
+                }
+                kind => anyhow::bail!("Remote track has unsupported kind {:?}", kind),
+            }
+        };
+
+        let media = if track.kind() == RTPCodecType::Video { "video" } else { "audio" };
+        let encoding_name = codec
+            .capability
+            .mime_type
+            .split('/')
+            .nth(1)
+            .unwrap_or_default()
+            .to_uppercase();
+
+        let caps = gst::Caps::builder("application/x-rtp")
+            .field("media", media)
+            .field("encoding-name", encoding_name.as_str())
+            .field("clock-rate", codec.capability.clock_rate as i32)
+            .field("payload", track.payload_type() as i32)
+            .build();
+
+        debug!(CAT, obj: element, "Remote track {} negotiated with caps {}", name, caps);
+
+        let appsrc = self.add_output(element, &format!("recv_{}_%u", media), &name, &caps)?;
+
+        // The on_track handler is held locked by webrtc-rs while it runs, so read on a separate task
+        self.runtime_handle().spawn(async move {
+            let mut rtp_buf = vec![0u8; 1500];
+            while let Ok((n, _)) = track.read(&mut rtp_buf).await {
+                if appsrc
+                    .push_buffer(gst::Buffer::from_mut_slice(rtp_buf[..n].to_vec()))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            let _ = appsrc.end_of_stream();
+            debug!(CAT, "Remote track {} ended", name);
+        });
+
+        Ok(())
+    }
+
+    async fn add_remote_data_channel(&self, element: &super::WebRtcRedux, channel: Arc<RTCDataChannel>) -> Result<(), Error> {
+        let name = {
+            let mut state = self.state.lock().unwrap();
+            let name = format!("recv_data_{}", state.next_recv_data_pad_id);
+            state.next_recv_data_pad_id += 1;
+            name
+        };
+
+        debug!(CAT, obj: element, "Remote data channel '{}' exposed as {}", channel.label(), name);
+
+        let caps = gst::Caps::builder("application/data").build();
+        let appsrc = self.add_output(element, "recv_data_%u", &name, &caps)?;
+
+        let message_appsrc = appsrc.clone();
+        channel.on_message(Box::new(move |message: DataChannelMessage| {
+            let _ = message_appsrc.push_buffer(gst::Buffer::from_mut_slice(message.data.to_vec()));
+            Box::pin(async {})
+        }));
+
+        channel.on_close(Box::new(move || {
+            let _ = appsrc.end_of_stream();
+            Box::pin(async {})
+        }));
+
+        Ok(())
+    }
+
+    fn add_output(&self, element: &super::WebRtcRedux, templ_name: &str, name: &str, caps: &gst::Caps) -> Result<gst_app::AppSrc, Error> {
+        let appsrc = make_element("appsrc", None)?
+            .dynamic_cast::<gst_app::AppSrc>()
+            .unwrap();
+        appsrc.set_caps(Some(caps));
+        appsrc.set_format(gst::Format::Time);
+        appsrc.set_is_live(true);
+        appsrc.set_do_timestamp(true);
+
+        element.add(&appsrc).with_context(|| format!("Adding appsrc for {}", name))?;
+        appsrc
+            .sync_state_with_parent()
+            .with_context(|| format!("Syncing appsrc state for {}", name))?;
+
+        let templ = element.pad_template(templ_name).unwrap();
+        let src_pad = gst::GhostPad::builder_with_template(&templ, Some(name)).build();
+        src_pad
+            .set_target(Some(&appsrc.static_pad("src").unwrap()))
+            .unwrap();
+        src_pad.set_active(true)?;
+        element.add_pad(&src_pad)?;
+
+        self.state.lock().unwrap().outputs.insert(
+            name.to_string(),
+            OutputStream {
+                src_pad,
+                appsrc: appsrc.clone(),
+            },
+        );
+
+        Ok(appsrc)
+    }
+
+    fn remove_outputs(&self, element: &super::WebRtcRedux) {
+        let outputs = std::mem::take(&mut self.state.lock().unwrap().outputs);
+        outputs.values().for_each(|output| output.teardown(element));
+    }
+
+    fn handle_bandwidth_feedback(&self, element: &super::WebRtcRedux, peer: Option<&str>, ssrc: u32, packets: &[Box<dyn Packet + Send + Sync>]) {
+        let target_bitrate = match self.bandwidth.lock().unwrap().on_rtcp(peer, ssrc, packets) {
+            Some(bitrate) => bitrate,
+            None => return,
+        };
+
+        self.apply_target_bitrate(element, target_bitrate);
+    }
+
+    /// The encoders are shared by every peer connection, so they get the lowest estimate
+    fn apply_target_bitrate(&self, element: &super::WebRtcRedux, target_bitrate: u32) {
+        debug!(CAT, obj: element, "Target bitrate changed to {} bps", target_bitrate);
+
+        let (property, divisor) = {
+            let webrtc_settings = self.webrtc_settings.lock().unwrap();
+            (webrtc_settings.encoder_bitrate_property.clone(), webrtc_settings.encoder_bitrate_divisor.max(1))
+        };
+
+        if let Some(property) = property {
+            let encoders = self
+                .state
+                .lock()
+                .unwrap()
+                .streams
+                .iter()
+                .filter(|(name, _)| name.starts_with("video"))
+                .filter_map(|(_, stream)| upstream_encoder(&stream.sink_pad, &property))
+                .collect::<Vec<_>>();
+
+            // Audio is comparatively tiny, so the video encoders split the budget evenly
+            if !encoders.is_empty() {
+                let bitrate = (target_bitrate / encoders.len() as u32 / divisor) as u64;
+                for encoder in encoders {
+                    set_encoder_bitrate(&encoder, &property, bitrate);
+                }
             }
         }
+
+        element.notify("target-bitrate");
+        element.emit_by_name::<()>("on-target-bitrate", &[&target_bitrate]);
     }
 
     pub fn set_stream_id(&self, pad_name: &str, stream_id: &str) -> Result<(), ErrorMessage> {
@@ -443,12 +1586,28 @@ impl WebRtcRedux {
         let webrtc_state = self.webrtc_state.lock().await;
         let peer_connection = WebRtcRedux::get_peer_connection(&webrtc_state)?;
 
+        let ice_restart = std::mem::take(&mut self.state.lock().unwrap().ice_restart);
+        let options = match (ice_restart, options) {
+            (true, options) => Some(RTCOfferOptions {
+                ice_restart: true,
+                ..options.unwrap_or_default()
+            }),
+            (false, options) => options,
+        };
+
         match peer_connection.create_offer(options).await {
-            Ok(res) => Ok(SDP::from_str(&res.sdp).unwrap()),
-            Err(e) => Err(gst::error_msg!(
-                gst::ResourceError::Failed,
-                [&format!("Failed to create offer: {:?}", e)]
-            )),
+            Ok(res) => parse_sdp(&res.sdp),
+            Err(e) => {
+                // Still pending for the next attempt
+                if ice_restart {
+                    self.state.lock().unwrap().ice_restart = true;
+                }
+
+                Err(gst::error_msg!(
+                    gst::ResourceError::Failed,
+                    [&format!("Failed to create offer: {:?}", e)]
+                ))
+            }
         }
     }
 
@@ -460,7 +1619,7 @@ impl WebRtcRedux {
         let peer_connection = WebRtcRedux::get_peer_connection(&webrtc_state)?;
 
         match peer_connection.create_answer(options).await {
-            Ok(res) => Ok(SDP::from_str(&res.sdp).unwrap()),
+            Ok(res) => parse_sdp(&res.sdp),
             Err(e) => Err(gst::error_msg!(
                 gst::ResourceError::Failed,
                 [&format!("Failed to create answer: {:?}", e)]
@@ -474,7 +1633,7 @@ impl WebRtcRedux {
 
         match peer_connection.local_description().await {
             None => Ok(None),
-            Some(res) => Ok(Some(SDP::from_str(&res.sdp).unwrap()))
+            Some(res) => parse_sdp(&res.sdp).map(Some),
         }
     }
 
@@ -514,26 +1673,39 @@ impl WebRtcRedux {
         Ok(())
     }
 
-    pub async fn on_negotiation_needed(&self, f: OnNegotiationNeededHdlrFn) -> Result<(), ErrorMessage>
-    {
+    pub async fn create_data_channel(
+        &self,
+        label: &str,
+        options: Option<RTCDataChannelInit>,
+    ) -> Result<Arc<RTCDataChannel>, ErrorMessage> {
         let webrtc_state = self.webrtc_state.lock().await;
         let peer_connection = WebRtcRedux::get_peer_connection(&webrtc_state)?;
 
-        peer_connection
-            .on_negotiation_needed(Box::new(f))
-            .await;
+        match peer_connection.create_data_channel(label, options).await {
+            Ok(channel) => Ok(channel),
+            Err(e) => Err(gst::error_msg!(
+                gst::ResourceError::Failed,
+                [&format!("Failed to create data channel: {:?}", e)]
+            )),
+        }
+    }
+
+    pub async fn on_data_channel(&self, f: OnDataChannelHdlrFn) {
+        let _ = self.on_data_channel.lock().await.insert(f);
+    }
+
+    pub async fn on_negotiation_needed(&self, f: OnNegotiationNeededHdlrFn) -> Result<(), ErrorMessage>
+    {
+        // The peer connection handler is owned by the element so the on-negotiation-needed signal keeps firing
+        let _ = self.on_negotiation_needed.lock().await.insert(f);
 
         Ok(())
     }
 
     pub async fn on_ice_candidate(&self, f: OnLocalCandidateHdlrFn) -> Result<(), ErrorMessage>
     {
-        let webrtc_state = self.webrtc_state.lock().await;
-        let peer_connection = WebRtcRedux::get_peer_connection(&webrtc_state)?;
-
-        peer_connection
-            .on_ice_candidate(Box::new(f))
-            .await;
+        // The peer connection handler is owned by the element so the on-ice-candidate signal keeps firing
+        let _ = self.on_ice_candidate.lock().await.insert(f);
 
         Ok(())
     }
@@ -543,36 +1715,151 @@ impl WebRtcRedux {
         let webrtc_state = self.webrtc_state.lock().await;
         let peer_connection = WebRtcRedux::get_peer_connection(&webrtc_state)?;
 
-        peer_connection
-            .on_ice_gathering_state_change(Box::new(f))
-            .await;
+        peer_connection.on_ice_gathering_state_change(Box::new(f));
 
         Ok(())
     }
 
     pub async fn on_ice_connection_state_change(&self, f: OnICEConnectionStateChangeHdlrFn) -> Result<(), ErrorMessage>
     {
+        // The peer connection handler is owned by the element so ICE restarts can be scheduled
+        let _ = self.on_ice_connection_state_change.lock().await.insert(f);
+
+        Ok(())
+    }
+
+    pub async fn on_peer_connection_state_change(&self, f: OnPeerConnectionStateChangeHdlrFn) -> Result<(), ErrorMessage> {
         let webrtc_state = self.webrtc_state.lock().await;
         let peer_connection = WebRtcRedux::get_peer_connection(&webrtc_state)?;
 
-        peer_connection
-            .on_ice_connection_state_change(Box::new(f))
-            .await;
+        peer_connection.on_peer_connection_state_change(Box::new(f));
 
         Ok(())
     }
 
-    pub async fn on_peer_connection_state_change(&self, f: OnPeerConnectionStateChangeHdlrFn) -> Result<(), ErrorMessage> {
+    pub async fn get_stats(&self) -> Result<Stats, ErrorMessage> {
+        let (senders, mut peer_senders) = {
+            let state = self.state.lock().unwrap();
+            let mut senders = Vec::new();
+            let mut peer_senders = HashMap::<String, Vec<(String, Arc<RTCRtpSender>)>>::new();
+            for (name, stream) in &state.streams {
+                if let Some(rtp_sender) = &stream.rtp_sender {
+                    senders.push((name.clone(), rtp_sender.clone()));
+                }
+                for (id, rtp_sender) in &stream.peer_senders {
+                    peer_senders.entry(id.clone()).or_default().push((name.clone(), rtp_sender.clone()));
+                }
+            }
+            (senders, peer_senders)
+        };
+        let reception = self.reception_stats.lock().unwrap().clone();
+        let (target_bitrate, peer_target_bitrates) = {
+            let bandwidth = self.bandwidth.lock().unwrap();
+            let peer_target_bitrates = peer_senders
+                .keys()
+                .map(|id| (id.clone(), bandwidth.connection_target_bitrate(Some(id))))
+                .collect::<HashMap<_, _>>();
+            (bandwidth.connection_target_bitrate(None), peer_target_bitrates)
+        };
+
         let webrtc_state = self.webrtc_state.lock().await;
         let peer_connection = WebRtcRedux::get_peer_connection(&webrtc_state)?;
 
-        peer_connection
-            .on_peer_connection_state_change(Box::new(f))
-            .await;
+        let mut stats = Stats {
+            connection: stats::collect(peer_connection, &senders, &reception, target_bitrate).await,
+            ..Default::default()
+        };
+        for (id, peer) in &webrtc_state.peers {
+            let senders = peer_senders.remove(id).unwrap_or_default();
+            let target_bitrate = peer_target_bitrates.get(id).copied().unwrap_or(target_bitrate);
+            stats.peers.insert(id.clone(), stats::collect(peer, &senders, &reception, target_bitrate).await);
+        }
+
+        Ok(stats)
+    }
+
+    /// Posts a stats snapshot as element message every `stats-interval-ms` while the element is running
+    fn start_stats_task(&self, element: &super::WebRtcRedux) {
+        self.stop_stats_task();
+
+        let interval = self.webrtc_settings.lock().unwrap().stats_interval_ms;
+        if interval == 0 {
+            return;
+        }
+
+        let element_weak = element.downgrade();
+        let task = self.runtime_handle().spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_millis(interval as u64));
+            // The first tick completes immediately, there is nothing to report yet
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+
+                let element = match element_weak.upgrade() {
+                    Some(element) => element,
+                    None => break,
+                };
+
+                match WebRtcRedux::from_instance(&element).get_stats().await {
+                    Ok(stats) => {
+                        let message = gst::message::Element::builder(stats.to_structure()).src(&element).build();
+                        let _ = element.post_message(message);
+                    }
+                    Err(e) => debug!(CAT, obj: &element, "Failed to collect stats: {:?}", e),
+                }
+            }
+        });
+
+        let _ = self.state.lock().unwrap().stats_task.insert(task);
+    }
+
+    fn stop_stats_task(&self) {
+        if let Some(task) = self.state.lock().unwrap().stats_task.take() {
+            task.abort();
+        }
+    }
+
+    /// Makes the next offer restart ICE with fresh credentials and asks the application to renegotiate
+    pub async fn restart_ice(&self, element: &super::WebRtcRedux) -> Result<(), ErrorMessage> {
+        self.start_ice_restart(element, false).await
+    }
+
+    async fn start_ice_restart(&self, element: &super::WebRtcRedux, automatic: bool) -> Result<(), ErrorMessage> {
+        {
+            let webrtc_state = self.webrtc_state.lock().await;
+            WebRtcRedux::get_peer_connection(&webrtc_state)?;
+        }
+
+        info!(CAT, obj: element, "Restarting ICE");
+        self.state.lock().unwrap().ice_restart = true;
+        element.emit_by_name::<()>("on-ice-restart", &[&automatic, &None::<String>]);
+        self.negotiation_needed(element).await;
 
         Ok(())
     }
 
+    async fn negotiation_needed(&self, element: &super::WebRtcRedux) {
+        element.emit_by_name::<()>("on-negotiation-needed", &[]);
+
+        if let Some(f) = &mut *self.on_negotiation_needed.lock().await {
+            f().await;
+        }
+    }
+
+    /// Schedules an ICE restart when the connection stays disconnected or failed for `ice-restart-timeout-ms`
+    fn watch_ice_connection_state(&self, element: &super::WebRtcRedux, connection_state: RTCIceConnectionState) {
+        let timeout = self.webrtc_settings.lock().unwrap().ice_restart_timeout_ms;
+        let element_weak = element.downgrade();
+        self.ice_restart_watcher.on_ice_connection_state_change(&self.runtime_handle(), timeout, connection_state, move || async move {
+            if let Some(element) = element_weak.upgrade() {
+                if let Err(e) = WebRtcRedux::from_instance(&element).start_ice_restart(&element, true).await {
+                    warning!(CAT, obj: &element, "Failed to restart ICE: {:?}", e);
+                }
+            }
+        });
+    }
+
     pub async fn add_ice_candidate(
         &self,
         candidate: RTCIceCandidateInit,
@@ -602,10 +1889,289 @@ impl WebRtcRedux {
         all.await.unwrap();
     }
 
-    fn runtime_handle(&self) -> Handle {
+    pub async fn add_peer(&self, element: &super::WebRtcRedux, id: &str) -> Result<PeerHandle, ErrorMessage> {
+        let mut webrtc_state = self.webrtc_state.lock().await;
+        let (api, config) = match (&webrtc_state.api, &webrtc_state.config) {
+            (Some(api), Some(config)) => (api, config.clone()),
+            _ => return Err(gst::error_msg!(
+                gst::ResourceError::Failed,
+                ["Peers can only be added once the element is ready"]
+            )),
+        };
+
+        if webrtc_state.peers.contains_key(id) {
+            return Err(gst::error_msg!(
+                gst::ResourceError::Failed,
+                [&format!("Peer {} already exists", id)]
+            ));
+        }
+
+        let peer_connection = match api.new_peer_connection(config).await {
+            Ok(peer_connection) => Arc::new(peer_connection),
+            Err(e) => return Err(gst::error_msg!(
+                gst::ResourceError::Failed,
+                [&format!("Failed to create peer connection for peer {}: {:?}", id, e)]
+            )),
+        };
+
+        // Tracks of pads that are already linked are shared with the new peer right away
+        let tracks = self.state.lock().unwrap().streams.iter()
+            .filter_map(|(name, stream)| stream.track.clone().map(|track| (name.clone(), track, stream.sink_pad.clone())))
+            .collect::<Vec<_>>();
+
+        for (name, track, sink_pad) in tracks {
+            let rtp_sender = match peer_connection.add_track(track).await {
+                Ok(rtp_sender) => rtp_sender,
+                Err(e) => {
+                    // Undo the tracks added so far, closing the connection stops their senders and with them
+                    // the RTCP loops
+                    for stream in self.state.lock().unwrap().streams.values_mut() {
+                        stream.peer_senders.remove(id);
+                    }
+                    let _ = peer_connection.close().await;
+                    self.bandwidth.lock().unwrap().remove_peer(id);
+                    return Err(gst::error_msg!(
+                        gst::ResourceError::Failed,
+                        [&format!("Failed to add track {} to peer {}: {:?}", name, id, e)]
+                    ));
+                }
+            };
+
+            self.spawn_rtcp_loop(element, &sink_pad, name.starts_with("video"), rtp_sender.clone(), Some(id.to_string()));
+            if let Some(stream) = self.state.lock().unwrap().streams.get_mut(&name) {
+                stream.peer_senders.insert(id.to_string(), rtp_sender);
+            }
+        }
+
+        webrtc_state.peers.insert(id.to_string(), Arc::clone(&peer_connection));
+
+        Ok(PeerHandle::new(element, id, peer_connection))
+    }
+
+    pub async fn whip_connect(&self, element: &super::WebRtcRedux, endpoint: &str, token: Option<&str>) -> Result<(), ErrorMessage> {
+        if self.whip_client.lock().unwrap().is_some() {
+            return Err(gst::error_msg!(
+                gst::ResourceError::Busy,
+                ["Already connected to a WHIP endpoint"]
+            ));
+        }
+
+        let client = WhipClient::new(endpoint, token, self.runtime_handle());
+        client.connect(element).await?;
+        let _ = self.whip_client.lock().unwrap().insert(client);
+
+        Ok(())
+    }
+
+    pub async fn whip_disconnect(&self, element: &super::WebRtcRedux) -> Result<(), ErrorMessage> {
+        let client = self.whip_client.lock().unwrap().take();
+        match client {
+            Some(client) => client.disconnect(element).await,
+            None => Err(gst::error_msg!(
+                gst::ResourceError::NotFound,
+                ["Not connected to a WHIP endpoint"]
+            )),
+        }
+    }
+
+    #[cfg(feature = "whep")]
+    pub fn whep_serve(&self, element: &super::WebRtcRedux, addr: std::net::SocketAddr) -> Result<std::net::SocketAddr, ErrorMessage> {
+        let server = WhepServer::start(element, addr, &self.runtime_handle())?;
+        let local_addr = server.local_addr();
+        // Replacing a running server shuts it down
+        let _ = self.whep_server.lock().unwrap().insert(server);
+
+        Ok(local_addr)
+    }
+
+    pub async fn remove_peer(&self, element: &super::WebRtcRedux, id: &str) -> Result<(), ErrorMessage> {
+        let peer_connection = match self.webrtc_state.lock().await.peers.remove(id) {
+            Some(peer_connection) => peer_connection,
+            None => return Err(gst::error_msg!(
+                gst::ResourceError::NotFound,
+                [&format!("Peer {} not found", id)]
+            )),
+        };
+
+        for stream in self.state.lock().unwrap().streams.values_mut() {
+            stream.peer_senders.remove(id);
+        }
+
+        // The peer may have been the slowest one, the encoders can speed up again
+        let target_bitrate = self.bandwidth.lock().unwrap().remove_peer(id);
+        if let Some(target_bitrate) = target_bitrate {
+            self.apply_target_bitrate(element, target_bitrate);
+        }
+
+        if let Err(e) = peer_connection.close().await {
+            return Err(gst::error_msg!(
+                gst::ResourceError::Failed,
+                [&format!("Failed to close peer {}: {:?}", id, e)]
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn runtime_handle(&self) -> Handle {
         self.state.lock().unwrap().handle.as_ref().unwrap_or(RUNTIME.handle()).clone()
     }
 
+    fn connect_handlers(conn: &RTCPeerConnection, element_weak: glib::WeakRef<super::WebRtcRedux>) {
+        let track_element_weak = element_weak.clone();
+        conn.on_track(Box::new(move |track, _receiver| {
+            let element = track_element_weak.upgrade();
+            Box::pin(async move {
+                if let (Some(element), Some(track)) = (element, track) {
+                    let this = WebRtcRedux::from_instance(&element);
+                    if let Err(e) = this.add_remote_track(&element, track).await {
+                        error!(CAT, obj: &element, "Failed to expose remote track: {:?}", e);
+                    }
+                }
+            })
+        }));
+
+        let data_element_weak = element_weak.clone();
+        conn.on_data_channel(Box::new(move |channel| {
+            let element = data_element_weak.upgrade();
+            Box::pin(async move {
+                if let Some(element) = element {
+                    let this = WebRtcRedux::from_instance(&element);
+                    if let Err(e) = this.add_remote_data_channel(&element, channel.clone()).await {
+                        error!(CAT, obj: &element, "Failed to expose remote data channel: {:?}", e);
+                    }
+
+                    if let Some(f) = &mut *this.on_data_channel.lock().await {
+                        f(channel).await;
+                    }
+                }
+            })
+        }));
+
+        let candidate_element_weak = element_weak.clone();
+        conn.on_ice_candidate(Box::new(move |candidate| {
+            let element = candidate_element_weak.upgrade();
+            Box::pin(async move {
+                if let Some(element) = element {
+                    if let Some(candidate) = &candidate {
+                        match candidate.to_json() {
+                            Ok(init) => element.emit_by_name::<()>(
+                                "on-ice-candidate",
+                                &[&(init.sdp_mline_index.unwrap_or(0) as u32), &init.candidate],
+                            ),
+                            Err(e) => error!(CAT, obj: &element, "Failed to serialize ICE candidate: {:?}", e),
+                        }
+                    }
+
+                    let this = WebRtcRedux::from_instance(&element);
+                    if let Some(f) = &mut *this.on_ice_candidate.lock().await {
+                        f(candidate).await;
+                    }
+                }
+            })
+        }));
+
+        let ice_element_weak = element_weak.clone();
+        conn.on_ice_connection_state_change(Box::new(move |connection_state| {
+            let element = ice_element_weak.upgrade();
+            Box::pin(async move {
+                if let Some(element) = element {
+                    let this = WebRtcRedux::from_instance(&element);
+                    this.watch_ice_connection_state(&element, connection_state);
+
+                    if let Some(f) = &mut *this.on_ice_connection_state_change.lock().await {
+                        f(connection_state).await;
+                    }
+                }
+            })
+        }));
+
+        let negotiation_element_weak = element_weak;
+        conn.on_negotiation_needed(Box::new(move || {
+            let element = negotiation_element_weak.upgrade();
+            Box::pin(async move {
+                if let Some(element) = element {
+                    WebRtcRedux::from_instance(&element).negotiation_needed(&element).await;
+                }
+            })
+        }));
+    }
+
+    /// Runs a future on the element's tokio runtime and blocks the calling thread until it finishes
+    fn run_blocking<F>(&self, future: F) -> F::Output
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let handle = self.runtime_handle();
+        let inner = handle.clone();
+
+        block_on(async move {
+            handle.spawn_blocking(move || inner.block_on(future)).await
+        }).unwrap()
+    }
+
+    fn spawn_promise<F>(&self, promise: Option<gst::Promise>, future: F)
+    where
+        F: Future<Output = Result<Option<gst::Structure>, ErrorMessage>> + Send + 'static,
+    {
+        self.runtime_handle().spawn(async move {
+            let reply = match future.await {
+                Ok(reply) => reply,
+                Err(e) => Some(
+                    gst::Structure::builder("application/x-gst-promise")
+                        .field("error", format!("{:?}", e))
+                        .build(),
+                ),
+            };
+
+            if let Some(promise) = promise {
+                promise.reply(reply);
+            }
+        });
+    }
+
+    fn set_description_from_signal(
+        element: &super::WebRtcRedux,
+        args: &[glib::Value],
+        remote: bool,
+    ) -> Option<glib::Value> {
+        let type_name = args[1].get::<Option<String>>().expect("signal arg");
+        let sdp = args[2].get::<Option<String>>().expect("signal arg");
+        let promise = args[3].get::<Option<gst::Promise>>().expect("signal arg");
+
+        let this = WebRtcRedux::from_instance(element);
+        let element = element.clone();
+        this.spawn_promise(promise, async move {
+            let (type_name, sdp) = match (type_name, sdp) {
+                (Some(type_name), Some(sdp)) => (type_name, sdp),
+                _ => return Err(gst::error_msg!(
+                    gst::ResourceError::Settings,
+                    ["The description type and SDP must not be NULL"]
+                )),
+            };
+            let sdp_type = match RTCSdpType::from(type_name.as_str()) {
+                RTCSdpType::Unspecified => return Err(gst::error_msg!(
+                    gst::ResourceError::Settings,
+                    [&format!("Unknown description type {}", type_name)]
+                )),
+                sdp_type => sdp_type,
+            };
+            let sdp = parse_sdp(&sdp)?;
+
+            let this = WebRtcRedux::from_instance(&element);
+            if remote {
+                this.set_remote_description(&sdp, sdp_type).await?;
+            } else {
+                this.set_local_description(&sdp, sdp_type).await?;
+            }
+
+            Ok(None)
+        });
+
+        None
+    }
+
     fn get_peer_connection(state: &WebRtcState) -> Result<&RTCPeerConnection, ErrorMessage> {
         match &state.peer_connection {
             Some(conn) => Ok(conn),
@@ -643,9 +2209,17 @@ impl ElementImpl for WebRtcRedux {
     fn pad_templates() -> &'static [gst::PadTemplate] {
         static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
             let caps = gst::Caps::builder_full()
-                .structure(gst::Structure::builder("video/x-h264").field("stream-format", "byte-stream").field("profile", "baseline").build())
+                .structure(gst::Structure::builder("video/x-h264").field("stream-format", "byte-stream").field("profile", gst::List::new(&[&"constrained-baseline", &"baseline", &"main", &"high"])).build())
                 .structure(gst::Structure::builder("video/x-vp8").build())
                 .structure(gst::Structure::builder("video/x-vp9").build())
+                .structure(gst::Structure::builder("video/x-h265").field("stream-format", "byte-stream").field("alignment", "au").build())
+                .structure(gst::Structure::builder("video/x-av1").field("stream-format", "obu-stream").field("alignment", "tu").build())
+                .structure(
+                    gst::Structure::builder("application/x-rtp")
+                        .field("media", "video")
+                        .field("encoding-name", gst::List::new(&[&"H264", &"VP8", &"VP9", &"H265", &"AV1"]))
+                        .build(),
+                )
                 .build();
             let video_pad_template = gst::PadTemplate::new(
                 "video_%u",
@@ -660,6 +2234,12 @@ impl ElementImpl for WebRtcRedux {
                 .structure(gst::Structure::builder("audio/G722").build())
                 .structure(gst::Structure::builder("audio/x-mulaw").build())
                 .structure(gst::Structure::builder("audio/x-alaw").build())
+                .structure(
+                    gst::Structure::builder("application/x-rtp")
+                        .field("media", "audio")
+                        .field("encoding-name", gst::List::new(&[&"OPUS", &"G722", &"PCMU", &"PCMA"]))
+                        .build(),
+                )
                 .build();
             let audio_pad_template = gst::PadTemplate::new(
                 "audio_%u",
@@ -669,7 +2249,49 @@ impl ElementImpl for WebRtcRedux {
             )
                 .unwrap();
 
-            vec![video_pad_template, audio_pad_template]
+            let caps = gst::Caps::builder("application/x-rtp").field("media", "video").build();
+            let recv_video_pad_template = gst::PadTemplate::new(
+                "recv_video_%u",
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &caps,
+            )
+                .unwrap();
+
+            let caps = gst::Caps::builder("application/x-rtp").field("media", "audio").build();
+            let recv_audio_pad_template = gst::PadTemplate::new(
+                "recv_audio_%u",
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &caps,
+            )
+                .unwrap();
+
+            let caps = gst::Caps::builder("application/data").build();
+            let data_pad_template = gst::PadTemplate::new(
+                "data_%u",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Request,
+                &caps,
+            )
+                .unwrap();
+
+            let recv_data_pad_template = gst::PadTemplate::new(
+                "recv_data_%u",
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &caps,
+            )
+                .unwrap();
+
+            vec![
+                video_pad_template,
+                audio_pad_template,
+                data_pad_template,
+                recv_video_pad_template,
+                recv_audio_pad_template,
+                recv_data_pad_template
+            ]
         });
 
         PAD_TEMPLATES.as_ref()
@@ -682,14 +2304,41 @@ impl ElementImpl for WebRtcRedux {
         _name: Option<String>,
         _caps: Option<&gst::Caps>,
     ) -> Option<gst::Pad> {
-        //
-        if element.current_state() > gst::State::Ready {
-            error!(CAT, "element pads can only be requested before starting");
-            return None;
-        }
+        // Streams are normally prepared in ReadyToPaused, pads requested later are prepared right away
+        let running = element.current_state() > gst::State::Ready;
 
         let mut state = self.state.lock().unwrap();
 
+        if templ.name().starts_with("data_") {
+            let name = format!("data_{}", state.next_data_pad_id);
+            state.next_data_pad_id += 1;
+
+            let sink_pad = gst::GhostPad::builder_with_template(templ, Some(name.as_str())).build();
+
+            sink_pad.set_active(true).unwrap();
+            element.add_pad(&sink_pad).unwrap();
+
+            state.data_streams.insert(
+                name.clone(),
+                DataInputStream {
+                    sink_pad: sink_pad.clone(),
+                    appsink: None,
+                    channel: None,
+                },
+            );
+            drop(state);
+
+            if running {
+                if let Err(err) = self.prepare_data_stream(element, &name) {
+                    error!(CAT, obj: element, "Failed to prepare {}: {:?}", name, err);
+                    self.remove_data_stream(element, &name);
+                    return None;
+                }
+            }
+
+            return Some(sink_pad.upcast());
+        }
+
         let name = if templ.name().starts_with("video_") {
             let name = format!("video_{}", state.next_video_pad_id);
             state.next_video_pad_id += 1;
@@ -715,16 +2364,41 @@ impl ElementImpl for WebRtcRedux {
         element.add_pad(&sink_pad).unwrap();
 
         state.streams.insert(
-            name,
+            name.clone(),
             InputStream {
                 sink_pad: sink_pad.clone(),
                 sender: None,
+                track: None,
+                rtp_sender: None,
+                peer_senders: HashMap::new(),
             },
         );
+        drop(state);
+
+        // The track itself is added once caps arrive, which makes webrtc-rs fire negotiation-needed
+        if running {
+            let res = self.state.lock().unwrap().streams.get_mut(&name).unwrap().prepare(element);
+            if let Err(err) = res {
+                error!(CAT, obj: element, "Failed to prepare {}: {:?}", name, err);
+                self.remove_stream(element, &name);
+                return None;
+            }
+        }
 
         Some(sink_pad.upcast())
     }
 
+    fn release_pad(&self, element: &Self::Type, pad: &gst::Pad) {
+        let name = pad.name();
+        debug!(CAT, obj: element, "Releasing pad {}", name);
+
+        if name.starts_with("data_") {
+            self.remove_data_stream(element, &name);
+        } else {
+            self.remove_stream(element, &name);
+        }
+    }
+
     fn change_state(
         &self,
         element: &Self::Type,
@@ -745,8 +2419,42 @@ impl ElementImpl for WebRtcRedux {
 
         match transition {
             gst::StateChange::NullToReady => {
-                match self.webrtc_settings.lock().unwrap().config.take() {
-                    Some(config) => {
+                let (config, api) = {
+                    let mut webrtc_settings = self.webrtc_settings.lock().unwrap();
+                    // Bad properties are caught before anything is taken, so they can be corrected and Ready retried
+                    let mut api_settings = webrtc_settings.api.take();
+                    let udp_mux = api_settings
+                        .as_mut()
+                        .map(|api| webrtc_settings.apply_network_properties(&mut api.setting_engine))
+                        .transpose();
+                    webrtc_settings.api = api_settings;
+
+                    match udp_mux {
+                        Err(err) => (None, Some(Err(err))),
+                        Ok(udp_mux) => {
+                            let ice_servers = webrtc_settings.property_ice_servers();
+                            let config = webrtc_settings.config.take().map(|mut config| -> Result<_, ErrorMessage> {
+                                config.ice_servers.extend(ice_servers);
+                                // Otherwise webrtc-rs generates one per connection and broadcast peers get different fingerprints
+                                if config.certificates.is_empty() {
+                                    config.certificates.push(generate_certificate()?);
+                                }
+                                Ok(config)
+                            });
+                            let api = webrtc_settings.api.take().map(|api| -> Result<_, ErrorMessage> {
+                                Ok((api.build_api()?, udp_mux.flatten()))
+                            });
+                            (config, api)
+                        }
+                    }
+                };
+
+                match (config, api) {
+                    (Some(Err(err)), _) | (_, Some(Err(err))) => {
+                        element.post_error_message(err);
+                        return Err(gst::StateChangeError);
+                    }
+                    (Some(Ok(config)), Some(Ok((api, udp_mux)))) => {
                         //Acquiring lock before the future instead of cloning because we need to return a value which is dropped with it.
                         let webrtc_state = self.webrtc_state.clone();
 
@@ -759,14 +2467,18 @@ impl ElementImpl for WebRtcRedux {
 
                         let handle = self.runtime_handle();
                         let inner = handle.clone();
-                        
-                        block_on(async move {
+                        let element_weak = element.downgrade();
+
+                        let res = block_on(async move {
                             handle.spawn_blocking(move || {
                                 inner.block_on(async move {
                                     let mut webrtc_state = webrtc_state.lock().await;
+                                    webrtc_state.config = Some(config.clone());
+                                    webrtc_state.udp_mux = udp_mux;
                                     //TODO: Fix mutex with an async safe mutex
                                     let peer_connection = webrtc_state
                                         .api
+                                        .insert(api)
                                         .new_peer_connection(config)
                                         .await
                                         .map_err(|e| {
@@ -778,21 +2490,30 @@ impl ElementImpl for WebRtcRedux {
         
                                     match peer_connection {
                                         Ok(conn) => {
+                                            WebRtcRedux::connect_handlers(&conn, element_weak);
+
                                             let _ = webrtc_state.peer_connection.insert(conn);
                                             Ok(())
                                         },
                                         Err(e) => Err(e)
                                     }
-                                }).unwrap();
+                                })
                             }).await
                         }).unwrap();
+
+                        // e.g. a certificate that expired since it was set
+                        if let Err(err) = res {
+                            element.post_error_message(err);
+                            return Err(gst::StateChangeError);
+                        }
                     }
-                    None => {
+                    _ => {
                         return Err(gst::StateChangeError);
                     }
                 }
             }
             gst::StateChange::PausedToReady => {
+                self.stop_stats_task();
                 if let Err(err) = self.unprepare(element) {
                     gst::element_error!(
                         element,
@@ -803,27 +2524,67 @@ impl ElementImpl for WebRtcRedux {
                 }
             }
             gst::StateChange::ReadyToNull => {
+                #[cfg(feature = "whep")]
+                self.whep_server.lock().unwrap().take();
+                self.reception_stats.lock().unwrap().clear();
+                self.bandwidth.lock().unwrap().reset();
+                self.state.lock().unwrap().ice_restart = false;
+                self.ice_restart_watcher.cancel();
+
+                // The WHIP resource is deleted before the connection goes away so the server doesn't wait for a timeout
+                let whip_client = self.whip_client.lock().unwrap().take();
+                if let Some(client) = whip_client {
+                    client.stop_trickle(element);
+                    if let Err(e) = self.run_blocking(async move { client.delete().await }) {
+                        gst::element_warning!(element, gst::ResourceError::Failed, ["Failed to delete WHIP resource: {:?}", e]);
+                    }
+                }
+
                 //Acquiring lock before the future instead of cloning because we need to return a value which is dropped with it.
                 let webrtc_state = self.webrtc_state.clone();
 
                 let handle = self.runtime_handle();
                 let inner = handle.clone();
 
-                block_on(async move {
+                // A peer failing to close must not keep the others, or the main connection, open
+                let errors = block_on(async move {
                     handle.spawn_blocking(move || {
                         inner.block_on(async move {
                             let mut webrtc_state = webrtc_state.lock().await;
+                            webrtc_state.config = None;
+                            webrtc_state.api = None;
+                            let mut errors = vec![];
+                            for (id, peer) in webrtc_state.peers.drain() {
+                                if let Err(e) = peer.close().await {
+                                    errors.push(format!("peer {}: {:?}", id, e));
+                                }
+                            }
+
                             //TODO: Fix mutex with an async safe mutex
                             if let Some(conn) = webrtc_state.peer_connection.take() {
-                                conn.close().await
-                            } else {
-                                Ok(())
+                                if let Err(e) = conn.close().await {
+                                    errors.push(format!("peer connection: {:?}", e));
+                                }
                             }
+
+                            // Released once nothing of the element uses the shared mux anymore
+                            webrtc_state.udp_mux = None;
+                            errors
                         })
                     }).await
-                }).unwrap().unwrap();
+                }).unwrap();
+
+                for error in errors {
+                    gst::element_warning!(element, gst::ResourceError::Close, ["Failed to close {}", error]);
+                }
+                for stream in self.state.lock().unwrap().streams.values_mut() {
+                    stream.peer_senders.clear();
+                }
+
+                self.remove_outputs(element);
             }
             gst::StateChange::ReadyToPaused => {
+                self.start_stats_task(element);
                 ret = Ok(gst::StateChangeSuccess::NoPreroll);
             }
             _ => (),
@@ -833,9 +2594,715 @@ impl ElementImpl for WebRtcRedux {
     }
 }
 
-//TODO: Add signals
-impl ObjectImpl for WebRtcRedux {}
+impl ObjectImpl for WebRtcRedux {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecString::new(
+                    "stun-server",
+                    "STUN Server",
+                    "The STUN server of the form stun://hostname:port",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "turn-server",
+                    "TURN Server",
+                    "The TURN server of the form turn(s)://username:password@host:port",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "bundle-policy",
+                    "Bundle Policy",
+                    "The policy to apply for bundling (balanced, max-compat, max-bundle)",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "ice-transport-policy",
+                    "ICE Transport Policy",
+                    "The policy to apply for ICE transport (all, relay)",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "connection-state",
+                    "Connection State",
+                    "The overall connection state of this element",
+                    None,
+                    glib::ParamFlags::READABLE,
+                ),
+                glib::ParamSpecString::new(
+                    "ice-gathering-state",
+                    "ICE Gathering State",
+                    "The ICE gathering state of this element",
+                    None,
+                    glib::ParamFlags::READABLE,
+                ),
+                glib::ParamSpecString::new(
+                    "dtls-fingerprint",
+                    "DTLS Fingerprint",
+                    "Fingerprint of the local DTLS certificate as in a=fingerprint, known once the element is ready",
+                    None,
+                    glib::ParamFlags::READABLE,
+                ),
+                glib::ParamSpecString::new(
+                    "local-description",
+                    "Local Description",
+                    "The local SDP description in use for this connection",
+                    None,
+                    glib::ParamFlags::READABLE,
+                ),
+                glib::ParamSpecString::new(
+                    "remote-description",
+                    "Remote Description",
+                    "The remote SDP description in use for this connection",
+                    None,
+                    glib::ParamFlags::READABLE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "target-bitrate",
+                    "Target Bitrate",
+                    "Lowest bitrate in bit/s the peer connections are estimated to carry, from REMB, TWCC and receiver report feedback",
+                    0,
+                    u32::MAX,
+                    DEFAULT_START_BITRATE,
+                    glib::ParamFlags::READABLE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "start-bitrate",
+                    "Start Bitrate",
+                    "Initial bitrate estimate in bit/s",
+                    0,
+                    u32::MAX,
+                    DEFAULT_START_BITRATE,
+                    glib::ParamFlags::WRITABLE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "min-bitrate",
+                    "Minimum Bitrate",
+                    "Lower bound in bit/s for the target bitrate",
+                    0,
+                    u32::MAX,
+                    DEFAULT_MIN_BITRATE,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "max-bitrate",
+                    "Maximum Bitrate",
+                    "Upper bound in bit/s for the target bitrate",
+                    0,
+                    u32::MAX,
+                    DEFAULT_MAX_BITRATE,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "encoder-bitrate-property",
+                    "Encoder Bitrate Property",
+                    "If set, the target bitrate is applied to this property of the encoders linked to the video pads",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "encoder-bitrate-divisor",
+                    "Encoder Bitrate Divisor",
+                    "The target bitrate is divided by this before being applied to encoders (1000 for kbit/s)",
+                    1,
+                    u32::MAX,
+                    DEFAULT_ENCODER_BITRATE_DIVISOR,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "stats-interval-ms",
+                    "Stats Interval",
+                    "If not 0, a stats snapshot is posted as element message on the bus at this interval (ms)",
+                    0,
+                    u32::MAX,
+                    0,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "min-port",
+                    "Minimum Port",
+                    "Lowest local UDP port used for ICE candidates (0 = no limit)",
+                    0,
+                    u16::MAX as u32,
+                    0,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "max-port",
+                    "Maximum Port",
+                    "Highest local UDP port used for ICE candidates (0 = no limit)",
+                    0,
+                    u16::MAX as u32,
+                    0,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "ice-restart-timeout-ms",
+                    "ICE Restart Timeout",
+                    "If not 0, ICE is restarted when the connection stays disconnected or failed for this long (ms)",
+                    0,
+                    u32::MAX,
+                    0,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "udp-mux-port",
+                    "UDP Mux Port",
+                    "If not 0, ICE traffic of all peer connections goes through one UDP port, shared by all elements using the same port (takes precedence over min-port and max-port)",
+                    0,
+                    u16::MAX as u32,
+                    0,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "nat-1to1-ips",
+                    "NAT 1:1 IPs",
+                    "Comma separated public IPs advertised in host candidates instead of the local addresses",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "nat-1to1-candidate-type",
+                    "NAT 1:1 Candidate Type",
+                    "Candidates carrying nat-1to1-ips (host replaces the local addresses, srflx adds server reflexive candidates), host if unset",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "network-types",
+                    "Network Types",
+                    "Comma separated network types gathered (udp4, udp6, TCP is not supported by webrtc-rs), all if unset",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "interface-filter",
+                    "Interface Filter",
+                    "Comma separated network interfaces used for ICE candidates, all if unset",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "ip-filter",
+                    "IP Filter",
+                    "Comma separated local IP addresses used for ICE candidates, all if unset",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "mdns-mode",
+                    "mDNS Mode",
+                    "Handling of mDNS candidates (disabled, query-only, query-and-gather), query-only if unset",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "ice-disconnected-timeout-ms",
+                    "ICE Disconnected Timeout",
+                    "Time without network activity before ICE is disconnected (ms, 0 = webrtc-rs default of 5 s)",
+                    0,
+                    u32::MAX,
+                    0,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "ice-failed-timeout-ms",
+                    "ICE Failed Timeout",
+                    "Time after disconnection before ICE fails (ms, 0 = webrtc-rs default of 25 s)",
+                    0,
+                    u32::MAX,
+                    0,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "ice-keepalive-interval-ms",
+                    "ICE Keepalive Interval",
+                    "Interval of ICE keepalives while no media flows (ms, 0 = webrtc-rs default of 2 s)",
+                    0,
+                    u32::MAX,
+                    0,
+                    glib::ParamFlags::READWRITE,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, obj: &Self::Type, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "stun-server" => {
+                self.webrtc_settings.lock().unwrap().stun_server = value.get().expect("type checked upstream");
+            }
+            "turn-server" => {
+                self.webrtc_settings.lock().unwrap().turn_server = value.get().expect("type checked upstream");
+            }
+            "bundle-policy" => {
+                let policy = value.get::<Option<String>>().expect("type checked upstream");
+                // webrtc-rs maps unknown names to unspecified, a typo would silently drop the policy
+                match RTCBundlePolicy::from(policy.as_deref().unwrap_or_default()) {
+                    RTCBundlePolicy::Unspecified if policy.is_some() => {
+                        warning!(CAT, obj: obj, "Ignoring unknown bundle policy {:?}", policy);
+                    }
+                    bundle_policy => self.set_bundle_policy(bundle_policy),
+                }
+            }
+            "ice-transport-policy" => {
+                let policy = value.get::<Option<String>>().expect("type checked upstream");
+                let ice_transport_policy = match RTCIceTransportPolicy::from(policy.as_deref().unwrap_or_default()) {
+                    RTCIceTransportPolicy::Unspecified if policy.is_some() => {
+                        warning!(CAT, obj: obj, "Ignoring unknown ICE transport policy {:?}", policy);
+                        return;
+                    }
+                    ice_transport_policy => ice_transport_policy,
+                };
+                let mut webrtc_settings = self.webrtc_settings.lock().unwrap();
+
+                match webrtc_settings.config {
+                    Some(ref mut config) => {
+                        config.ice_transport_policy = ice_transport_policy;
+                    }
+                    None => {
+                        error!(CAT, "Trying to set ICE transport policy after starting");
+                    }
+                }
+            }
+            "start-bitrate" => {
+                self.bandwidth.lock().unwrap().set_start_bitrate(value.get().expect("type checked upstream"));
+            }
+            "min-bitrate" => {
+                self.bandwidth.lock().unwrap().set_min_bitrate(value.get().expect("type checked upstream"));
+            }
+            "max-bitrate" => {
+                self.bandwidth.lock().unwrap().set_max_bitrate(value.get().expect("type checked upstream"));
+            }
+            "encoder-bitrate-property" => {
+                self.webrtc_settings.lock().unwrap().encoder_bitrate_property = value.get().expect("type checked upstream");
+            }
+            "encoder-bitrate-divisor" => {
+                self.webrtc_settings.lock().unwrap().encoder_bitrate_divisor = value.get().expect("type checked upstream");
+            }
+            "stats-interval-ms" => {
+                self.webrtc_settings.lock().unwrap().stats_interval_ms = value.get().expect("type checked upstream");
+                if obj.current_state() >= gst::State::Paused {
+                    self.start_stats_task(obj);
+                }
+            }
+            "min-port" => {
+                self.webrtc_settings.lock().unwrap().min_port = value.get().expect("type checked upstream");
+            }
+            "max-port" => {
+                self.webrtc_settings.lock().unwrap().max_port = value.get().expect("type checked upstream");
+            }
+            "nat-1to1-ips" => {
+                self.webrtc_settings.lock().unwrap().nat_1to1_ips = value.get().expect("type checked upstream");
+            }
+            "udp-mux-port" => {
+                self.webrtc_settings.lock().unwrap().udp_mux_port = value.get().expect("type checked upstream");
+            }
+            "ice-restart-timeout-ms" => {
+                self.webrtc_settings.lock().unwrap().ice_restart_timeout_ms = value.get().expect("type checked upstream");
+            }
+            "nat-1to1-candidate-type" => {
+                self.webrtc_settings.lock().unwrap().nat_1to1_candidate_type = value.get().expect("type checked upstream");
+            }
+            "network-types" => {
+                self.webrtc_settings.lock().unwrap().network_types = value.get().expect("type checked upstream");
+            }
+            "interface-filter" => {
+                self.webrtc_settings.lock().unwrap().interface_filter = value.get().expect("type checked upstream");
+            }
+            "ip-filter" => {
+                self.webrtc_settings.lock().unwrap().ip_filter = value.get().expect("type checked upstream");
+            }
+            "mdns-mode" => {
+                self.webrtc_settings.lock().unwrap().mdns_mode = value.get().expect("type checked upstream");
+            }
+            "ice-disconnected-timeout-ms" => {
+                self.webrtc_settings.lock().unwrap().ice_disconnected_timeout_ms = value.get().expect("type checked upstream");
+            }
+            "ice-failed-timeout-ms" => {
+                self.webrtc_settings.lock().unwrap().ice_failed_timeout_ms = value.get().expect("type checked upstream");
+            }
+            "ice-keepalive-interval-ms" => {
+                self.webrtc_settings.lock().unwrap().ice_keepalive_interval_ms = value.get().expect("type checked upstream");
+            }
+            _ => unreachable!("unknown property {}", pspec.name()),
+        }
+    }
+
+    fn property(&self, obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "stun-server" => self.webrtc_settings.lock().unwrap().stun_server.to_value(),
+            "target-bitrate" => self.bandwidth.lock().unwrap().target_bitrate().to_value(),
+            "min-bitrate" => self.bandwidth.lock().unwrap().min_bitrate().to_value(),
+            "max-bitrate" => self.bandwidth.lock().unwrap().max_bitrate().to_value(),
+            "encoder-bitrate-property" => self.webrtc_settings.lock().unwrap().encoder_bitrate_property.to_value(),
+            "encoder-bitrate-divisor" => self.webrtc_settings.lock().unwrap().encoder_bitrate_divisor.to_value(),
+            "stats-interval-ms" => self.webrtc_settings.lock().unwrap().stats_interval_ms.to_value(),
+            "min-port" => self.webrtc_settings.lock().unwrap().min_port.to_value(),
+            "max-port" => self.webrtc_settings.lock().unwrap().max_port.to_value(),
+            "nat-1to1-ips" => self.webrtc_settings.lock().unwrap().nat_1to1_ips.to_value(),
+            "udp-mux-port" => self.webrtc_settings.lock().unwrap().udp_mux_port.to_value(),
+            "ice-restart-timeout-ms" => self.webrtc_settings.lock().unwrap().ice_restart_timeout_ms.to_value(),
+            "nat-1to1-candidate-type" => self.webrtc_settings.lock().unwrap().nat_1to1_candidate_type.to_value(),
+            "network-types" => self.webrtc_settings.lock().unwrap().network_types.to_value(),
+            "interface-filter" => self.webrtc_settings.lock().unwrap().interface_filter.to_value(),
+            "ip-filter" => self.webrtc_settings.lock().unwrap().ip_filter.to_value(),
+            "mdns-mode" => self.webrtc_settings.lock().unwrap().mdns_mode.to_value(),
+            "ice-disconnected-timeout-ms" => self.webrtc_settings.lock().unwrap().ice_disconnected_timeout_ms.to_value(),
+            "ice-failed-timeout-ms" => self.webrtc_settings.lock().unwrap().ice_failed_timeout_ms.to_value(),
+            "ice-keepalive-interval-ms" => self.webrtc_settings.lock().unwrap().ice_keepalive_interval_ms.to_value(),
+            "turn-server" => self.webrtc_settings.lock().unwrap().turn_server.to_value(),
+            "bundle-policy" | "ice-transport-policy" => {
+                let name = pspec.name().to_string();
+                let describe = move |config: &RTCConfiguration| {
+                    if name == "bundle-policy" {
+                        config.bundle_policy.to_string()
+                    } else {
+                        config.ice_transport_policy.to_string()
+                    }
+                };
+
+                if let Some(config) = &self.webrtc_settings.lock().unwrap().config {
+                    return describe(config).to_value();
+                }
+
+                let webrtc_state = self.webrtc_state.clone();
+                self.run_blocking(async move {
+                    webrtc_state
+                        .lock()
+                        .await
+                        .peer_connection
+                        .as_ref()
+                        .map(|conn| describe(conn.get_configuration()))
+                })
+                .to_value()
+            }
+            "connection-state" | "ice-gathering-state" => {
+                let name = pspec.name().to_string();
+                let webrtc_state = self.webrtc_state.clone();
+                self.run_blocking(async move {
+                    webrtc_state.lock().await.peer_connection.as_ref().map(|conn| {
+                        if name == "connection-state" {
+                            conn.connection_state().to_string()
+                        } else {
+                            conn.ice_gathering_state().to_string()
+                        }
+                    })
+                })
+                .to_value()
+            }
+            "dtls-fingerprint" => {
+                let element = obj.clone();
+                self.run_blocking(async move {
+                    WebRtcRedux::from_instance(&element)
+                        .local_fingerprints()
+                        .await
+                        .ok()?
+                        .first()
+                        .map(|fingerprint| format!("{} {}", fingerprint.algorithm, fingerprint.value))
+                })
+                .to_value()
+            }
+            "local-description" | "remote-description" => {
+                let remote = pspec.name() == "remote-description";
+                let webrtc_state = self.webrtc_state.clone();
+                self.run_blocking(async move {
+                    let webrtc_state = webrtc_state.lock().await;
+                    let conn = webrtc_state.peer_connection.as_ref()?;
+                    let description = if remote {
+                        conn.remote_description().await
+                    } else {
+                        conn.local_description().await
+                    };
+                    description.map(|description| description.sdp)
+                })
+                .to_value()
+            }
+            _ => unreachable!("unknown property {}", pspec.name()),
+        }
+    }
+
+    fn signals() -> &'static [glib::subclass::Signal] {
+        static SIGNALS: Lazy<Vec<glib::subclass::Signal>> = Lazy::new(|| {
+            vec![
+                /*
+                 * create-offer(options: GstStructure, promise: GstPromise)
+                 * Replies with a structure containing the SDP text in the "offer" field
+                 */
+                glib::subclass::Signal::builder(
+                    "create-offer",
+                    &[gst::Structure::static_type().into(), gst::Promise::static_type().into()],
+                    <()>::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::WebRtcRedux>().expect("signal arg");
+                    let options = args[1].get::<Option<gst::Structure>>().expect("signal arg");
+                    let promise = args[2].get::<Option<gst::Promise>>().expect("signal arg");
+
+                    let options = options.map(|options| RTCOfferOptions {
+                        ice_restart: options.get::<bool>("ice-restart").unwrap_or(false),
+                        ..Default::default()
+                    });
+
+                    let this = WebRtcRedux::from_instance(&element);
+                    let element = element.clone();
+                    this.spawn_promise(promise, async move {
+                        let offer = WebRtcRedux::from_instance(&element).create_offer(options).await?;
+                        Ok(Some(
+                            gst::Structure::builder("application/x-gst-promise")
+                                .field("offer", offer.to_string(LineEnding::CRLF))
+                                .build(),
+                        ))
+                    });
+
+                    None
+                })
+                .build(),
+                /*
+                 * create-answer(options: GstStructure, promise: GstPromise)
+                 * Replies with a structure containing the SDP text in the "answer" field
+                 */
+                glib::subclass::Signal::builder(
+                    "create-answer",
+                    &[gst::Structure::static_type().into(), gst::Promise::static_type().into()],
+                    <()>::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::WebRtcRedux>().expect("signal arg");
+                    let promise = args[2].get::<Option<gst::Promise>>().expect("signal arg");
+
+                    let this = WebRtcRedux::from_instance(&element);
+                    let element = element.clone();
+                    this.spawn_promise(promise, async move {
+                        let answer = WebRtcRedux::from_instance(&element).create_answer(None).await?;
+                        Ok(Some(
+                            gst::Structure::builder("application/x-gst-promise")
+                                .field("answer", answer.to_string(LineEnding::CRLF))
+                                .build(),
+                        ))
+                    });
+
+                    None
+                })
+                .build(),
+                /*
+                 * set-local-description(type: String, sdp: String, promise: GstPromise)
+                 */
+                glib::subclass::Signal::builder(
+                    "set-local-description",
+                    &[String::static_type().into(), String::static_type().into(), gst::Promise::static_type().into()],
+                    <()>::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::WebRtcRedux>().expect("signal arg");
+                    WebRtcRedux::set_description_from_signal(&element, args, false)
+                })
+                .build(),
+                /*
+                 * set-remote-description(type: String, sdp: String, promise: GstPromise)
+                 */
+                glib::subclass::Signal::builder(
+                    "set-remote-description",
+                    &[String::static_type().into(), String::static_type().into(), gst::Promise::static_type().into()],
+                    <()>::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::WebRtcRedux>().expect("signal arg");
+                    WebRtcRedux::set_description_from_signal(&element, args, true)
+                })
+                .build(),
+                /*
+                 * add-ice-candidate(mline_index: u32, candidate: String)
+                 */
+                glib::subclass::Signal::builder(
+                    "add-ice-candidate",
+                    &[u32::static_type().into(), String::static_type().into()],
+                    <()>::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::WebRtcRedux>().expect("signal arg");
+                    let mline_index = args[1].get::<u32>().expect("signal arg");
+                    let candidate = match args[2].get::<Option<String>>().expect("signal arg") {
+                        Some(candidate) => candidate,
+                        None => {
+                            warning!(CAT, obj: &element, "Ignoring NULL ICE candidate");
+                            return None;
+                        }
+                    };
+
+                    let this = WebRtcRedux::from_instance(&element);
+                    let element = element.clone();
+                    this.spawn_promise(None, async move {
+                        WebRtcRedux::from_instance(&element)
+                            .add_ice_candidate(RTCIceCandidateInit {
+                                candidate,
+                                sdp_mline_index: Some(mline_index as u16),
+                                ..Default::default()
+                            })
+                            .await?;
+
+                        Ok(None)
+                    });
+
+                    None
+                })
+                .build(),
+                /*
+                 * get-stats() -> GstStructure
+                 * Track, candidate pair and bitrate statistics, see `Stats::to_structure` for the layout
+                 */
+                glib::subclass::Signal::builder(
+                    "get-stats",
+                    &[],
+                    gst::Structure::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::WebRtcRedux>().expect("signal arg");
+
+                    let this = WebRtcRedux::from_instance(&element);
+                    let stats_element = element.clone();
+                    let stats = this.run_blocking(async move {
+                        WebRtcRedux::from_instance(&stats_element).get_stats().await
+                    });
+
+                    let structure = match stats {
+                        Ok(stats) => stats.to_structure(),
+                        Err(e) => {
+                            error!(CAT, obj: &element, "Failed to get stats: {:?}", e);
+                            gst::Structure::new_empty("application/x-webrtcredux-stats")
+                        }
+                    };
+
+                    Some(structure.to_value())
+                })
+                .build(),
+                /*
+                 * on-ice-candidate(mline_index: u32, candidate: String)
+                 */
+                glib::subclass::Signal::builder(
+                    "on-ice-candidate",
+                    &[u32::static_type().into(), String::static_type().into()],
+                    <()>::static_type().into(),
+                )
+                .build(),
+                /*
+                 * on-target-bitrate(bitrate: u32)
+                 * Emitted when the estimated available bitrate (bit/s) changes noticeably
+                 */
+                glib::subclass::Signal::builder(
+                    "on-target-bitrate",
+                    &[u32::static_type().into()],
+                    <()>::static_type().into(),
+                )
+                .build(),
+                /*
+                 * on-negotiation-needed()
+                 */
+                glib::subclass::Signal::builder(
+                    "on-negotiation-needed",
+                    &[],
+                    <()>::static_type().into(),
+                )
+                .build(),
+                /*
+                 * restart-ice()
+                 * The next offer restarts ICE, on-negotiation-needed is emitted to get it sent
+                 */
+                glib::subclass::Signal::builder(
+                    "restart-ice",
+                    &[],
+                    <()>::static_type().into(),
+                )
+                .action()
+                .class_handler(|_, args| {
+                    let element = args[0].get::<super::WebRtcRedux>().expect("signal arg");
+
+                    let this = WebRtcRedux::from_instance(&element);
+                    let restart_element = element.clone();
+                    let res = this.run_blocking(async move {
+                        WebRtcRedux::from_instance(&restart_element).restart_ice(&restart_element).await
+                    });
+                    if let Err(e) = res {
+                        warning!(CAT, obj: &element, "Failed to restart ICE: {:?}", e);
+                    }
+
+                    None
+                })
+                .build(),
+                /*
+                 * on-ice-restart(automatic: bool, peer: Option<String>)
+                 * Emitted when ICE is restarted, automatic is set when ice-restart-timeout-ms triggered it,
+                 * peer is the id of the broadcast peer or None for the main connection
+                 */
+                glib::subclass::Signal::builder(
+                    "on-ice-restart",
+                    &[bool::static_type().into(), String::static_type().into()],
+                    <()>::static_type().into(),
+                )
+                .build(),
+            ]
+        });
+
+        SIGNALS.as_ref()
+    }
+}
 
 impl GstObjectImpl for WebRtcRedux {}
 
-impl BinImpl for WebRtcRedux {}
\ No newline at end of file
+impl BinImpl for WebRtcRedux {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn h264_profile_level_ids() {
+        for (profile, level, profile_level_id) in [
+            ("constrained-baseline", Some("3.1"), "42e01f"),
+            ("baseline", Some("3.1"), "42001f"),
+            ("main", Some("3.1"), "4d001f"),
+            ("main", Some("4"), "4d0028"),
+            ("high", Some("5.1"), "640033"),
+            ("baseline", Some("1b"), "42100b"),
+            ("high", Some("1b"), "640009"),
+            // Whatever the encoder didn't say falls back to constrained baseline at level 3.1
+            ("", None, "42e01f"),
+            ("main", Some("bogus"), "4d001f"),
+        ] {
+            assert_eq!(h264_profile_level_id(profile, level), profile_level_id, "{} {:?}", profile, level);
+        }
+    }
+
+    #[test]
+    fn h264_fmtp_lines() {
+        gst::init().unwrap();
+
+        let caps = gst::Caps::builder("video/x-h264").field("profile", "main").field("level", "3.1").build();
+        assert_eq!(
+            h264_fmtp_line(caps.structure(0).unwrap()),
+            "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=4d001f"
+        );
+
+        // RTP caps are taken as they are
+        let caps = gst::Caps::builder("application/x-rtp")
+            .field("profile-level-id", "640028")
+            .field("packetization-mode", "0")
+            .build();
+        assert_eq!(
+            h264_fmtp_line(caps.structure(0).unwrap()),
+            "level-asymmetry-allowed=1;packetization-mode=0;profile-level-id=640028"
+        );
+    }
+}
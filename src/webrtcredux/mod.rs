@@ -1,9 +1,19 @@
+#[cfg(feature = "whep")]
+use std::net::SocketAddr;
+use std::sync::Arc;
+
 use gst::glib;
 use gst::prelude::*;
 use gst::subclass::prelude::ObjectSubclassExt;
 use gst::ErrorMessage;
 
+mod bandwidth;
+mod ice_restart;
 mod sender;
+mod udp_mux;
+#[cfg(feature = "whep")]
+mod whep;
+mod whip;
 
 mod imp;
 
@@ -11,12 +21,19 @@ pub use imp::*;
 use tokio::runtime::Handle;
 use webrtc::ice_transport::ice_gatherer::OnICEGathererStateChangeHdlrFn;
 use webrtc::ice_transport::ice_gatherer::OnLocalCandidateHdlrFn;
+use webrtc::peer_connection::OnDataChannelHdlrFn;
 use webrtc::peer_connection::OnICEConnectionStateChangeHdlrFn;
 use webrtc::peer_connection::OnNegotiationNeededHdlrFn;
 use webrtc::peer_connection::OnPeerConnectionStateChangeHdlrFn;
 
+use self::peer::PeerHandle;
 use self::sdp::SDP;
+use self::stats::Stats;
+pub mod payloader;
+pub mod peer;
 pub mod sdp;
+pub mod signalling;
+pub mod stats;
 
 glib::wrapper! {
     pub struct WebRtcRedux(ObjectSubclass<imp::WebRtcRedux>) @extends gst::Bin, gst::Element, gst::Object;
@@ -31,7 +48,6 @@ impl Default for WebRtcRedux {
 unsafe impl Send for WebRtcRedux {}
 unsafe impl Sync for WebRtcRedux {}
 
-//TODO: Add signal for those methods for compatibility with other programing languages
 impl WebRtcRedux {
     pub fn add_ice_servers(&self, ice_servers: Vec<RTCIceServer>) {
         imp::WebRtcRedux::from_instance(self).add_ice_servers(ice_servers);
@@ -41,10 +57,45 @@ impl WebRtcRedux {
         imp::WebRtcRedux::from_instance(self).set_bundle_policy(bundle_policy);
     }
 
+    #[deprecated(note = "webrtc-rs only supports unified plan SDP semantics")]
     pub fn set_sdp_semantics(&self, sdp_semantics: RTCSdpSemantics) {
         imp::WebRtcRedux::from_instance(self).set_sdp_semantics(sdp_semantics);
     }
 
+    /// Replaces the default codecs, payload types and RTCP feedback are used as given. Must be called before the element goes to Ready.
+    pub fn set_codecs(&self, codecs: Vec<RTCRtpCodecParameters>) {
+        imp::WebRtcRedux::from_instance(self).set_codecs(codecs);
+    }
+
+    pub fn set_header_extensions(&self, header_extensions: Vec<(RTCRtpHeaderExtensionCapability, RTPCodecType)>) {
+        imp::WebRtcRedux::from_instance(self).set_header_extensions(header_extensions);
+    }
+
+    /// Network, ICE and DTLS settings of webrtc-rs, applied to every peer connection. Must be called before the element goes to Ready.
+    pub fn set_setting_engine(&self, setting_engine: SettingEngine) {
+        imp::WebRtcRedux::from_instance(self).set_setting_engine(setting_engine);
+    }
+
+    pub fn set_interceptors(&self, interceptors: Interceptors) {
+        imp::WebRtcRedux::from_instance(self).set_interceptors(interceptors);
+    }
+
+    /// DTLS certificates presented by every peer connection, a stable one keeps the fingerprint stable across runs.
+    /// Without one, a certificate is generated when the element goes to Ready, setting one afterwards is an error.
+    pub fn set_certificates(&self, certificates: Vec<RTCCertificate>) -> Result<(), ErrorMessage> {
+        imp::WebRtcRedux::from_instance(self).set_certificates(certificates)
+    }
+
+    /// Same as `set_certificates` with a PKCS#8 private key and its certificate (optionally followed by the chain)
+    /// as PEM, e.g. written by `openssl req -x509`
+    pub fn set_certificate_pem(&self, key_pem: &str, cert_pem: &str) -> Result<(), ErrorMessage> {
+        imp::WebRtcRedux::from_instance(self).set_certificate_pem(key_pem, cert_pem)
+    }
+
+    pub async fn local_fingerprints(&self) -> Result<Vec<RTCDtlsFingerprint>, ErrorMessage> {
+        imp::WebRtcRedux::from_instance(self).local_fingerprints().await
+    }
+
     pub fn set_stream_id(&self, pad_name: &str, stream_id: &str) -> Result<(), ErrorMessage> {
         imp::WebRtcRedux::from_instance(self).set_stream_id(pad_name, stream_id)
     }
@@ -87,6 +138,22 @@ impl WebRtcRedux {
             .await
     }
 
+    pub async fn create_data_channel(
+        &self,
+        label: &str,
+        options: Option<RTCDataChannelInit>,
+    ) -> Result<Arc<RTCDataChannel>, ErrorMessage> {
+        imp::WebRtcRedux::from_instance(self)
+            .create_data_channel(label, options)
+            .await
+    }
+
+    pub async fn on_data_channel(&self, f: OnDataChannelHdlrFn) {
+        imp::WebRtcRedux::from_instance(self)
+            .on_data_channel(f)
+            .await
+    }
+
     pub async fn on_negotiation_needed(&self, f: OnNegotiationNeededHdlrFn) -> Result<(), ErrorMessage>
     {
         imp::WebRtcRedux::from_instance(self)
@@ -131,13 +198,54 @@ impl WebRtcRedux {
             .await
     }
 
+    /// Makes the next `create_offer` restart ICE with fresh credentials and fires on-negotiation-needed,
+    /// the application then renegotiates as usual.
+    pub async fn restart_ice(&self) -> Result<(), ErrorMessage> {
+        imp::WebRtcRedux::from_instance(self).restart_ice(self).await
+    }
+
+    pub async fn get_stats(&self) -> Result<Stats, ErrorMessage> {
+        imp::WebRtcRedux::from_instance(self).get_stats().await
+    }
+
     pub fn set_tokio_runtime(&self, handle: Handle) {
         imp::WebRtcRedux::from_instance(self).set_tokio_runtime(handle);
     }
 
+    /// The runtime set with `set_tokio_runtime`, or the element's own one
+    pub(crate) fn runtime_handle(&self) -> Handle {
+        imp::WebRtcRedux::from_instance(self).runtime_handle()
+    }
+
     pub async fn wait_for_all_tracks(&self) {
         imp::WebRtcRedux::from_instance(self).wait_for_all_tracks().await;
     }
+
+    /// Adds a peer connection that receives the same tracks as the main one (broadcast mode)
+    pub async fn add_peer(&self, id: &str) -> Result<PeerHandle, ErrorMessage> {
+        imp::WebRtcRedux::from_instance(self).add_peer(self, id).await
+    }
+
+    pub async fn remove_peer(&self, id: &str) -> Result<(), ErrorMessage> {
+        imp::WebRtcRedux::from_instance(self).remove_peer(self, id).await
+    }
+
+    /// Publishes to a WHIP endpoint: posts the offer, applies the answer and trickles ICE candidates.
+    /// The resource is deleted on `whip_disconnect` or when the element shuts down.
+    pub async fn whip_connect(&self, endpoint: &str, token: Option<&str>) -> Result<(), ErrorMessage> {
+        imp::WebRtcRedux::from_instance(self).whip_connect(self, endpoint, token).await
+    }
+
+    pub async fn whip_disconnect(&self) -> Result<(), ErrorMessage> {
+        imp::WebRtcRedux::from_instance(self).whip_disconnect(self).await
+    }
+
+    /// Serves a WHEP endpoint on `addr`, every POSTed offer gets its own peer connection fed by the element's
+    /// sink pads. Returns the bound address, the server stops when the element goes back to `Null`.
+    #[cfg(feature = "whep")]
+    pub fn whep_serve(&self, addr: SocketAddr) -> Result<SocketAddr, ErrorMessage> {
+        imp::WebRtcRedux::from_instance(self).whep_serve(self, addr)
+    }
 }
 
 pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
@@ -0,0 +1,266 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use webrtc::rtp::Error;
+use webrtc::rtp::packetizer::Payloader;
+
+// https://datatracker.ietf.org/doc/html/rfc7798#section-4.4.3
+const H265_NALU_HEADER_SIZE: usize = 2;
+const H265_FU_HEADER_SIZE: usize = 1;
+const H265_NALU_TYPE_AUD: u8 = 35;
+const H265_NALU_TYPE_FU: u8 = 49;
+
+/// Packetizes H.265 byte-stream access units as single NAL unit and fragmentation unit packets (RFC 7798)
+#[derive(Debug, Default, Clone)]
+pub struct H265Payloader;
+
+impl H265Payloader {
+    fn payload_nalu(mtu: usize, nalu: &[u8], payloads: &mut Vec<Bytes>) {
+        if nalu.len() <= H265_NALU_HEADER_SIZE {
+            return;
+        }
+
+        // Access unit delimiters are implied by the RTP timestamp
+        let nalu_type = (nalu[0] >> 1) & 0x3f;
+        if nalu_type == H265_NALU_TYPE_AUD {
+            return;
+        }
+
+        if nalu.len() <= mtu {
+            payloads.push(Bytes::copy_from_slice(nalu));
+            return;
+        }
+
+        let max_fragment_size = mtu.saturating_sub(H265_NALU_HEADER_SIZE + H265_FU_HEADER_SIZE);
+        if max_fragment_size == 0 {
+            return;
+        }
+
+        // The payload header keeps F, LayerId and TID of the NAL unit, only the type changes
+        let payload_header = [(nalu[0] & 0b1000_0001) | (H265_NALU_TYPE_FU << 1), nalu[1]];
+        let fragments = nalu[H265_NALU_HEADER_SIZE..].chunks(max_fragment_size);
+        let last = fragments.len() - 1;
+
+        for (i, fragment) in fragments.enumerate() {
+            let mut fu_header = nalu_type;
+            if i == 0 {
+                fu_header |= 0b1000_0000;
+            }
+            if i == last {
+                fu_header |= 0b0100_0000;
+            }
+
+            let mut payload = BytesMut::with_capacity(H265_NALU_HEADER_SIZE + H265_FU_HEADER_SIZE + fragment.len());
+            payload.put_slice(&payload_header);
+            payload.put_u8(fu_header);
+            payload.put_slice(fragment);
+            payloads.push(payload.freeze());
+        }
+    }
+}
+
+impl Payloader for H265Payloader {
+    fn payload(&mut self, mtu: usize, payload: &Bytes) -> Result<Vec<Bytes>, Error> {
+        let mut payloads = Vec::new();
+        for nalu in split_annex_b(payload) {
+            H265Payloader::payload_nalu(mtu, nalu, &mut payloads);
+        }
+
+        Ok(payloads)
+    }
+
+    fn clone_to(&self) -> Box<dyn Payloader + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+/// Splits an Annex B byte-stream into NAL units, dropping the start codes
+fn split_annex_b(data: &[u8]) -> Vec<&[u8]> {
+    let mut nalus = Vec::new();
+    let mut start = None;
+    let mut i = 0;
+
+    while i + 2 < data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            if let Some(start) = start {
+                // A four byte start code leaves a trailing zero on the previous NAL unit
+                let mut end = i;
+                while end > start && data[end - 1] == 0 {
+                    end -= 1;
+                }
+                nalus.push(&data[start..end]);
+            }
+
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+
+    match start {
+        Some(start) => nalus.push(&data[start..]),
+        // Not a byte-stream, treat it as a single NAL unit
+        None if !data.is_empty() => nalus.push(data),
+        None => {}
+    }
+
+    nalus
+}
+
+// https://aomediacodec.github.io/av1-rtp-spec/#44-av1-aggregation-header
+const AV1_AGGREGATION_HEADER_Z: u8 = 0b1000_0000;
+const AV1_AGGREGATION_HEADER_Y: u8 = 0b0100_0000;
+const AV1_AGGREGATION_HEADER_N: u8 = 0b0000_1000;
+const AV1_OBU_TYPE_SEQUENCE_HEADER: u8 = 1;
+const AV1_OBU_TYPE_TEMPORAL_DELIMITER: u8 = 2;
+const AV1_OBU_TYPE_TILE_LIST: u8 = 8;
+const AV1_OBU_EXTENSION_FLAG: u8 = 0b0000_0100;
+const AV1_OBU_HAS_SIZE_FIELD: u8 = 0b0000_0010;
+
+/// Packetizes AV1 temporal units (low overhead bitstream format) as described by the AV1 RTP payload spec
+#[derive(Debug, Default, Clone)]
+pub struct Av1Payloader;
+
+impl Av1Payloader {
+    /// Splits a temporal unit into OBUs with their size fields removed, as recommended for RTP
+    fn parse_obus(data: &[u8]) -> Vec<Bytes> {
+        let mut obus = Vec::new();
+        let mut data = data;
+
+        while !data.is_empty() {
+            let header = data[0];
+            let header_size = if header & AV1_OBU_EXTENSION_FLAG != 0 { 2 } else { 1 };
+            if data.len() < header_size {
+                break;
+            }
+
+            let (payload_size, size_field_size) = if header & AV1_OBU_HAS_SIZE_FIELD != 0 {
+                match read_leb128(&data[header_size..]) {
+                    Some((size, size_field_size)) => (size, size_field_size),
+                    None => break,
+                }
+            } else {
+                (data.len() - header_size, 0)
+            };
+
+            let payload_start = header_size + size_field_size;
+            let payload_end = match payload_start.checked_add(payload_size) {
+                Some(end) if end <= data.len() => end,
+                _ => break,
+            };
+
+            let obu_type = (header >> 3) & 0x0f;
+            if obu_type != AV1_OBU_TYPE_TEMPORAL_DELIMITER && obu_type != AV1_OBU_TYPE_TILE_LIST {
+                let mut obu = BytesMut::with_capacity(header_size + payload_size);
+                obu.put_u8(header & !AV1_OBU_HAS_SIZE_FIELD);
+                obu.put_slice(&data[1..header_size]);
+                obu.put_slice(&data[payload_start..payload_end]);
+                obus.push(obu.freeze());
+            }
+
+            data = &data[payload_end..];
+        }
+
+        obus
+    }
+}
+
+impl Payloader for Av1Payloader {
+    fn payload(&mut self, mtu: usize, payload: &Bytes) -> Result<Vec<Bytes>, Error> {
+        let obus = Av1Payloader::parse_obus(payload);
+        if obus.is_empty() || mtu < 3 {
+            return Ok(vec![]);
+        }
+
+        // A sequence header at the start of a temporal unit starts a new coded video sequence
+        let new_sequence = (obus[0][0] >> 3) & 0x0f == AV1_OBU_TYPE_SEQUENCE_HEADER;
+
+        let mut payloads = Vec::new();
+        let mut body = BytesMut::new();
+        let mut continuation = false;
+
+        let flush = |body: &mut BytesMut, continuation: bool, continues: bool, payloads: &mut Vec<Bytes>| {
+            let mut header = 0;
+            if continuation {
+                header |= AV1_AGGREGATION_HEADER_Z;
+            }
+            if continues {
+                header |= AV1_AGGREGATION_HEADER_Y;
+            }
+            if new_sequence && payloads.is_empty() {
+                header |= AV1_AGGREGATION_HEADER_N;
+            }
+
+            let mut payload = BytesMut::with_capacity(1 + body.len());
+            payload.put_u8(header);
+            payload.put_slice(body);
+            payloads.push(payload.freeze());
+            body.clear();
+        };
+
+        // Every OBU element is preceded by its length (W = 0), elements that don't fit are fragmented
+        for obu in obus {
+            let mut data = &obu[..];
+            loop {
+                let space = mtu - 1 - body.len();
+                if leb128_size(data.len()) + data.len() <= space {
+                    write_leb128(&mut body, data.len());
+                    body.put_slice(data);
+                    break;
+                }
+
+                if space > leb128_size(space) {
+                    let fragment_size = space - leb128_size(space);
+                    write_leb128(&mut body, fragment_size);
+                    body.put_slice(&data[..fragment_size]);
+                    data = &data[fragment_size..];
+
+                    flush(&mut body, continuation, true, &mut payloads);
+                    continuation = true;
+                } else {
+                    flush(&mut body, continuation, false, &mut payloads);
+                    continuation = false;
+                }
+            }
+        }
+
+        if !body.is_empty() {
+            flush(&mut body, continuation, false, &mut payloads);
+        }
+
+        Ok(payloads)
+    }
+
+    fn clone_to(&self) -> Box<dyn Payloader + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+fn read_leb128(data: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0usize;
+    for (i, byte) in data.iter().take(8).enumerate() {
+        value |= ((byte & 0x7f) as usize) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+
+    None
+}
+
+fn leb128_size(mut value: usize) -> usize {
+    let mut size = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        size += 1;
+    }
+
+    size
+}
+
+fn write_leb128(buf: &mut BytesMut, mut value: usize) {
+    while value >= 0x80 {
+        buf.put_u8((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
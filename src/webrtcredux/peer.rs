@@ -0,0 +1,250 @@
+use std::sync::{Arc, Mutex};
+
+use gst::glib;
+use gst::prelude::*;
+use gst::{gst_info as info, ErrorMessage};
+use tokio::sync::Mutex as AsyncMutex;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::ice_transport::ice_gatherer::{OnICEGathererStateChangeHdlrFn, OnLocalCandidateHdlrFn};
+use webrtc::peer_connection::offer_answer_options::{RTCAnswerOptions, RTCOfferOptions};
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::{OnICEConnectionStateChangeHdlrFn, OnNegotiationNeededHdlrFn, OnPeerConnectionStateChangeHdlrFn, RTCPeerConnection};
+
+use crate::webrtcredux::CAT;
+
+use super::ice_restart::IceRestartWatcher;
+use super::imp::parse_sdp;
+use super::sdp::{LineEnding, SDP};
+use super::WebRtcRedux;
+
+/// State shared by every handle of a peer. The negotiation and ICE handlers of the connection are owned by the
+/// element, like for the main connection, so ICE restarts can be scheduled.
+struct PeerShared {
+    id: String,
+    element: glib::WeakRef<WebRtcRedux>,
+    /// The next offer restarts ICE
+    ice_restart: Mutex<bool>,
+    ice_restart_watcher: Arc<IceRestartWatcher>,
+    on_negotiation_needed: AsyncMutex<Option<OnNegotiationNeededHdlrFn>>,
+    on_ice_connection_state_change: AsyncMutex<Option<OnICEConnectionStateChangeHdlrFn>>,
+}
+
+impl PeerShared {
+    /// Same as for the main connection, `on-ice-restart` carries the peer id
+    async fn restart_ice(&self, automatic: bool) {
+        *self.ice_restart.lock().unwrap() = true;
+        if let Some(element) = self.element.upgrade() {
+            info!(CAT, obj: &element, "Restarting ICE of peer {}", self.id);
+            element.emit_by_name::<()>("on-ice-restart", &[&automatic, &Some(self.id.clone())]);
+        }
+        self.negotiation_needed().await;
+    }
+
+    async fn negotiation_needed(&self) {
+        if let Some(f) = &mut *self.on_negotiation_needed.lock().await {
+            f().await;
+        }
+    }
+}
+
+/// A peer connection added to the element in broadcast mode.
+///
+/// Every peer receives the tracks of all input pads, the media is only encoded and packetized once.
+/// Negotiation, ICE and state callbacks are handled separately for every peer.
+#[derive(Clone)]
+pub struct PeerHandle {
+    id: String,
+    peer_connection: Arc<RTCPeerConnection>,
+    shared: Arc<PeerShared>,
+}
+
+impl PeerHandle {
+    pub(crate) fn new(element: &WebRtcRedux, id: &str, peer_connection: Arc<RTCPeerConnection>) -> Self {
+        let peer = PeerHandle {
+            id: id.to_string(),
+            peer_connection,
+            shared: Arc::new(PeerShared {
+                id: id.to_string(),
+                element: element.downgrade(),
+                ice_restart: Mutex::new(false),
+                ice_restart_watcher: Arc::new(IceRestartWatcher::default()),
+                on_negotiation_needed: AsyncMutex::new(None),
+                on_ice_connection_state_change: AsyncMutex::new(None),
+            }),
+        };
+        peer.connect_handlers(element);
+
+        peer
+    }
+
+    /// The handlers only hold weak references to the element, the connection owns them
+    fn connect_handlers(&self, element: &WebRtcRedux) {
+        let element_weak = element.downgrade();
+        let shared = self.shared.clone();
+        self.peer_connection.on_ice_connection_state_change(Box::new(move |connection_state| {
+            // Same timeout as the main connection
+            if let Some(element) = element_weak.upgrade() {
+                let timeout = element.property::<u32>("ice-restart-timeout-ms");
+                let restart_shared = Arc::downgrade(&shared);
+                shared.ice_restart_watcher.on_ice_connection_state_change(&element.runtime_handle(), timeout, connection_state, move || async move {
+                    if let Some(shared) = restart_shared.upgrade() {
+                        shared.restart_ice(true).await;
+                    }
+                });
+            }
+
+            let shared = shared.clone();
+            Box::pin(async move {
+                if let Some(f) = &mut *shared.on_ice_connection_state_change.lock().await {
+                    f(connection_state).await;
+                }
+            })
+        }));
+
+        let shared = self.shared.clone();
+        self.peer_connection.on_negotiation_needed(Box::new(move || {
+            let shared = shared.clone();
+            Box::pin(async move {
+                shared.negotiation_needed().await;
+            })
+        }));
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub async fn gathering_complete_promise(&self) -> tokio::sync::mpsc::Receiver<()> {
+        self.peer_connection.gathering_complete_promise().await
+    }
+
+    pub async fn create_offer(
+        &self,
+        options: Option<RTCOfferOptions>,
+    ) -> Result<SDP, ErrorMessage> {
+        let ice_restart = std::mem::take(&mut *self.shared.ice_restart.lock().unwrap());
+        let options = match (ice_restart, options) {
+            (true, options) => Some(RTCOfferOptions {
+                ice_restart: true,
+                ..options.unwrap_or_default()
+            }),
+            (false, options) => options,
+        };
+
+        match self.peer_connection.create_offer(options).await {
+            Ok(res) => parse_sdp(&res.sdp),
+            Err(e) => {
+                // Still pending for the next attempt
+                if ice_restart {
+                    *self.shared.ice_restart.lock().unwrap() = true;
+                }
+
+                Err(gst::error_msg!(
+                    gst::ResourceError::Failed,
+                    [&format!("Failed to create offer for peer {}: {:?}", self.id, e)]
+                ))
+            }
+        }
+    }
+
+    pub async fn create_answer(
+        &self,
+        options: Option<RTCAnswerOptions>,
+    ) -> Result<SDP, ErrorMessage> {
+        match self.peer_connection.create_answer(options).await {
+            Ok(res) => parse_sdp(&res.sdp),
+            Err(e) => Err(gst::error_msg!(
+                gst::ResourceError::Failed,
+                [&format!("Failed to create answer for peer {}: {:?}", self.id, e)]
+            )),
+        }
+    }
+
+    pub async fn local_description(&self) -> Result<Option<SDP>, ErrorMessage> {
+        match self.peer_connection.local_description().await {
+            None => Ok(None),
+            Some(res) => parse_sdp(&res.sdp).map(Some),
+        }
+    }
+
+    pub async fn set_local_description(&self, sdp: &SDP, sdp_type: RTCSdpType) -> Result<(), ErrorMessage> {
+        let mut default = RTCSessionDescription::default();
+        default.sdp = sdp.to_string(LineEnding::CRLF);
+        default.sdp_type = sdp_type;
+
+        if let Err(e) = self.peer_connection.set_local_description(default).await {
+            return Err(gst::error_msg!(
+                gst::ResourceError::Failed,
+                [&format!("Failed to set local description for peer {}: {:?}", self.id, e)]
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub async fn set_remote_description(&self, sdp: &SDP, sdp_type: RTCSdpType) -> Result<(), ErrorMessage> {
+        let mut default = RTCSessionDescription::default();
+        default.sdp = sdp.to_string(LineEnding::CRLF);
+        default.sdp_type = sdp_type;
+
+        if let Err(e) = self.peer_connection.set_remote_description(default).await {
+            return Err(gst::error_msg!(
+                gst::ResourceError::Failed,
+                [&format!("Failed to set remote description for peer {}: {:?}", self.id, e)]
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub async fn add_ice_candidate(
+        &self,
+        candidate: RTCIceCandidateInit,
+    ) -> Result<(), ErrorMessage> {
+        if let Err(e) = self.peer_connection.add_ice_candidate(candidate).await {
+            return Err(gst::error_msg!(
+                gst::ResourceError::Failed,
+                [&format!("Failed to add ICE candidate for peer {}: {:?}", self.id, e)]
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Makes the next `create_offer` of this peer restart ICE with fresh credentials, emits `on-ice-restart` with the
+    /// peer id and fires its negotiation-needed handler, the application then renegotiates as usual
+    pub async fn restart_ice(&self) -> Result<(), ErrorMessage> {
+        if self.peer_connection.connection_state() == RTCPeerConnectionState::Closed {
+            return Err(gst::error_msg!(
+                gst::ResourceError::Failed,
+                [&format!("Peer {} is closed", self.id)]
+            ));
+        }
+
+        self.shared.restart_ice(false).await;
+
+        Ok(())
+    }
+
+    pub async fn on_negotiation_needed(&self, f: OnNegotiationNeededHdlrFn) {
+        let _ = self.shared.on_negotiation_needed.lock().await.insert(f);
+    }
+
+    pub async fn on_ice_candidate(&self, f: OnLocalCandidateHdlrFn) {
+        self.peer_connection.on_ice_candidate(f);
+    }
+
+    pub async fn on_ice_gathering_state_change(&self, f: OnICEGathererStateChangeHdlrFn) {
+        self.peer_connection.on_ice_gathering_state_change(f);
+    }
+
+    pub async fn on_ice_connection_state_change(&self, f: OnICEConnectionStateChangeHdlrFn) {
+        let _ = self.shared.on_ice_connection_state_change.lock().await.insert(f);
+    }
+
+    pub async fn on_peer_connection_state_change(&self, f: OnPeerConnectionStateChangeHdlrFn) {
+        self.peer_connection.on_peer_connection_state_change(f);
+    }
+}
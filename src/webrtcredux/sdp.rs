@@ -1,5 +1,5 @@
 use std::{
-    fmt::Debug,
+    fmt::{self, Debug},
     num::{IntErrorKind, ParseIntError},
     str::FromStr,
 };
@@ -52,8 +52,9 @@ impl FromStr for MediaProp {
         match key {
             'i' => Ok(MediaProp::Title(value)),
             'c' => {
-                let address_split = tokens[2].split('/').collect::<Vec<&str>>();
-                let (address, ttl, num_addresses) = get_options_from_address_split(address_split)?;
+                let address_type = AddressType::from_str(token(&tokens, 1, "address type")?)?;
+                let address_split = token(&tokens, 2, "connection address")?.split('/').collect::<Vec<&str>>();
+                let (address, ttl, num_addresses) = get_options_from_address_split(&address_type, address_split)?;
 
                 let suffix = if tokens.len() > 3 {
                     Some(tokens[3..].join(" "))
@@ -62,8 +63,8 @@ impl FromStr for MediaProp {
                 };
 
                 Ok(MediaProp::Connection {
-                    net_type: NetworkType::from_str(tokens[0])?,
-                    address_type: AddressType::from_str(tokens[1])?,
+                    net_type: NetworkType::from_str(token(&tokens, 0, "network type")?)?,
+                    address_type,
                     address: address.to_string(),
                     ttl,
                     num_addresses,
@@ -74,8 +75,8 @@ impl FromStr for MediaProp {
                 let tokens = value.split(':').collect::<Vec<&str>>();
 
                 Ok(MediaProp::Bandwidth {
-                    r#type: BandwidthType::from_str(tokens[0])?,
-                    bandwidth: tokens[1].parse()?,
+                    r#type: BandwidthType::from_str(token(&tokens, 0, "bandwidth type")?)?,
+                    bandwidth: token(&tokens, 1, "bandwidth")?.parse()?,
                 })
             }
             'k' => Ok(MediaProp::EncryptionKeys(EncryptionKeyMethod::from_str(
@@ -96,7 +97,7 @@ impl FromStr for MediaProp {
                     }
                 })
             }
-            _ => Err(ParseError::UnknownToken(s.to_string())),
+            _ => Err(ParseError::UnknownKey(key, value)),
         }
     }
 }
@@ -113,7 +114,8 @@ impl ToString for MediaProp {
                 num_addresses,
                 suffix,
             } => {
-                // TTL is required for IPv4, but also apparently the major browsers don't like to follow specs so we're gonna ignore that
+                // TTL is required for IPv4, but also apparently the major browsers don't like to follow specs so we're gonna ignore that.
+                // IPv6 has no TTL, there the number of addresses directly follows the address
                 let mut address = if let Some(ttl) = ttl {
                     format!("{address}/{}", ttl)
                 } else {
@@ -258,8 +260,8 @@ impl ToString for BandwidthType {
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct TimeZoneAdjustment {
-    time: usize,
-    offset: String,
+    pub time: usize,
+    pub offset: String,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -366,12 +368,12 @@ impl FromStr for SdpProp {
         match key {
             'v' => Ok(SdpProp::Version(value.parse()?)),
             'o' => Ok(SdpProp::Origin {
-                username: tokens[0].to_string(),
-                session_id: tokens[1].to_string(),
-                session_version: tokens[2].parse()?,
-                net_type: NetworkType::from_str(tokens[3])?,
-                address_type: AddressType::from_str(tokens[4])?,
-                address: tokens[5].to_string(),
+                username: token(&tokens, 0, "username")?.to_string(),
+                session_id: token(&tokens, 1, "session id")?.to_string(),
+                session_version: token(&tokens, 2, "session version")?.parse()?,
+                net_type: NetworkType::from_str(token(&tokens, 3, "network type")?)?,
+                address_type: AddressType::from_str(token(&tokens, 4, "address type")?)?,
+                address: token(&tokens, 5, "unicast address")?.to_string(),
             }),
             's' => Ok(SdpProp::SessionName(value)),
             'i' => Ok(SdpProp::SessionInformation(value)),
@@ -379,8 +381,9 @@ impl FromStr for SdpProp {
             'e' => Ok(SdpProp::Email(value)),
             'p' => Ok(SdpProp::Phone(value)),
             'c' => {
-                let address_split = tokens[2].split('/').collect::<Vec<&str>>();
-                let (address, ttl, num_addresses) = get_options_from_address_split(address_split)?;
+                let address_type = AddressType::from_str(token(&tokens, 1, "address type")?)?;
+                let address_split = token(&tokens, 2, "connection address")?.split('/').collect::<Vec<&str>>();
+                let (address, ttl, num_addresses) = get_options_from_address_split(&address_type, address_split)?;
 
                 let suffix = if tokens.len() > 3 {
                     Some(tokens[3..].join(" "))
@@ -389,8 +392,8 @@ impl FromStr for SdpProp {
                 };
 
                 Ok(SdpProp::Connection {
-                    net_type: NetworkType::from_str(tokens[0])?,
-                    address_type: AddressType::from_str(tokens[1])?,
+                    net_type: NetworkType::from_str(token(&tokens, 0, "network type")?)?,
+                    address_type,
                     address: address.to_string(),
                     ttl,
                     num_addresses,
@@ -401,18 +404,18 @@ impl FromStr for SdpProp {
                 let tokens = value.split(':').collect::<Vec<&str>>();
 
                 Ok(SdpProp::Bandwidth {
-                    r#type: BandwidthType::from_str(tokens[0])?,
-                    bandwidth: tokens[1].parse()?,
+                    r#type: BandwidthType::from_str(token(&tokens, 0, "bandwidth type")?)?,
+                    bandwidth: token(&tokens, 1, "bandwidth")?.parse()?,
                 })
             }
             't' => Ok(SdpProp::Timing {
-                start: tokens[0].parse()?,
-                stop: tokens[1].parse()?,
+                start: token(&tokens, 0, "start time")?.parse()?,
+                stop: token(&tokens, 1, "stop time")?.parse()?,
             }),
             'r' => Ok(SdpProp::RepeatTimes {
-                interval: tokens[0].to_string(),
-                active_duration: tokens[1].to_string(),
-                start_offsets: tokens[2..]
+                interval: token(&tokens, 0, "repeat interval")?.to_string(),
+                active_duration: token(&tokens, 1, "active duration")?.to_string(),
+                start_offsets: tokens[2.min(tokens.len())..]
                     .iter()
                     .map(|t| t.to_string())
                     .collect::<Vec<String>>(),
@@ -421,8 +424,8 @@ impl FromStr for SdpProp {
                 let mut adjustments = Vec::new();
                 for group in tokens.chunks(2) {
                     adjustments.push(TimeZoneAdjustment {
-                        time: group[0].to_string().parse()?,
-                        offset: group[1].to_string(),
+                        time: token(group, 0, "adjustment time")?.parse()?,
+                        offset: token(group, 1, "offset")?.to_string(),
                     });
                 }
 
@@ -448,17 +451,20 @@ impl FromStr for SdpProp {
             }
             // Media lines can have their own attributes, the entire media block will be passed in
             'm' => {
-                let lines = value.split('\n').collect::<Vec<&str>>();
+                let lines = value
+                    .split('\n')
+                    .map(|line| line.strip_suffix('\r').unwrap_or(line))
+                    .collect::<Vec<&str>>();
                 let tokens = lines[0].split(' ').collect::<Vec<&str>>();
 
                 Ok(SdpProp::Media {
-                    r#type: MediaType::from_str(tokens[0])?,
-                    ports: tokens[1]
+                    r#type: MediaType::from_str(token(&tokens, 0, "media type")?)?,
+                    ports: token(&tokens, 1, "port")?
                         .split('/')
                         .map(|port| port.parse())
                         .collect::<Result<Vec<_>, _>>()?,
-                    protocol: tokens[2].to_string(),
-                    format: tokens[3..].join(" "),
+                    protocol: token(&tokens, 2, "protocol")?.to_string(),
+                    format: tokens[3.min(tokens.len())..].join(" "),
                     props: lines[1..]
                         .iter()
                         .map(|line| MediaProp::from_str(line))
@@ -471,7 +477,7 @@ impl FromStr for SdpProp {
 }
 
 impl SdpProp {
-    fn to_string(&self, ending: LineEnding) -> String {
+    pub fn to_string(&self, ending: LineEnding) -> String {
         // TODO: Cut down on code copying from SDPProp to MediaProp
         match self {
             SdpProp::Version(v) => format!("v={v}"),
@@ -500,9 +506,10 @@ impl SdpProp {
                 num_addresses,
                 suffix,
             } => {
-                // TTL is required for IPv4
-                let mut address = if *address_type == AddressType::IPv4 || ttl.is_some() {
-                    format!("{address}/{}", ttl.unwrap())
+                // TTL is required for IPv4, but browsers leave it out so only write it when there is one. IPv6 has no TTL,
+                // there the number of addresses directly follows the address
+                let mut address = if let Some(ttl) = ttl {
+                    format!("{address}/{ttl}")
                 } else {
                     address.clone()
                 };
@@ -521,7 +528,15 @@ impl SdpProp {
                 interval,
                 active_duration,
                 start_offsets,
-            } => format!("r={interval} {active_duration} {}", start_offsets.join(" ")),
+            } => format!(
+                "r={}",
+                [interval, active_duration]
+                    .into_iter()
+                    .chain(start_offsets)
+                    .map(|token| token.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(" ")
+            ),
             SdpProp::TimeZone(adjustments) => format!(
                 "z={}",
                 adjustments
@@ -578,15 +593,577 @@ impl SdpProp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SetupRole {
+    Active,
+    Passive,
+    ActPass,
+    HoldConn,
+}
+
+impl FromStr for SetupRole {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(SetupRole::Active),
+            "passive" => Ok(SetupRole::Passive),
+            "actpass" => Ok(SetupRole::ActPass),
+            "holdconn" => Ok(SetupRole::HoldConn),
+            _ => Err(ParseError::UnknownToken(s.to_string())),
+        }
+    }
+}
+
+impl ToString for SetupRole {
+    fn to_string(&self) -> String {
+        match self {
+            SetupRole::Active => "active",
+            SetupRole::Passive => "passive",
+            SetupRole::ActPass => "actpass",
+            SetupRole::HoldConn => "holdconn",
+        }
+        .to_string()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Direction {
+    SendRecv,
+    SendOnly,
+    RecvOnly,
+    Inactive,
+}
+
+impl FromStr for Direction {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sendrecv" => Ok(Direction::SendRecv),
+            "sendonly" => Ok(Direction::SendOnly),
+            "recvonly" => Ok(Direction::RecvOnly),
+            "inactive" => Ok(Direction::Inactive),
+            _ => Err(ParseError::UnknownToken(s.to_string())),
+        }
+    }
+}
+
+impl ToString for Direction {
+    fn to_string(&self) -> String {
+        match self {
+            Direction::SendRecv => "sendrecv",
+            Direction::SendOnly => "sendonly",
+            Direction::RecvOnly => "recvonly",
+            Direction::Inactive => "inactive",
+        }
+        .to_string()
+    }
+}
+
+// https://datatracker.ietf.org/doc/html/rfc8839#section-5.1
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Candidate {
+    pub foundation: String,
+    pub component: u16,
+    pub transport: String,
+    pub priority: u32,
+    pub address: String,
+    pub port: u16,
+    pub typ: String,
+    pub related_address: Option<String>,
+    pub related_port: Option<u16>,
+    /// Extension attributes such as generation or network-cost, in their original order
+    pub extensions: Vec<(String, String)>,
+    /// Number of extensions before raddr and rport, 0 puts them right after the type as RFC 8839 does
+    pub related_position: usize,
+}
+
+impl FromStr for Candidate {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = s.split(' ').collect::<Vec<&str>>();
+        if tokens.len() < 8 || tokens[6] != "typ" {
+            return Err(ParseError::UnknownToken(s.to_string()));
+        }
+
+        let mut candidate = Candidate {
+            foundation: tokens[0].to_string(),
+            component: tokens[1].parse()?,
+            transport: tokens[2].to_string(),
+            priority: tokens[3].parse()?,
+            address: tokens[4].to_string(),
+            port: tokens[5].parse()?,
+            typ: tokens[7].to_string(),
+            related_address: None,
+            related_port: None,
+            extensions: vec![],
+            related_position: 0,
+        };
+
+        for pair in tokens[8..].chunks(2) {
+            if pair.len() != 2 {
+                return Err(ParseError::UnknownToken(s.to_string()));
+            }
+
+            match pair[0] {
+                "raddr" if candidate.related_address.is_none() => {
+                    if candidate.related_port.is_none() {
+                        candidate.related_position = candidate.extensions.len();
+                    }
+                    candidate.related_address = Some(pair[1].to_string());
+                }
+                "rport" if candidate.related_port.is_none() => {
+                    if candidate.related_address.is_none() {
+                        candidate.related_position = candidate.extensions.len();
+                    }
+                    candidate.related_port = Some(pair[1].parse()?);
+                }
+                key => candidate.extensions.push((key.to_string(), pair[1].to_string())),
+            }
+        }
+
+        Ok(candidate)
+    }
+}
+
+impl ToString for Candidate {
+    fn to_string(&self) -> String {
+        let mut out = format!(
+            "{} {} {} {} {} {} typ {}",
+            self.foundation, self.component, self.transport, self.priority, self.address, self.port, self.typ
+        );
+
+        let (before, after) = self.extensions.split_at(self.related_position.min(self.extensions.len()));
+        for (key, value) in before {
+            out = format!("{out} {key} {value}");
+        }
+
+        if let Some(related_address) = &self.related_address {
+            out = format!("{out} raddr {related_address}");
+        }
+
+        if let Some(related_port) = self.related_port {
+            out = format!("{out} rport {related_port}");
+        }
+
+        for (key, value) in after {
+            out = format!("{out} {key} {value}");
+        }
+
+        out
+    }
+}
+
+/// Typed view of the attributes WebRTC cares about, anything else stays in [`SdpAttribute::Generic`]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SdpAttribute {
+    Rtpmap {
+        payload_type: u8,
+        encoding_name: String,
+        clock_rate: u32,
+        /// Number of channels for audio
+        encoding_params: Option<String>,
+    },
+    Fmtp {
+        payload_type: u8,
+        parameters: String,
+    },
+    Candidate(Candidate),
+    Fingerprint {
+        hash_function: String,
+        fingerprint: String,
+    },
+    IceUfrag(String),
+    IcePwd(String),
+    Setup(SetupRole),
+    Mid(String),
+    Msid {
+        stream_id: String,
+        track_id: Option<String>,
+    },
+    Ssrc {
+        ssrc: u32,
+        attribute: String,
+        value: Option<String>,
+    },
+    SsrcGroup {
+        semantics: String,
+        ssrcs: Vec<u32>,
+    },
+    Group {
+        semantics: String,
+        mids: Vec<String>,
+    },
+    Extmap {
+        id: u16,
+        direction: Option<Direction>,
+        uri: String,
+        extension_attributes: Option<String>,
+    },
+    RtcpFb {
+        /// Either a payload type or `*`
+        payload_type: String,
+        feedback_type: String,
+        parameter: Option<String>,
+    },
+    Rid {
+        id: String,
+        direction: String,
+        restrictions: Option<String>,
+    },
+    Simulcast(Vec<(String, String)>),
+    Direction(Direction),
+    Generic {
+        key: String,
+        value: Option<String>,
+    },
+}
+
+impl SdpAttribute {
+    /// Parses an already split attribute, unknown keys end up as [`SdpAttribute::Generic`]
+    pub fn from_parts(key: &str, value: Option<&str>) -> Result<Self, ParseError> {
+        let generic = || SdpAttribute::Generic {
+            key: key.to_string(),
+            value: value.map(|value| value.to_string()),
+        };
+
+        let value = match value {
+            Some(value) => value,
+            None => {
+                return Ok(match Direction::from_str(key) {
+                    Ok(direction) => SdpAttribute::Direction(direction),
+                    Err(_) => generic(),
+                });
+            }
+        };
+
+        let tokens = value.split(' ').collect::<Vec<&str>>();
+        let rest = |from: usize| {
+            if tokens.len() > from {
+                Some(tokens[from..].join(" "))
+            } else {
+                None
+            }
+        };
+        let invalid = || ParseError::UnknownToken(format!("{key}:{value}"));
+
+        Ok(match key {
+            "rtpmap" => {
+                if tokens.len() != 2 {
+                    return Err(invalid());
+                }
+
+                let encoding = tokens[1].split('/').collect::<Vec<&str>>();
+                if encoding.len() < 2 {
+                    return Err(invalid());
+                }
+
+                SdpAttribute::Rtpmap {
+                    payload_type: tokens[0].parse()?,
+                    encoding_name: encoding[0].to_string(),
+                    clock_rate: encoding[1].parse()?,
+                    encoding_params: if encoding.len() > 2 { Some(encoding[2..].join("/")) } else { None },
+                }
+            }
+            "fmtp" => SdpAttribute::Fmtp {
+                payload_type: tokens[0].parse()?,
+                parameters: rest(1).ok_or_else(invalid)?,
+            },
+            "candidate" => SdpAttribute::Candidate(Candidate::from_str(value)?),
+            "fingerprint" => {
+                if tokens.len() != 2 {
+                    return Err(invalid());
+                }
+
+                SdpAttribute::Fingerprint {
+                    hash_function: tokens[0].to_string(),
+                    fingerprint: tokens[1].to_string(),
+                }
+            }
+            "ice-ufrag" => SdpAttribute::IceUfrag(value.to_string()),
+            "ice-pwd" => SdpAttribute::IcePwd(value.to_string()),
+            "setup" => SdpAttribute::Setup(SetupRole::from_str(value)?),
+            "mid" => SdpAttribute::Mid(value.to_string()),
+            "msid" => SdpAttribute::Msid {
+                stream_id: tokens[0].to_string(),
+                track_id: rest(1),
+            },
+            "ssrc" => {
+                let (attribute, attribute_value) = match rest(1) {
+                    Some(attribute) => match attribute.split_once(':') {
+                        Some((attribute, attribute_value)) => (attribute.to_string(), Some(attribute_value.to_string())),
+                        None => (attribute, None),
+                    },
+                    None => return Err(invalid()),
+                };
+
+                SdpAttribute::Ssrc {
+                    ssrc: tokens[0].parse()?,
+                    attribute,
+                    value: attribute_value,
+                }
+            }
+            "ssrc-group" => SdpAttribute::SsrcGroup {
+                semantics: tokens[0].to_string(),
+                ssrcs: tokens[1..]
+                    .iter()
+                    .map(|ssrc| ssrc.parse())
+                    .collect::<Result<Vec<_>, _>>()?,
+            },
+            "group" => SdpAttribute::Group {
+                semantics: tokens[0].to_string(),
+                mids: tokens[1..].iter().map(|mid| mid.to_string()).collect(),
+            },
+            "extmap" => {
+                if tokens.len() < 2 {
+                    return Err(invalid());
+                }
+
+                let (id, direction) = match tokens[0].split_once('/') {
+                    Some((id, direction)) => (id, Some(Direction::from_str(direction)?)),
+                    None => (tokens[0], None),
+                };
+
+                SdpAttribute::Extmap {
+                    id: id.parse()?,
+                    direction,
+                    uri: tokens[1].to_string(),
+                    extension_attributes: rest(2),
+                }
+            }
+            "rtcp-fb" => {
+                if tokens.len() < 2 {
+                    return Err(invalid());
+                }
+
+                SdpAttribute::RtcpFb {
+                    payload_type: tokens[0].to_string(),
+                    feedback_type: tokens[1].to_string(),
+                    parameter: rest(2),
+                }
+            }
+            "rid" => {
+                if tokens.len() < 2 {
+                    return Err(invalid());
+                }
+
+                SdpAttribute::Rid {
+                    id: tokens[0].to_string(),
+                    direction: tokens[1].to_string(),
+                    restrictions: rest(2),
+                }
+            }
+            "simulcast" => {
+                if tokens.len() % 2 != 0 {
+                    return Err(invalid());
+                }
+
+                SdpAttribute::Simulcast(
+                    tokens
+                        .chunks(2)
+                        .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+                        .collect(),
+                )
+            }
+            _ => generic(),
+        })
+    }
+
+    pub fn key(&self) -> String {
+        match self {
+            SdpAttribute::Rtpmap { .. } => "rtpmap".to_string(),
+            SdpAttribute::Fmtp { .. } => "fmtp".to_string(),
+            SdpAttribute::Candidate(_) => "candidate".to_string(),
+            SdpAttribute::Fingerprint { .. } => "fingerprint".to_string(),
+            SdpAttribute::IceUfrag(_) => "ice-ufrag".to_string(),
+            SdpAttribute::IcePwd(_) => "ice-pwd".to_string(),
+            SdpAttribute::Setup(_) => "setup".to_string(),
+            SdpAttribute::Mid(_) => "mid".to_string(),
+            SdpAttribute::Msid { .. } => "msid".to_string(),
+            SdpAttribute::Ssrc { .. } => "ssrc".to_string(),
+            SdpAttribute::SsrcGroup { .. } => "ssrc-group".to_string(),
+            SdpAttribute::Group { .. } => "group".to_string(),
+            SdpAttribute::Extmap { .. } => "extmap".to_string(),
+            SdpAttribute::RtcpFb { .. } => "rtcp-fb".to_string(),
+            SdpAttribute::Rid { .. } => "rid".to_string(),
+            SdpAttribute::Simulcast(_) => "simulcast".to_string(),
+            SdpAttribute::Direction(direction) => direction.to_string(),
+            SdpAttribute::Generic { key, .. } => key.clone(),
+        }
+    }
+
+    pub fn value(&self) -> Option<String> {
+        let join = |first: String, rest: &Option<String>| match rest {
+            Some(rest) => format!("{first} {rest}"),
+            None => first,
+        };
+
+        match self {
+            SdpAttribute::Rtpmap {
+                payload_type,
+                encoding_name,
+                clock_rate,
+                encoding_params,
+            } => Some(match encoding_params {
+                Some(params) => format!("{payload_type} {encoding_name}/{clock_rate}/{params}"),
+                None => format!("{payload_type} {encoding_name}/{clock_rate}"),
+            }),
+            SdpAttribute::Fmtp { payload_type, parameters } => Some(format!("{payload_type} {parameters}")),
+            SdpAttribute::Candidate(candidate) => Some(candidate.to_string()),
+            SdpAttribute::Fingerprint { hash_function, fingerprint } => Some(format!("{hash_function} {fingerprint}")),
+            SdpAttribute::IceUfrag(ufrag) => Some(ufrag.clone()),
+            SdpAttribute::IcePwd(pwd) => Some(pwd.clone()),
+            SdpAttribute::Setup(role) => Some(role.to_string()),
+            SdpAttribute::Mid(mid) => Some(mid.clone()),
+            SdpAttribute::Msid { stream_id, track_id } => Some(join(stream_id.clone(), track_id)),
+            SdpAttribute::Ssrc { ssrc, attribute, value } => Some(match value {
+                Some(value) => format!("{ssrc} {attribute}:{value}"),
+                None => format!("{ssrc} {attribute}"),
+            }),
+            SdpAttribute::SsrcGroup { semantics, ssrcs } => Some(
+                std::iter::once(semantics.clone())
+                    .chain(ssrcs.iter().map(|ssrc| ssrc.to_string()))
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            ),
+            SdpAttribute::Group { semantics, mids } => Some(
+                std::iter::once(semantics.clone())
+                    .chain(mids.iter().cloned())
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            ),
+            SdpAttribute::Extmap {
+                id,
+                direction,
+                uri,
+                extension_attributes,
+            } => {
+                let id = match direction {
+                    Some(direction) => format!("{id}/{}", direction.to_string()),
+                    None => id.to_string(),
+                };
+                Some(join(format!("{id} {uri}"), extension_attributes))
+            }
+            SdpAttribute::RtcpFb {
+                payload_type,
+                feedback_type,
+                parameter,
+            } => Some(join(format!("{payload_type} {feedback_type}"), parameter)),
+            SdpAttribute::Rid { id, direction, restrictions } => Some(join(format!("{id} {direction}"), restrictions)),
+            SdpAttribute::Simulcast(streams) => Some(
+                streams
+                    .iter()
+                    .map(|(direction, list)| format!("{direction} {list}"))
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            ),
+            SdpAttribute::Direction(_) => None,
+            SdpAttribute::Generic { value, .. } => value.clone(),
+        }
+    }
+}
+
+impl FromStr for SdpAttribute {
+    type Err = ParseError;
+
+    /// Parses the content of an attribute line, without the leading `a=`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((key, value)) => SdpAttribute::from_parts(key, Some(value)),
+            None => SdpAttribute::from_parts(s, None),
+        }
+    }
+}
+
+impl ToString for SdpAttribute {
+    fn to_string(&self) -> String {
+        match self.value() {
+            Some(value) => format!("{}:{}", self.key(), value),
+            None => self.key(),
+        }
+    }
+}
+
+impl From<SdpAttribute> for SdpProp {
+    fn from(attribute: SdpAttribute) -> Self {
+        SdpProp::Attribute {
+            key: attribute.key(),
+            value: attribute.value(),
+        }
+    }
+}
+
+impl From<SdpAttribute> for MediaProp {
+    fn from(attribute: SdpAttribute) -> Self {
+        MediaProp::Attribute {
+            key: attribute.key(),
+            value: attribute.value(),
+        }
+    }
+}
+
+/// Typed attribute for an attribute prop, malformed values of known keys are kept as [`SdpAttribute::Generic`]
+fn typed_attribute(key: &str, value: &Option<String>) -> SdpAttribute {
+    SdpAttribute::from_parts(key, value.as_deref()).unwrap_or_else(|_| SdpAttribute::Generic {
+        key: key.to_string(),
+        value: value.clone(),
+    })
+}
+
+impl SdpProp {
+    pub fn attribute(&self) -> Option<SdpAttribute> {
+        match self {
+            SdpProp::Attribute { key, value } => Some(typed_attribute(key, value)),
+            _ => None,
+        }
+    }
+}
+
+impl MediaProp {
+    pub fn attribute(&self) -> Option<SdpAttribute> {
+        match self {
+            MediaProp::Attribute { key, value } => Some(typed_attribute(key, value)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum ParseError {
     /// Unknown attribute key along with its value
     UnknownKey(char, String),
     UnknownToken(String),
+    /// A required field is absent
+    MissingToken(&'static str),
+    /// Line isn't of the form `<type>=<value>` with a single character type
+    MalformedLine(String),
     /// Failed to cast from String to another type
     TypeParseFailed(IntErrorKind),
+    /// Error in a session description along with the (1-based) number and text of the offending line
+    Line {
+        number: usize,
+        text: String,
+        reason: Box<ParseError>,
+    },
 }
 
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnknownKey(key, value) => write!(f, "unknown line type '{key}' with value '{value}'"),
+            ParseError::UnknownToken(token) => write!(f, "unexpected '{token}'"),
+            ParseError::MissingToken(name) => write!(f, "missing {name}"),
+            ParseError::MalformedLine(line) => write!(f, "malformed line '{line}'"),
+            ParseError::TypeParseFailed(kind) => write!(f, "invalid number ({kind:?})"),
+            ParseError::Line { number, text, reason } => write!(f, "line {number} ('{text}'): {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 impl From<ParseIntError> for ParseError {
     fn from(e: ParseIntError) -> Self {
         Self::TypeParseFailed(e.kind().clone())
@@ -598,57 +1175,91 @@ pub struct SDP {
     pub props: Vec<SdpProp>,
 }
 
+/// How [`SDP::parse`] treats lines it doesn't know
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Unknown line types are an error
+    Strict,
+    /// Unknown line types and lines that aren't `<type>=<value>` are skipped
+    Lenient,
+}
+
 impl FromStr for SDP {
     type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // Convert \r\n to \n
-        let s = s.replace("\r\n", "\n");
-
-        // Split string
-        let lines = s
-            .split('\n')
-            .map(|line| line.to_string())
-            .collect::<Vec<String>>();
-
-        // Group media attributes
-        // Find indexes of all media lines
-        let m_indices = lines
-            .iter()
-            .enumerate()
-            .filter(|(_, line)| line.starts_with('m'))
-            .map(|(idx, _)| idx)
-            .collect::<Vec<_>>();
+        SDP::parse(s, ParseMode::Strict)
+    }
+}
 
-        // Combine all media sections into one line per section
-        let lines: Vec<String> =
-            lines
-                .into_iter()
-                .filter(|line| !line.is_empty())
-                .enumerate()
-                .fold(Vec::new(), |mut acc, (idx, line)| {
-                    // If m-line detected or array empty, start a new section
-                    if acc.is_empty()
-                        || m_indices.contains(&idx)
-                        || m_indices.is_empty()
-                        || idx < m_indices[0]
-                    {
-                        acc.push(line);
-                        return acc;
-                    }
+impl SDP {
+    /// Parses a session description, errors carry the number and text of the offending line.
+    /// Never panics, whatever the input.
+    pub fn parse(s: &str, mode: ParseMode) -> Result<Self, ParseError> {
+        let mut props = Vec::new();
+
+        for (idx, line) in s.split('\n').enumerate() {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if line.is_empty() {
+                continue;
+            }
 
-                    // Add to current section
-                    *acc.last_mut().unwrap() = format!("{}\n{line}", acc.last_mut().unwrap());
+            let at_line = |reason: ParseError| ParseError::Line {
+                number: idx + 1,
+                text: line.to_string(),
+                reason: Box::new(reason),
+            };
+
+            let key = match content_from_line(line) {
+                Ok((key, _)) => key,
+                Err(_) if mode == ParseMode::Lenient => continue,
+                Err(e) => return Err(at_line(e)),
+            };
+
+            // Media attributes belong to the last media section
+            let media_props = match props.last_mut() {
+                Some(SdpProp::Media { props, .. }) if key != 'm' => Some(props),
+                _ => None,
+            };
+
+            let result = match media_props {
+                Some(media_props) => MediaProp::from_str(line).map(|prop| media_props.push(prop)),
+                None => SdpProp::from_str(line).map(|prop| props.push(prop)),
+            };
+
+            match result {
+                Ok(()) => {}
+                Err(ParseError::UnknownKey(..)) if mode == ParseMode::Lenient => {}
+                Err(e) => return Err(at_line(e)),
+            }
+        }
 
-                    acc
-                });
+        Ok(Self { props })
+    }
+}
 
-        Ok(Self {
-            props: lines
-                .into_iter()
-                .map(|line| SdpProp::from_str(&line))
-                .collect::<Result<Vec<_>, _>>()?,
-        })
+/// A codec offered in a media section, assembled from its rtpmap, fmtp and rtcp-fb attributes
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Codec {
+    pub payload_type: u8,
+    pub encoding_name: String,
+    pub clock_rate: u32,
+    pub encoding_params: Option<String>,
+    pub fmtp: Option<String>,
+    pub rtcp_fb: Vec<String>,
+}
+
+impl Codec {
+    /// Payload type repaired by this codec if it is an RTX stream
+    pub fn rtx_apt(&self) -> Option<u8> {
+        if !self.encoding_name.eq_ignore_ascii_case("rtx") {
+            return None;
+        }
+
+        self.fmtp
+            .as_deref()
+            .and_then(|fmtp| fmtp.split(';').find_map(|param| param.trim().strip_prefix("apt=")))
+            .and_then(|apt| apt.parse().ok())
     }
 }
 
@@ -660,26 +1271,309 @@ impl SDP {
             .collect::<Vec<String>>()
             .join(ending.string()), ending.string())
     }
+
+    pub fn media_sections(&self) -> impl Iterator<Item = &SdpProp> {
+        self.props
+            .iter()
+            .filter(|prop| matches!(prop, SdpProp::Media { .. }))
+    }
+
+    pub fn media_by_mid(&self, mid: &str) -> Option<&SdpProp> {
+        self.media_sections().find(|media| media.mid().as_deref() == Some(mid))
+    }
+
+    pub fn media_by_mid_mut(&mut self, mid: &str) -> Option<&mut SdpProp> {
+        self.props
+            .iter_mut()
+            .find(|prop| matches!(prop, SdpProp::Media { .. }) && prop.mid().as_deref() == Some(mid))
+    }
+
+    /// Codecs of the media section in the order of preference of its m-line
+    pub fn codecs_for(&self, mid: &str) -> Vec<Codec> {
+        let (format, props) = match self.media_by_mid(mid) {
+            Some(SdpProp::Media { format, props, .. }) => (format, props),
+            _ => return vec![],
+        };
+
+        let attributes = props.iter().filter_map(|prop| prop.attribute()).collect::<Vec<_>>();
+
+        payload_types(format)
+            .into_iter()
+            .filter_map(|pt| {
+                let mut codec = attributes.iter().find_map(|attribute| match attribute {
+                    SdpAttribute::Rtpmap {
+                        payload_type,
+                        encoding_name,
+                        clock_rate,
+                        encoding_params,
+                    } if *payload_type == pt => Some(Codec {
+                        payload_type: pt,
+                        encoding_name: encoding_name.clone(),
+                        clock_rate: *clock_rate,
+                        encoding_params: encoding_params.clone(),
+                        fmtp: None,
+                        rtcp_fb: vec![],
+                    }),
+                    _ => None,
+                })?;
+
+                for attribute in &attributes {
+                    match attribute {
+                        SdpAttribute::Fmtp { payload_type, parameters } if *payload_type == pt => {
+                            codec.fmtp = Some(parameters.clone());
+                        }
+                        SdpAttribute::RtcpFb {
+                            payload_type,
+                            feedback_type,
+                            parameter,
+                        } if *payload_type == pt.to_string() => {
+                            codec.rtcp_fb.push(match parameter {
+                                Some(parameter) => format!("{feedback_type} {parameter}"),
+                                None => feedback_type.clone(),
+                            });
+                        }
+                        _ => (),
+                    }
+                }
+
+                Some(codec)
+            })
+            .collect()
+    }
+
+    /// Removes every codec with the given encoding name (case insensitive) along with the RTX streams repairing it.
+    /// Returns false if the media section doesn't exist, doesn't offer the codec or would be left without codecs,
+    /// reject the whole section instead by setting its port to 0.
+    pub fn remove_codec(&mut self, mid: &str, encoding_name: &str) -> bool {
+        let codecs = self.codecs_for(mid);
+
+        let mut removed = codecs
+            .iter()
+            .filter(|codec| codec.encoding_name.eq_ignore_ascii_case(encoding_name))
+            .map(|codec| codec.payload_type)
+            .collect::<Vec<_>>();
+
+        if removed.is_empty() {
+            return false;
+        }
+
+        removed.extend(
+            codecs
+                .iter()
+                .filter(|codec| codec.rtx_apt().map_or(false, |apt| removed.contains(&apt)))
+                .map(|codec| codec.payload_type)
+                .collect::<Vec<_>>(),
+        );
+
+        if let Some(SdpProp::Media { format, props, .. }) = self.media_by_mid_mut(mid) {
+            let remaining = format
+                .split(' ')
+                .filter(|pt| pt.parse::<u8>().map_or(true, |pt| !removed.contains(&pt)))
+                .collect::<Vec<_>>();
+            // An m-line needs at least one format
+            if remaining.is_empty() {
+                return false;
+            }
+            *format = remaining.join(" ");
+
+            props.retain(|prop| match prop.attribute() {
+                Some(SdpAttribute::Rtpmap { payload_type, .. }) | Some(SdpAttribute::Fmtp { payload_type, .. }) => {
+                    !removed.contains(&payload_type)
+                }
+                Some(SdpAttribute::RtcpFb { payload_type, .. }) => {
+                    payload_type.parse::<u8>().map_or(true, |pt| !removed.contains(&pt))
+                }
+                _ => true,
+            });
+        }
+
+        true
+    }
+
+    /// Moves the codecs with the given encoding names to the front of the m-line, in the given order.
+    /// RTX streams move along with the codec they repair, codecs not mentioned keep their relative order.
+    pub fn reorder_codecs(&mut self, mid: &str, preferred: &[&str]) -> bool {
+        let codecs = self.codecs_for(mid);
+
+        let find = |pt: u8| codecs.iter().find(|codec| codec.payload_type == pt);
+        let rank = |pt: &str| {
+            pt.parse::<u8>()
+                .ok()
+                .and_then(find)
+                .and_then(|codec| codec.rtx_apt().and_then(find).or(Some(codec)))
+                .and_then(|codec| {
+                    preferred
+                        .iter()
+                        .position(|name| codec.encoding_name.eq_ignore_ascii_case(name))
+                })
+                .unwrap_or(preferred.len())
+        };
+
+        match self.media_by_mid_mut(mid) {
+            Some(SdpProp::Media { format, .. }) => {
+                let mut pts = format.split(' ').collect::<Vec<_>>();
+                pts.sort_by_key(|pt| rank(pt));
+                *format = pts.join(" ");
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Replaces the application specific bandwidth limit (`b=AS`) of a media section
+    pub fn set_bandwidth(&mut self, mid: &str, kbps: usize) -> bool {
+        match self.media_by_mid_mut(mid) {
+            Some(SdpProp::Media { props, .. }) => {
+                props.retain(|prop| {
+                    !matches!(prop, MediaProp::Bandwidth { r#type: BandwidthType::ApplicationSpecific, .. })
+                });
+
+                // b= lines come after i= and c= lines
+                let position = props
+                    .iter()
+                    .position(|prop| !matches!(prop, MediaProp::Title(_) | MediaProp::Connection { .. } | MediaProp::Bandwidth { .. }))
+                    .unwrap_or(props.len());
+
+                props.insert(
+                    position,
+                    MediaProp::Bandwidth {
+                        r#type: BandwidthType::ApplicationSpecific,
+                        bandwidth: kbps,
+                    },
+                );
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn set_direction(&mut self, mid: &str, direction: Direction) -> bool {
+        match self.media_by_mid_mut(mid) {
+            Some(SdpProp::Media { props, .. }) => {
+                let new = MediaProp::from(SdpAttribute::Direction(direction));
+
+                match props
+                    .iter()
+                    .position(|prop| matches!(prop.attribute(), Some(SdpAttribute::Direction(_))))
+                {
+                    Some(position) => {
+                        props[position] = new;
+                        props.retain(|prop| {
+                            !matches!(prop.attribute(), Some(SdpAttribute::Direction(d)) if d != direction)
+                        });
+                    }
+                    None => props.push(new),
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes all ICE candidates (and end-of-candidates markers), e.g. before trickling them separately
+    pub fn strip_candidates(&mut self) {
+        let is_candidate = |attribute: Option<SdpAttribute>| match attribute {
+            Some(SdpAttribute::Candidate(_)) => true,
+            Some(SdpAttribute::Generic { key, .. }) => key == "candidate" || key == "end-of-candidates",
+            _ => false,
+        };
+
+        self.props.retain(|prop| !is_candidate(prop.attribute()));
+
+        for prop in self.props.iter_mut() {
+            if let SdpProp::Media { props, .. } = prop {
+                props.retain(|prop| !is_candidate(prop.attribute()));
+            }
+        }
+    }
+
+    pub fn add_candidate(&mut self, mid: &str, candidate: Candidate) -> bool {
+        match self.media_by_mid_mut(mid) {
+            Some(SdpProp::Media { props, .. }) => {
+                let new = MediaProp::from(SdpAttribute::Candidate(candidate));
+
+                // Keep candidates grouped together
+                match props
+                    .iter()
+                    .rposition(|prop| matches!(prop.attribute(), Some(SdpAttribute::Candidate(_))))
+                {
+                    Some(position) => props.insert(position + 1, new),
+                    None => props.push(new),
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// ICE username fragment and password, from the session level or else the first media section that has them
+    pub fn ice_credentials(&self) -> Option<(String, String)> {
+        let find = |attributes: Vec<SdpAttribute>| {
+            let ufrag = attributes.iter().find_map(|attribute| match attribute {
+                SdpAttribute::IceUfrag(ufrag) => Some(ufrag.clone()),
+                _ => None,
+            })?;
+            let pwd = attributes.iter().find_map(|attribute| match attribute {
+                SdpAttribute::IcePwd(pwd) => Some(pwd.clone()),
+                _ => None,
+            })?;
+            Some((ufrag, pwd))
+        };
+
+        find(self.props.iter().filter_map(|prop| prop.attribute()).collect()).or_else(|| {
+            self.media_sections().find_map(|media| match media {
+                SdpProp::Media { props, .. } => find(props.iter().filter_map(|prop| prop.attribute()).collect()),
+                _ => None,
+            })
+        })
+    }
+}
+
+impl SdpProp {
+    /// The `a=mid` of a media section
+    pub fn mid(&self) -> Option<String> {
+        match self {
+            SdpProp::Media { props, .. } => props.iter().find_map(|prop| match prop.attribute() {
+                Some(SdpAttribute::Mid(mid)) => Some(mid),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn payload_types(format: &str) -> Vec<u8> {
+    format.split(' ').filter_map(|pt| pt.parse().ok()).collect()
 }
 
 fn content_from_line(line: &str) -> Result<(char, String), ParseError> {
-    let split = line.split('=').collect::<Vec<&str>>();
-    if split.len() < 2 {
-        return Err(ParseError::UnknownToken(line.to_string()));
-    }
-    Ok((split[0].chars().next().unwrap(), split[1..].join("=")))
-}
-
-fn get_options_from_address_split(address_split: Vec<&str>) -> Result<(&str, Option<usize>, Option<usize>), ParseError> {
-    Ok(match address_split.len() {
-        1 => (address_split[0], None, None),
-        2 => (address_split[0], Some(address_split[1].parse()?), None),
-        3 => (
-            address_split[0],
-            Some(address_split[1].parse()?),
-            Some(address_split[2].parse()?),
-        ),
-        _ => unreachable!(),
+    let (key, value) = line
+        .split_once('=')
+        .ok_or_else(|| ParseError::MalformedLine(line.to_string()))?;
+
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(key), None) => Ok((key, value.to_string())),
+        _ => Err(ParseError::MalformedLine(line.to_string())),
+    }
+}
+
+fn token<'a>(tokens: &[&'a str], index: usize, name: &'static str) -> Result<&'a str, ParseError> {
+    match tokens.get(index) {
+        Some(token) if !token.is_empty() => Ok(token),
+        _ => Err(ParseError::MissingToken(name)),
+    }
+}
+
+/// Splits `address[/ttl][/number of addresses]`, IPv6 multicast has no TTL so its only option is the number of
+/// addresses (RFC 4566 section 5.7)
+fn get_options_from_address_split<'a>(address_type: &AddressType, address_split: Vec<&'a str>) -> Result<(&'a str, Option<usize>, Option<usize>), ParseError> {
+    Ok(match (address_type, &address_split[..]) {
+        (_, [address]) => (*address, None, None),
+        (AddressType::IPv6, [address, num_addresses]) => (*address, None, Some(num_addresses.parse()?)),
+        (AddressType::IPv4, [address, ttl]) => (*address, Some(ttl.parse()?), None),
+        (AddressType::IPv4, [address, ttl, num_addresses]) => (*address, Some(ttl.parse()?), Some(num_addresses.parse()?)),
+        _ => return Err(ParseError::UnknownToken(address_split.join("/"))),
     })
 }
 
@@ -5,29 +5,113 @@ use bytes::Bytes;
 use futures::executor::block_on;
 use gst::prelude::ClockExtManual;
 use gst::traits::ClockExt;
-use gst::{Buffer, FlowError, FlowSuccess, glib, gst_trace as trace, ClockTime};
+use gst::{Buffer, FlowError, FlowSuccess, glib, gst_trace as trace, gst_warning as warning, ClockTime};
 use gst::subclass::ElementMetadata;
 use gst::subclass::prelude::*;
 use gst_base::subclass::prelude::*;
 use once_cell::sync::Lazy;
 use tokio::runtime::Handle;
+use webrtc::rtp::packetizer::{new_packetizer, Packetizer, Payloader};
+use webrtc::rtp::sequence::new_random_sequencer;
+use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
 use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+use webrtc::track::track_local::{TrackLocal, TrackLocalWriter};
 use webrtc_media::Sample;
 
 use crate::webrtcredux::CAT;
 
+/// Same as webrtc-rs uses for its sample tracks
+const RTP_OUTBOUND_MTU: usize = 1200;
+const VIDEO_CLOCK_RATE: u32 = 90000;
+
 #[derive(PartialEq, Eq)]
 pub enum MediaType {
     Video,
     Audio
 }
 
+/// Elementary streams are packetized by webrtc-rs, `application/x-rtp` input is already packetized and goes out as is
+#[derive(Clone)]
+pub enum SenderTrack {
+    Sample(Arc<TrackLocalStaticSample>),
+    Rtp(Arc<TrackLocalStaticRTP>),
+    /// Elementary streams of codecs webrtc-rs has no payloader for
+    Packetized(Arc<TrackLocalStaticRTP>, Box<dyn Payloader + Send + Sync>),
+}
+
+impl SenderTrack {
+    pub fn track_local(&self) -> Arc<dyn TrackLocal + Send + Sync> {
+        match self {
+            SenderTrack::Sample(track) => Arc::clone(track) as Arc<dyn TrackLocal + Send + Sync>,
+            SenderTrack::Rtp(track) | SenderTrack::Packetized(track, _) => Arc::clone(track) as Arc<dyn TrackLocal + Send + Sync>,
+        }
+    }
+}
+
 #[derive(Default)]
 struct State {
-    track: Option<Arc<TrackLocalStaticSample>>,
+    track: Option<SenderTrack>,
     duration: Option<ClockTime>,
     handle: Option<Handle>,
-    media_type: Option<MediaType>
+    media_type: Option<MediaType>,
+    /// Last buffer, written once the next PTS tells how long it lasts
+    held: Option<Buffer>,
+    packetizer: Option<Box<dyn Packetizer + Send + Sync>>
+}
+
+impl State {
+    /// Works out how far the RTP clock should advance after this buffer.
+    ///
+    /// webrtc-rs advances the RTP timestamp by a sample's duration after sending it, so the duration has to be the
+    /// gap to the next buffer, not to the previous one. The PTS delta to `next` is preferred as it is the only thing
+    /// that is correct for variable framerate sources and already covers any gap after the buffer. Without a usable
+    /// next PTS (end of stream, discontinuity, missing timestamps) fall back to the buffer duration, then the caps
+    /// framerate and finally a sane default.
+    fn sample_duration(&self, buffer: &gst::BufferRef, next: Option<&gst::BufferRef>) -> Duration {
+        let delta = next
+            .filter(|next| !next.flags().contains(gst::BufferFlags::DISCONT))
+            .and_then(|next| match (buffer.pts(), next.pts()) {
+                (Some(pts), Some(next_pts)) if next_pts >= pts => Some(next_pts - pts),
+                _ => None,
+            });
+
+        let duration = delta
+            .or_else(|| buffer.duration())
+            .or(self.duration)
+            .unwrap_or_else(|| match self.media_type {
+                Some(MediaType::Audio) => ClockTime::from_mseconds(20),
+                _ => ClockTime::from_mseconds(33),
+            });
+
+        Duration::from_nanos(duration.nseconds())
+    }
+
+    /// Returns the buffers that can be written now with their durations. A buffer with a PTS is held back until the
+    /// next one arrives, one without can't be timed by the next PTS and is written straight away.
+    fn queue(&mut self, buffer: Buffer) -> Vec<(Buffer, Duration)> {
+        let mut ready = Vec::new();
+
+        if let Some(held) = self.held.take() {
+            let duration = self.sample_duration(&held, Some(&buffer));
+            ready.push((held, duration));
+        }
+
+        if buffer.pts().is_some() {
+            self.held = Some(buffer);
+        } else {
+            let duration = self.sample_duration(&buffer, None);
+            ready.push((buffer, duration));
+        }
+
+        ready
+    }
+
+    /// The held back buffer at the end of the stream, there is no next PTS to time it with
+    fn drain(&mut self) -> Option<(Buffer, Duration)> {
+        let held = self.held.take()?;
+        let duration = self.sample_duration(&held, None);
+        Some((held, duration))
+    }
 }
 
 #[derive(Default)]
@@ -36,11 +120,24 @@ pub struct WebRtcReduxSender {
 }
 
 impl WebRtcReduxSender {
-    pub fn add_info(&self, track: Arc<TrackLocalStaticSample>, handle: Handle, media_type: MediaType, duration: Option<ClockTime>) {
+    pub fn add_info(&self, track: SenderTrack, handle: Handle, media_type: MediaType, duration: Option<ClockTime>) {
+        self.state.lock().unwrap().packetizer = match &track {
+            SenderTrack::Packetized(_, payloader) => Some(Box::new(new_packetizer(
+                RTP_OUTBOUND_MTU,
+                // Payload type and SSRC are filled in by the track when writing
+                0,
+                0,
+                payloader.clone(),
+                Box::new(new_random_sequencer()),
+                VIDEO_CLOCK_RATE,
+            ))),
+            _ => None,
+        };
         let _ = self.state.lock().unwrap().track.insert(track);
         let _ = self.state.lock().unwrap().handle.insert(handle);
         let _ = self.state.lock().unwrap().media_type.insert(media_type);
         self.state.lock().unwrap().duration = duration;
+        self.state.lock().unwrap().held = None;
     }
 }
 
@@ -65,9 +162,12 @@ impl ElementImpl for WebRtcReduxSender {
                 .structure(gst::Structure::builder("audio/G722").build())
                 .structure(gst::Structure::builder("audio/x-mulaw").build())
                 .structure(gst::Structure::builder("audio/x-alaw").build())
-                .structure(gst::Structure::builder("video/x-h264").field("stream-format", "byte-stream").field("profile", "baseline").build())
+                .structure(gst::Structure::builder("video/x-h264").field("stream-format", "byte-stream").field("profile", gst::List::new(&[&"constrained-baseline", &"baseline", &"main", &"high"])).build())
                 .structure(gst::Structure::builder("video/x-vp8").build())
                 .structure(gst::Structure::builder("video/x-vp9").build())
+                .structure(gst::Structure::builder("video/x-h265").field("stream-format", "byte-stream").field("alignment", "au").build())
+                .structure(gst::Structure::builder("video/x-av1").field("stream-format", "obu-stream").field("alignment", "tu").build())
+                .structure(gst::Structure::builder("application/x-rtp").build())
                 .build();
             let sink_pad_template = gst::PadTemplate::new(
                 "sink",
@@ -96,26 +196,63 @@ impl ElementImpl for WebRtcReduxSender {
 
 impl BaseSinkImpl for WebRtcReduxSender {
     fn render(&self, element: &Self::Type, buffer: &Buffer) -> Result<FlowSuccess, FlowError> {
-        let sample_duration = if *self.state.lock().unwrap().media_type.as_ref().unwrap() == MediaType::Video {
-            Duration::from_secs(1)
-        } else {
-            Duration::from_millis(buffer.duration().unwrap().mseconds())
-        };
+        let track = self.state.lock().unwrap().track.as_ref().unwrap().clone();
+        if let SenderTrack::Rtp(track) = track {
+            return self.render_rtp(element, buffer, track);
+        }
 
-        // If the clock hasn't been set, set it from the buffer timestamp
+        // If the clock hasn't been set, set it from the buffer duration
         if self.state.lock().unwrap().duration.is_none() {
-            let _ = self.state.lock().unwrap().duration.insert(buffer.duration().unwrap());
-            self.set_clock(element, Some(&format_clock(buffer.duration().unwrap())));
+            if let Some(duration) = buffer.duration() {
+                let _ = self.state.lock().unwrap().duration.insert(duration);
+                self.set_clock(element, Some(&format_clock(duration)));
+            }
+        }
+
+        let ready = self.state.lock().unwrap().queue(buffer.clone());
+        for (buffer, duration) in ready {
+            self.write_buffer(element, &track, &buffer, duration)?;
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    fn event(&self, element: &Self::Type, event: gst::Event) -> bool {
+        match event.view() {
+            gst::EventView::Eos(_) => {
+                let track = self.state.lock().unwrap().track.clone();
+                let held = self.state.lock().unwrap().drain();
+                if let (Some(track), Some((buffer, duration))) = (track, held) {
+                    let _ = self.write_buffer(element, &track, &buffer, duration);
+                }
+            }
+            gst::EventView::FlushStop(_) => {
+                self.state.lock().unwrap().held = None;
+            }
+            _ => (),
         }
 
+        self.parent_event(element, event)
+    }
+}
+
+impl WebRtcReduxSender {
+    fn write_buffer(&self, element: &super::WebRtcReduxSender, track: &SenderTrack, buffer: &Buffer, duration: Duration) -> Result<FlowSuccess, FlowError> {
+        match track {
+            SenderTrack::Sample(track) => self.write_sample(element, buffer, track.clone(), duration),
+            SenderTrack::Packetized(track, _) => self.render_packetized(element, buffer, track.clone(), duration),
+            SenderTrack::Rtp(track) => self.render_rtp(element, buffer, track.clone()),
+        }
+    }
+
+    fn write_sample(&self, element: &super::WebRtcReduxSender, buffer: &Buffer, track: Arc<TrackLocalStaticSample>, sample_duration: Duration) -> Result<FlowSuccess, FlowError> {
         let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
         trace!(CAT, "Rendering {} bytes", map.size());
         let bytes = Bytes::copy_from_slice(map.as_slice());
 
         let handle = self.state.lock().unwrap().handle.as_ref().unwrap().clone();
-        let track = self.state.lock().unwrap().track.as_ref().unwrap().clone();
         let inner = handle.clone();
-        block_on(async move {
+        let res = block_on(async move {
             handle.spawn_blocking(move || {
                 inner.block_on(async move {
                     track.write_sample(&Sample {
@@ -125,7 +262,80 @@ impl BaseSinkImpl for WebRtcReduxSender {
                     }).await
                 })
             }).await
-        }).unwrap().unwrap();
+        }).map_err(|e| {
+            gst::element_error!(element, gst::StreamError::Failed, ["Failed to write sample: {}", e]);
+            gst::FlowError::Error
+        })?;
+
+        // The track is shared by every connection, one that went away must not stop the others
+        if let Err(e) = res {
+            warning!(CAT, obj: element, "Dropping sample: {}", e);
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    /// Buffers are complete RTP packets, webrtc-rs only rewrites SSRC and payload type to what was negotiated
+    fn render_rtp(&self, element: &super::WebRtcReduxSender, buffer: &Buffer, track: Arc<TrackLocalStaticRTP>) -> Result<FlowSuccess, FlowError> {
+        let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+        trace!(CAT, "Writing {} byte RTP packet", map.size());
+        let bytes = Bytes::copy_from_slice(map.as_slice());
+
+        let handle = self.state.lock().unwrap().handle.as_ref().unwrap().clone();
+        let inner = handle.clone();
+        let res = block_on(async move {
+            handle.spawn_blocking(move || {
+                inner.block_on(async move {
+                    track.write(&bytes).await
+                })
+            }).await
+        }).unwrap();
+
+        // A single bad packet from a relayed source shouldn't take the stream down
+        if let Err(e) = res {
+            warning!(CAT, obj: element, "Dropping RTP packet: {}", e);
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    fn render_packetized(&self, element: &super::WebRtcReduxSender, buffer: &Buffer, track: Arc<TrackLocalStaticRTP>, sample_duration: Duration) -> Result<FlowSuccess, FlowError> {
+        let samples = (sample_duration.as_secs_f64() * VIDEO_CLOCK_RATE as f64).round() as u32;
+
+        let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+        trace!(CAT, "Packetizing {} bytes", map.size());
+        let bytes = Bytes::copy_from_slice(map.as_slice());
+
+        // abs-send-time is never enabled on this packetizer, so packetizing never actually waits
+        let packets = {
+            let mut state = self.state.lock().unwrap();
+            let packetizer = state.packetizer.as_mut().unwrap();
+            block_on(packetizer.packetize(&bytes, samples))
+        }.map_err(|e| {
+            gst::element_error!(element, gst::StreamError::Encode, ["Failed to packetize buffer: {}", e]);
+            gst::FlowError::Error
+        })?;
+
+        let handle = self.state.lock().unwrap().handle.as_ref().unwrap().clone();
+        let inner = handle.clone();
+        let errors = block_on(async move {
+            handle.spawn_blocking(move || {
+                inner.block_on(async move {
+                    let mut errors = vec![];
+                    for packet in packets {
+                        if let Err(e) = track.write_rtp(&packet).await {
+                            errors.push(e);
+                        }
+                    }
+                    errors
+                })
+            }).await
+        }).unwrap();
+
+        // Same as for RTP input, losing a packet is better than stopping the stream
+        for e in errors {
+            warning!(CAT, obj: element, "Dropping RTP packet: {}", e);
+        }
 
         Ok(gst::FlowSuccess::Ok)
     }
@@ -147,4 +357,99 @@ fn format_clock(duration: ClockTime) -> gst::Clock {
     let _ = clock.new_periodic_id(clock.internal_time(), duration);
 
     clock
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(pts: Option<u64>, duration: Option<u64>, discont: bool) -> Buffer {
+        let mut buffer = Buffer::new();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(pts.map(ClockTime::from_mseconds));
+            buffer.set_duration(duration.map(ClockTime::from_mseconds));
+            if discont {
+                buffer.set_flags(gst::BufferFlags::DISCONT);
+            }
+        }
+        buffer
+    }
+
+    fn video_state() -> State {
+        State {
+            media_type: Some(MediaType::Video),
+            ..State::default()
+        }
+    }
+
+    fn durations(state: &mut State, buffers: Vec<Buffer>) -> Vec<Duration> {
+        let mut durations = buffers
+            .into_iter()
+            .flat_map(|buffer| state.queue(buffer))
+            .map(|(_, duration)| duration)
+            .collect::<Vec<_>>();
+        durations.extend(state.drain().map(|(_, duration)| duration));
+        durations
+    }
+
+    #[test]
+    fn variable_framerate_uses_the_gap_to_the_next_buffer() {
+        gst::init().unwrap();
+        let mut state = video_state();
+
+        // A screen share that goes idle for a second after the second frame
+        let buffers = vec![buffer(Some(0), None, false), buffer(Some(33), None, false), buffer(Some(1033), None, false), buffer(Some(1066), None, false)];
+        assert_eq!(
+            durations(&mut state, buffers),
+            vec![Duration::from_millis(33), Duration::from_millis(1000), Duration::from_millis(33), Duration::from_millis(33)]
+        );
+    }
+
+    #[test]
+    fn pts_delta_is_preferred_over_the_buffer_duration() {
+        gst::init().unwrap();
+        let mut state = video_state();
+
+        assert!(state.queue(buffer(Some(0), Some(40), false)).is_empty(), "Buffers are held until the next PTS");
+
+        // The encoder stamps a nominal duration, the source went idle after the second frame
+        let buffers = vec![buffer(Some(40), Some(20), false), buffer(Some(500), Some(20), true), buffer(Some(520), Some(20), false)];
+        assert_eq!(
+            durations(&mut state, buffers),
+            vec![Duration::from_millis(40), Duration::from_millis(20), Duration::from_millis(20), Duration::from_millis(20)]
+        );
+    }
+
+    #[test]
+    fn discont_falls_back_to_the_framerate() {
+        gst::init().unwrap();
+        let mut state = video_state();
+        state.duration = Some(ClockTime::from_mseconds(50));
+
+        let buffers = vec![buffer(Some(0), None, false), buffer(Some(5000), None, true), buffer(Some(5050), None, false)];
+        assert_eq!(
+            durations(&mut state, buffers),
+            vec![Duration::from_millis(50), Duration::from_millis(50), Duration::from_millis(50)]
+        );
+    }
+
+    #[test]
+    fn missing_pts_falls_back_to_the_default() {
+        gst::init().unwrap();
+        let mut state = State {
+            media_type: Some(MediaType::Audio),
+            ..State::default()
+        };
+
+        let buffers = vec![buffer(None, None, false), buffer(Some(20), None, false), buffer(None, None, false)];
+        assert_eq!(durations(&mut state, buffers), vec![Duration::from_millis(20); 3]);
+
+        let mut state = video_state();
+        assert_eq!(
+            durations(&mut state, vec![buffer(Some(100), None, false), buffer(Some(50), None, false)]),
+            vec![Duration::from_millis(33); 2],
+            "Going back in time is no usable delta"
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures::{SinkExt, Stream, StreamExt};
+use gst::glib::{self, SignalHandlerId};
+use gst::prelude::*;
+use gst::{gst_debug as debug, gst_warning as warning, ErrorMessage};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+
+use crate::webrtcredux::CAT;
+
+use super::imp::parse_sdp;
+use super::sdp::LineEnding;
+use super::WebRtcRedux;
+
+/// Messages relayed between the peers once a session is set up, same as the gst-examples webrtc demos use
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerMessage {
+    Sdp {
+        r#type: String,
+        sdp: String,
+    },
+    Ice {
+        candidate: String,
+        #[serde(rename = "sdpMLineIndex")]
+        sdp_mline_index: u32,
+    },
+}
+
+/// Sending side of the connection to the signalling server
+struct Outgoing {
+    tx: mpsc::UnboundedSender<Message>,
+    /// Candidates gathered while a local description is applied but not sent yet, the remote peer can't use them before
+    held: Mutex<Option<Vec<PeerMessage>>>,
+}
+
+impl Outgoing {
+    fn send(&self, message: &PeerMessage) {
+        // Fails only once the connection is closed, nothing left to tell the peer then
+        let _ = self.tx.send(Message::Text(serde_json::to_string(message).unwrap()));
+    }
+
+    fn hold_candidates(&self) {
+        let mut held = self.held.lock().unwrap();
+        if held.is_none() {
+            let _ = held.insert(Vec::new());
+        }
+    }
+
+    fn send_candidate(&self, candidate: PeerMessage) {
+        if let Some(held) = &mut *self.held.lock().unwrap() {
+            held.push(candidate);
+            return;
+        }
+
+        self.send(&candidate);
+    }
+
+    fn send_description(&self, description: PeerMessage) {
+        self.send(&description);
+        for candidate in self.held.lock().unwrap().take().unwrap_or_default() {
+            self.send(&candidate);
+        }
+    }
+
+    fn close(&self) {
+        let _ = self.tx.send(Message::Close(None));
+    }
+}
+
+/// Client for the signalling protocol of GStreamer's webrtc examples (gst-examples/webrtc/signalling).
+///
+/// Registers with `HELLO <our id>`, optionally calls a peer with `SESSION <peer id>` and then exchanges
+/// `{"sdp": ...}` and `{"ice": ...}` JSON messages, driving the element's negotiation and ICE.
+pub struct SignallingClient {
+    element: glib::WeakRef<WebRtcRedux>,
+    outgoing: Arc<Outgoing>,
+    handlers: Mutex<Vec<SignalHandlerId>>,
+    task: Option<JoinHandle<Result<(), ErrorMessage>>>,
+}
+
+impl SignallingClient {
+    /// Connects to the signalling server at `url` as `our_id`.
+    ///
+    /// With a `peer_id` the remote peer is called and the element sends the offer, otherwise the element waits
+    /// for a call and answers the remote offer.
+    pub async fn connect(element: &WebRtcRedux, url: &str, our_id: &str, peer_id: Option<&str>) -> Result<Self, ErrorMessage> {
+        // Everything runs on the element's runtime, the caller may not be in one
+        let handle = element.runtime_handle();
+
+        let (url, our_id, session_peer) = (url.to_string(), our_id.to_string(), peer_id.map(str::to_string));
+        let (mut sink, stream) = handle
+            .spawn(async move {
+                let (socket, _) = tokio_tungstenite::connect_async(&url).await.map_err(|e| {
+                    gst::error_msg!(
+                        gst::ResourceError::OpenReadWrite,
+                        [&format!("Failed to connect to signalling server {}: {:?}", url, e)]
+                    )
+                })?;
+                let (mut sink, mut stream) = socket.split();
+
+                sink.send(Message::Text(format!("HELLO {}", our_id))).await.map_err(send_error)?;
+                expect_reply(&mut stream, "HELLO").await?;
+
+                if let Some(peer_id) = session_peer {
+                    sink.send(Message::Text(format!("SESSION {}", peer_id))).await.map_err(send_error)?;
+                    expect_reply(&mut stream, "SESSION_OK").await?;
+                }
+
+                Ok::<_, ErrorMessage>((sink, stream))
+            })
+            .await
+            .map_err(task_error)??;
+
+        let (tx, mut outgoing_rx) = mpsc::unbounded_channel::<Message>();
+        handle.spawn(async move {
+            while let Some(message) = outgoing_rx.recv().await {
+                let close = matches!(message, Message::Close(_));
+                if sink.send(message).await.is_err() || close {
+                    break;
+                }
+            }
+        });
+
+        let outgoing = Arc::new(Outgoing {
+            tx,
+            held: Mutex::new(None),
+        });
+
+        // The answering side only renegotiates once the first offer went through
+        let negotiated = Arc::new(AtomicBool::new(peer_id.is_some()));
+
+        let candidate_outgoing = outgoing.clone();
+        let candidate_handler = element.connect("on-ice-candidate", false, move |values| {
+            let sdp_mline_index = values[1].get::<u32>().unwrap();
+            let candidate = values[2].get::<String>().unwrap();
+            candidate_outgoing.send_candidate(PeerMessage::Ice { candidate, sdp_mline_index });
+            None
+        });
+
+        let element_weak = element.downgrade();
+        let negotiation_handle = handle.clone();
+        let negotiation_outgoing = outgoing.clone();
+        let negotiation_negotiated = negotiated.clone();
+        let negotiation_handler = element.connect("on-negotiation-needed", false, move |_| {
+            if !negotiation_negotiated.load(Ordering::SeqCst) {
+                return None;
+            }
+
+            if let Some(element) = element_weak.upgrade() {
+                let outgoing = negotiation_outgoing.clone();
+                negotiation_handle.spawn(async move {
+                    if let Err(e) = send_offer(&element, &outgoing).await {
+                        warning!(CAT, obj: &element, "Failed to renegotiate: {:?}", e);
+                    }
+                });
+            }
+            None
+        });
+
+        let client = SignallingClient {
+            element: element.downgrade(),
+            outgoing: outgoing.clone(),
+            handlers: Mutex::new(vec![candidate_handler, negotiation_handler]),
+            task: Some(handle.spawn(receive(element.clone(), stream, outgoing.clone(), negotiated))),
+        };
+
+        if peer_id.is_some() {
+            let element = element.clone();
+            handle.spawn(async move { send_offer(&element, &outgoing).await }).await.map_err(task_error)??;
+        }
+
+        Ok(client)
+    }
+
+    /// Waits until the signalling server or the remote peer ends the session
+    pub async fn wait(mut self) -> Result<(), ErrorMessage> {
+        match self.task.take() {
+            Some(task) => task.await.unwrap_or(Ok(())),
+            None => Ok(()),
+        }
+    }
+
+    /// Stops driving the element and closes the connection to the signalling server
+    pub fn close(&self) {
+        if let Some(element) = self.element.upgrade() {
+            for handler in self.handlers.lock().unwrap().drain(..) {
+                element.disconnect(handler);
+            }
+        }
+
+        self.outgoing.close();
+    }
+}
+
+impl Drop for SignallingClient {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+async fn receive<S>(
+    element: WebRtcRedux,
+    mut stream: S,
+    outgoing: Arc<Outgoing>,
+    negotiated: Arc<AtomicBool>,
+) -> Result<(), ErrorMessage>
+where
+    S: Stream<Item = Result<Message, WsError>> + Unpin,
+{
+    while let Some(message) = stream.next().await {
+        let text = match message {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Close(_)) => break,
+            Ok(_) => continue,
+            Err(e) => return Err(gst::error_msg!(
+                gst::ResourceError::Read,
+                [&format!("Signalling connection failed: {:?}", e)]
+            )),
+        };
+
+        if let Some(error) = text.strip_prefix("ERROR") {
+            return Err(gst::error_msg!(
+                gst::ResourceError::Failed,
+                [&format!("Signalling server error: {}", error.trim())]
+            ));
+        }
+
+        match serde_json::from_str::<PeerMessage>(&text) {
+            Ok(PeerMessage::Sdp { r#type, sdp }) => {
+                let sdp = parse_sdp(&sdp)?;
+                match r#type.as_str() {
+                    "offer" => {
+                        element.set_remote_description(&sdp, RTCSdpType::Offer).await?;
+                        let answer = element.create_answer(None).await?;
+                        outgoing.hold_candidates();
+                        element.set_local_description(&answer, RTCSdpType::Answer).await?;
+                        outgoing.send_description(PeerMessage::Sdp {
+                            r#type: "answer".to_string(),
+                            sdp: answer.to_string(LineEnding::CRLF),
+                        });
+                        negotiated.store(true, Ordering::SeqCst);
+                    }
+                    "answer" => element.set_remote_description(&sdp, RTCSdpType::Answer).await?,
+                    other => warning!(CAT, obj: &element, "Ignoring session description of type {}", other),
+                }
+            }
+            Ok(PeerMessage::Ice { candidate, sdp_mline_index }) => {
+                let candidate = RTCIceCandidateInit {
+                    candidate,
+                    sdp_mline_index: Some(sdp_mline_index as u16),
+                    ..Default::default()
+                };
+                if let Err(e) = element.add_ice_candidate(candidate).await {
+                    warning!(CAT, obj: &element, "Failed to add remote ICE candidate: {:?}", e);
+                }
+            }
+            Err(_) => warning!(CAT, obj: &element, "Ignoring unknown signalling message: {}", text),
+        }
+    }
+
+    debug!(CAT, obj: &element, "Signalling session ended");
+    Ok(())
+}
+
+async fn send_offer(element: &WebRtcRedux, outgoing: &Outgoing) -> Result<(), ErrorMessage> {
+    let offer = element.create_offer(None).await?;
+    outgoing.hold_candidates();
+    element.set_local_description(&offer, RTCSdpType::Offer).await?;
+    outgoing.send_description(PeerMessage::Sdp {
+        r#type: "offer".to_string(),
+        sdp: offer.to_string(LineEnding::CRLF),
+    });
+
+    Ok(())
+}
+
+async fn expect_reply<S>(stream: &mut S, expected: &str) -> Result<(), ErrorMessage>
+where
+    S: Stream<Item = Result<Message, WsError>> + Unpin,
+{
+    match stream.next().await {
+        Some(Ok(Message::Text(text))) if text == expected => Ok(()),
+        Some(Ok(message)) => Err(gst::error_msg!(
+            gst::ResourceError::Failed,
+            [&format!("Expected {} from signalling server, got {:?}", expected, message)]
+        )),
+        Some(Err(e)) => Err(gst::error_msg!(
+            gst::ResourceError::Read,
+            [&format!("Signalling connection failed: {:?}", e)]
+        )),
+        None => Err(gst::error_msg!(
+            gst::ResourceError::Read,
+            ["Signalling server closed the connection"]
+        )),
+    }
+}
+
+fn task_error(e: tokio::task::JoinError) -> ErrorMessage {
+    gst::error_msg!(
+        gst::ResourceError::Failed,
+        [&format!("Signalling task failed: {:?}", e)]
+    )
+}
+
+fn send_error(e: WsError) -> ErrorMessage {
+    gst::error_msg!(
+        gst::ResourceError::Write,
+        [&format!("Failed to send to signalling server: {:?}", e)]
+    )
+}
@@ -0,0 +1,266 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtcp::packet::Packet;
+use webrtc::rtcp::receiver_report::ReceiverReport;
+use webrtc::rtcp::sender_report::SenderReport;
+use webrtc::rtp_transceiver::rtp_sender::RTCRtpSender;
+use webrtc::stats::StatsReportType;
+
+/// Seconds between the NTP (1900) and Unix (1970) epochs
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+const DEFAULT_CLOCK_RATE: u32 = 90000;
+
+/// Statistics of an outgoing track, loss, jitter and round trip time come from the remote receiver reports
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackStats {
+    /// Name of the sink pad feeding the track
+    pub pad: String,
+    pub ssrc: u32,
+    pub kind: String,
+    pub mid: String,
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub packets_lost: u32,
+    /// Fraction of packets lost since the previous receiver report, 0 to 1
+    pub fraction_lost: f64,
+    /// Interarrival jitter in seconds
+    pub jitter: f64,
+    /// Round trip time in seconds, once the remote peer reported on one of our sender reports
+    pub round_trip_time: Option<f64>,
+}
+
+/// Statistics of the candidate pair in use
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CandidatePairStats {
+    pub local_candidate_id: String,
+    pub remote_candidate_id: String,
+    pub state: String,
+    pub nominated: bool,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Seconds
+    pub current_round_trip_time: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectionStats {
+    pub tracks: Vec<TrackStats>,
+    pub candidate_pair: Option<CandidatePairStats>,
+    /// Estimated available outgoing bitrate in bit/s
+    pub available_outgoing_bitrate: f64,
+}
+
+/// Statistics of the element's peer connection and its broadcast peers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub connection: ConnectionStats,
+    pub peers: BTreeMap<String, ConnectionStats>,
+}
+
+/// Latest receiver report block about one of our SSRCs
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ReceptionStats {
+    fraction_lost: u8,
+    total_lost: u32,
+    jitter: u32,
+    round_trip_time: Option<f64>,
+}
+
+/// Records the report blocks of receiver and sender reports, by the SSRC they describe
+pub(crate) fn on_rtcp(reception: &mut HashMap<u32, ReceptionStats>, packets: &[Box<dyn Packet + Send + Sync>]) {
+    let now = ntp_middle_now();
+
+    for packet in packets {
+        let packet = packet.as_any();
+        let reports = if let Some(rr) = packet.downcast_ref::<ReceiverReport>() {
+            &rr.reports
+        } else if let Some(sr) = packet.downcast_ref::<SenderReport>() {
+            &sr.reports
+        } else {
+            continue;
+        };
+
+        for report in reports {
+            // https://datatracker.ietf.org/doc/html/rfc3550#section-6.4.1, in units of 1/65536 seconds
+            let round_trip_time = if report.last_sender_report != 0 {
+                Some(now.wrapping_sub(report.last_sender_report).wrapping_sub(report.delay) as f64 / 65536.0)
+            } else {
+                None
+            };
+
+            reception.insert(report.ssrc, ReceptionStats {
+                fraction_lost: report.fraction_lost,
+                total_lost: report.total_lost,
+                jitter: report.jitter,
+                round_trip_time,
+            });
+        }
+    }
+}
+
+/// The middle 32 bits of the current NTP timestamp, as used by the LSR and DLSR report fields
+fn ntp_middle_now() -> u32 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = now.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = ((now.subsec_nanos() as u64) << 32) / 1_000_000_000;
+
+    (((seconds & 0xffff) << 16) | (fraction >> 16)) as u32
+}
+
+pub(crate) async fn collect(
+    peer_connection: &RTCPeerConnection,
+    senders: &[(String, Arc<RTCRtpSender>)],
+    reception: &HashMap<u32, ReceptionStats>,
+    target_bitrate: u32,
+) -> ConnectionStats {
+    let report = peer_connection.get_stats().await;
+
+    let mut outbound = HashMap::new();
+    let mut candidate_pairs = Vec::new();
+    for stats in report.reports.values() {
+        match stats {
+            StatsReportType::OutboundRTP(stats) => {
+                outbound.insert(stats.ssrc, stats);
+            }
+            StatsReportType::CandidatePair(stats) => candidate_pairs.push(stats),
+            _ => {}
+        }
+    }
+
+    let mut tracks = Vec::new();
+    for (pad, sender) in senders {
+        let parameters = sender.get_parameters().await;
+        let ssrc = match parameters.encodings.first() {
+            Some(encoding) => encoding.ssrc,
+            None => continue,
+        };
+        let clock_rate = parameters
+            .rtp_parameters
+            .codecs
+            .first()
+            .map(|codec| codec.capability.clock_rate)
+            .filter(|clock_rate| *clock_rate > 0)
+            .unwrap_or(DEFAULT_CLOCK_RATE);
+
+        let mut track = TrackStats {
+            pad: pad.clone(),
+            ssrc,
+            kind: pad.split('_').next().unwrap_or_default().to_string(),
+            ..Default::default()
+        };
+
+        if let Some(stats) = outbound.get(&ssrc) {
+            track.mid = stats.mid.clone();
+            track.packets_sent = stats.packets_sent;
+            track.bytes_sent = stats.bytes_sent;
+        }
+
+        if let Some(stats) = reception.get(&ssrc) {
+            track.packets_lost = stats.total_lost;
+            track.fraction_lost = stats.fraction_lost as f64 / 256.0;
+            track.jitter = stats.jitter as f64 / clock_rate as f64;
+            track.round_trip_time = stats.round_trip_time;
+        }
+
+        tracks.push(track);
+    }
+    tracks.sort_by(|a, b| a.pad.cmp(&b.pad));
+
+    // The nominated pair carries the media, before nomination the busiest one is the best guess
+    let pair = candidate_pairs
+        .iter()
+        .find(|pair| pair.nominated)
+        .or_else(|| candidate_pairs.iter().max_by_key(|pair| pair.bytes_sent));
+
+    // webrtc-rs doesn't estimate the outgoing bitrate itself, the element's congestion control does
+    let available_outgoing_bitrate = pair
+        .map(|pair| pair.available_outgoing_bitrate)
+        .filter(|bitrate| *bitrate > 0.0)
+        .unwrap_or(target_bitrate as f64);
+
+    ConnectionStats {
+        tracks,
+        candidate_pair: pair.map(|pair| CandidatePairStats {
+            local_candidate_id: pair.local_candidate_id.clone(),
+            remote_candidate_id: pair.remote_candidate_id.clone(),
+            state: pair.state.to_string(),
+            nominated: pair.nominated,
+            bytes_sent: pair.bytes_sent,
+            bytes_received: pair.bytes_received,
+            current_round_trip_time: pair.current_round_trip_time,
+        }),
+        available_outgoing_bitrate,
+    }
+}
+
+impl TrackStats {
+    pub fn to_structure(&self) -> gst::Structure {
+        let mut structure = gst::Structure::builder("track-stats")
+            .field("ssrc", self.ssrc)
+            .field("kind", &self.kind)
+            .field("mid", &self.mid)
+            .field("packets-sent", self.packets_sent)
+            .field("bytes-sent", self.bytes_sent)
+            .field("packets-lost", self.packets_lost)
+            .field("fraction-lost", self.fraction_lost)
+            .field("jitter", self.jitter)
+            .build();
+
+        if let Some(round_trip_time) = self.round_trip_time {
+            structure.set("round-trip-time", round_trip_time);
+        }
+
+        structure
+    }
+}
+
+impl CandidatePairStats {
+    pub fn to_structure(&self) -> gst::Structure {
+        gst::Structure::builder("candidate-pair-stats")
+            .field("local-candidate-id", &self.local_candidate_id)
+            .field("remote-candidate-id", &self.remote_candidate_id)
+            .field("state", &self.state)
+            .field("nominated", self.nominated)
+            .field("bytes-sent", self.bytes_sent)
+            .field("bytes-received", self.bytes_received)
+            .field("current-round-trip-time", self.current_round_trip_time)
+            .build()
+    }
+}
+
+impl ConnectionStats {
+    /// Every track is a field named after its pad
+    pub fn to_structure(&self, name: &str) -> gst::Structure {
+        let mut structure = gst::Structure::builder(name)
+            .field("available-outgoing-bitrate", self.available_outgoing_bitrate)
+            .build();
+
+        for track in &self.tracks {
+            structure.set(track.pad.as_str(), track.to_structure());
+        }
+
+        if let Some(pair) = &self.candidate_pair {
+            structure.set("candidate-pair", pair.to_structure());
+        }
+
+        structure
+    }
+}
+
+impl Stats {
+    /// Layout of the `get-stats` signal reply, the broadcast peers are in the "peers" field, by id
+    pub fn to_structure(&self) -> gst::Structure {
+        let mut structure = self.connection.to_structure("application/x-webrtcredux-stats");
+
+        let mut peers = gst::Structure::new_empty("peer-stats");
+        for (id, stats) in &self.peers {
+            peers.set(id.as_str(), stats.to_structure("connection-stats"));
+        }
+        structure.set("peers", peers);
+
+        structure
+    }
+}
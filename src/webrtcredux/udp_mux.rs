@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::{Arc, Mutex};
+
+use gst::{gst_debug as debug, ErrorMessage};
+use once_cell::sync::Lazy;
+use tokio::runtime::Handle;
+use webrtc::ice::udp_mux::{UDPMux, UDPMuxDefault, UDPMuxParams};
+
+use crate::webrtcredux::CAT;
+
+struct SharedMux {
+    mux: Arc<UDPMuxDefault>,
+    users: usize,
+}
+
+/// Muxes shared by every element of the process, by port
+static MUXES: Lazy<Mutex<HashMap<u16, SharedMux>>> = Lazy::new(Default::default);
+
+/// Keeps the shared UDP mux on a port open, the socket is closed when the last element using it lets go
+pub(crate) struct UdpMuxHandle {
+    port: u16,
+    mux: Arc<UDPMuxDefault>,
+    handle: Handle,
+}
+
+impl UdpMuxHandle {
+    /// Joins the mux listening on `port`, or binds it if no element uses that port yet.
+    /// The mux runs on `handle` until it is closed, so it should outlive the elements.
+    pub(crate) fn acquire(port: u16, handle: &Handle) -> Result<Self, ErrorMessage> {
+        let mut muxes = MUXES.lock().unwrap();
+
+        if let Some(shared) = muxes.get_mut(&port) {
+            shared.users += 1;
+            return Ok(UdpMuxHandle {
+                port,
+                mux: shared.mux.clone(),
+                handle: handle.clone(),
+            });
+        }
+
+        let bind_error = |e: std::io::Error| {
+            gst::error_msg!(
+                gst::ResourceError::OpenReadWrite,
+                [&format!("Failed to bind UDP mux to port {}: {:?}", port, e)]
+            )
+        };
+
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).map_err(bind_error)?;
+        socket.set_nonblocking(true).map_err(bind_error)?;
+
+        // The socket and the mux's read loop have to be registered with the runtime they run on
+        let mux = {
+            let _guard = handle.enter();
+            let socket = tokio::net::UdpSocket::from_std(socket).map_err(bind_error)?;
+            UDPMuxDefault::new(UDPMuxParams::new(socket))
+        };
+
+        debug!(CAT, "Listening for muxed ICE traffic on UDP port {}", port);
+        muxes.insert(port, SharedMux { mux: mux.clone(), users: 1 });
+
+        Ok(UdpMuxHandle {
+            port,
+            mux,
+            handle: handle.clone(),
+        })
+    }
+
+    pub(crate) fn mux(&self) -> Arc<UDPMuxDefault> {
+        self.mux.clone()
+    }
+}
+
+impl Drop for UdpMuxHandle {
+    fn drop(&mut self) {
+        let mut muxes = MUXES.lock().unwrap();
+
+        let unused = match muxes.get_mut(&self.port) {
+            Some(shared) => {
+                shared.users -= 1;
+                shared.users == 0
+            }
+            None => false,
+        };
+
+        if unused {
+            muxes.remove(&self.port);
+            debug!(CAT, "Closing UDP mux on port {}", self.port);
+
+            let mux = self.mux.clone();
+            self.handle.spawn(async move {
+                let _ = mux.close().await;
+            });
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use gst::glib;
+use gst::prelude::*;
+use gst::{gst_debug as debug, gst_warning as warning, ErrorMessage};
+use hyper::header::{
+    ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+    ACCESS_CONTROL_EXPOSE_HEADERS, CONTENT_TYPE, LOCATION,
+};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+
+use crate::webrtcredux::CAT;
+
+use super::sdp::{LineEnding, SDP};
+use super::WebRtcRedux;
+
+const SDP_CONTENT_TYPE: &str = "application/sdp";
+/// Only peers created by the server can be deleted through it
+const SESSION_PREFIX: &str = "whep-";
+
+/// Embedded WebRTC-HTTP Egress Protocol (WHEP) endpoint
+///
+/// Every POSTed offer becomes a broadcast peer of the element, see `WebRtcRedux::add_peer`.
+/// https://datatracker.ietf.org/doc/html/draft-murillo-whep
+pub struct WhepServer {
+    local_addr: SocketAddr,
+    /// Dropping it shuts the server down
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl WhepServer {
+    pub fn start(element: &WebRtcRedux, addr: SocketAddr, handle: &Handle) -> Result<Self, ErrorMessage> {
+        let element = element.downgrade();
+        let next_session = Arc::new(AtomicUsize::new(0));
+        let service_handle = handle.clone();
+        let make_service = make_service_fn(move |_| {
+            let element = element.clone();
+            let next_session = next_session.clone();
+            let handle = service_handle.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    handle_request(element.clone(), next_session.clone(), handle.clone(), request)
+                }))
+            }
+        });
+
+        // The listener has to be registered with the runtime the server runs on
+        let server = {
+            let _guard = handle.enter();
+            Server::try_bind(&addr)
+                .map_err(|e| {
+                    gst::error_msg!(
+                        gst::ResourceError::OpenReadWrite,
+                        [&format!("Failed to bind WHEP endpoint to {}: {:?}", addr, e)]
+                    )
+                })?
+                .serve(make_service)
+        };
+
+        let local_addr = server.local_addr();
+        let (shutdown, on_shutdown) = oneshot::channel::<()>();
+        handle.spawn(server.with_graceful_shutdown(async {
+            let _ = on_shutdown.await;
+        }));
+
+        Ok(WhepServer {
+            local_addr,
+            _shutdown: shutdown,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+async fn handle_request(
+    element: glib::WeakRef<WebRtcRedux>,
+    next_session: Arc<AtomicUsize>,
+    handle: Handle,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let element = match element.upgrade() {
+        Some(element) => element,
+        None => return Ok(status(StatusCode::SERVICE_UNAVAILABLE)),
+    };
+
+    let mut response = match *request.method() {
+        // CORS preflight, browsers send one because of the SDP content type
+        Method::OPTIONS => status(StatusCode::NO_CONTENT),
+        Method::POST => create_session(&element, &next_session, &handle, request).await,
+        Method::DELETE => delete_session(&element, request.uri().path()).await,
+        _ => status(StatusCode::METHOD_NOT_ALLOWED),
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, "*".parse().unwrap());
+    headers.insert(ACCESS_CONTROL_ALLOW_METHODS, "POST, DELETE, OPTIONS".parse().unwrap());
+    headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, Authorization".parse().unwrap());
+    headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, "Location".parse().unwrap());
+
+    Ok(response)
+}
+
+async fn create_session(element: &WebRtcRedux, next_session: &AtomicUsize, handle: &Handle, request: Request<Body>) -> Response<Body> {
+    let path = request.uri().path().trim_end_matches('/').to_string();
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(_) => return status(StatusCode::BAD_REQUEST),
+    };
+
+    let offer = match std::str::from_utf8(&body).ok().and_then(|offer| SDP::from_str(offer).ok()) {
+        Some(offer) => offer,
+        None => return status(StatusCode::BAD_REQUEST),
+    };
+
+    let id = format!("{}{}", SESSION_PREFIX, next_session.fetch_add(1, Ordering::Relaxed));
+    match answer(element, &id, &offer, handle).await {
+        Ok(answer) => {
+            debug!(CAT, obj: element, "Created WHEP session {}", id);
+            Response::builder()
+                .status(StatusCode::CREATED)
+                .header(CONTENT_TYPE, SDP_CONTENT_TYPE)
+                .header(LOCATION, format!("{}/{}", path, id))
+                .body(Body::from(answer.to_string(LineEnding::CRLF)))
+                .unwrap()
+        }
+        Err(e) => {
+            warning!(CAT, obj: element, "Failed to create WHEP session: {:?}", e);
+            let _ = element.remove_peer(&id).await;
+            status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn answer(element: &WebRtcRedux, id: &str, offer: &SDP, handle: &Handle) -> Result<SDP, ErrorMessage> {
+    let peer = element.add_peer(id).await?;
+
+    // Viewers that go away without a DELETE are cleaned up once the connection fails.
+    // Removing closes the connection, which waits for this very handler, so it can't be awaited here.
+    let element_weak = element.downgrade();
+    let session = id.to_string();
+    let handle = handle.clone();
+    peer.on_peer_connection_state_change(Box::new(move |state| {
+        if let (Some(element), RTCPeerConnectionState::Failed) = (element_weak.upgrade(), state) {
+            let session = session.clone();
+            handle.spawn(async move {
+                if element.remove_peer(&session).await.is_ok() {
+                    debug!(CAT, obj: &element, "Removed failed WHEP session {}", session);
+                }
+            });
+        }
+        Box::pin(async {})
+    })).await;
+
+    peer.set_remote_description(offer, RTCSdpType::Offer).await?;
+    let answer = peer.create_answer(None).await?;
+
+    // No trickle ICE, the answer carries all candidates
+    let mut gathering_complete = peer.gathering_complete_promise().await;
+    peer.set_local_description(&answer, RTCSdpType::Answer).await?;
+    let _ = gathering_complete.recv().await;
+
+    Ok(peer.local_description().await?.unwrap_or(answer))
+}
+
+async fn delete_session(element: &WebRtcRedux, path: &str) -> Response<Body> {
+    let id = path.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+    if !id.starts_with(SESSION_PREFIX) {
+        return status(StatusCode::NOT_FOUND);
+    }
+
+    match element.remove_peer(id).await {
+        Ok(()) => {
+            debug!(CAT, obj: element, "Deleted WHEP session {}", id);
+            status(StatusCode::OK)
+        }
+        Err(_) => status(StatusCode::NOT_FOUND),
+    }
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    Response::builder().status(status).body(Body::empty()).unwrap()
+}
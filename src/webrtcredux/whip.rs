@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use gst::glib::SignalHandlerId;
+use gst::prelude::*;
+use gst::{gst_debug as debug, gst_warning as warning, ErrorMessage};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MATCH, LOCATION};
+use reqwest::{Client, RequestBuilder, StatusCode, Url};
+use tokio::runtime::Handle;
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+
+use crate::webrtcredux::CAT;
+
+use super::imp::parse_sdp;
+use super::sdp::{LineEnding, SdpProp};
+use super::WebRtcRedux;
+
+const SDP_CONTENT_TYPE: &str = "application/sdp";
+const TRICKLE_CONTENT_TYPE: &str = "application/trickle-ice-sdpfrag";
+
+/// What is needed to trickle candidates to an established WHIP resource
+#[derive(Debug, Clone)]
+struct WhipSession {
+    resource: Url,
+    etag: Option<String>,
+    ice_ufrag: String,
+    ice_pwd: String,
+    /// Mid and media line of every media section of the offer, by m-line index
+    media: Vec<(String, String)>,
+}
+
+#[derive(Default)]
+struct Trickle {
+    session: Option<WhipSession>,
+    /// Candidates gathered before the answer arrived, by m-line index
+    pending: Vec<(u32, String)>,
+}
+
+/// Client for the WebRTC-HTTP Ingestion Protocol (WHIP)
+///
+/// https://datatracker.ietf.org/doc/html/draft-ietf-wish-whip
+pub struct WhipClient {
+    endpoint: String,
+    token: Option<String>,
+    client: Client,
+    handle: Handle,
+    trickle: Arc<Mutex<Trickle>>,
+    candidate_handler: Mutex<Option<SignalHandlerId>>,
+}
+
+impl WhipClient {
+    pub fn new(endpoint: &str, token: Option<&str>, handle: Handle) -> Self {
+        WhipClient {
+            endpoint: endpoint.to_string(),
+            token: token.map(str::to_string),
+            client: Client::new(),
+            handle,
+            trickle: Default::default(),
+            candidate_handler: Default::default(),
+        }
+    }
+
+    /// Sends the element's offer to the endpoint, applies the answer and starts trickling ICE candidates
+    pub async fn connect(&self, element: &WebRtcRedux) -> Result<(), ErrorMessage> {
+        let endpoint = Url::parse(&self.endpoint).map_err(|e| {
+            gst::error_msg!(
+                gst::ResourceError::Settings,
+                [&format!("Invalid WHIP endpoint {}: {:?}", self.endpoint, e)]
+            )
+        })?;
+
+        let client = self.client.clone();
+        let token = self.token.clone();
+        let handle = self.handle.clone();
+        let trickle = self.trickle.clone();
+        let handler = element.connect("on-ice-candidate", false, move |values| {
+            let mline_index = values[1].get::<u32>().unwrap();
+            let candidate = values[2].get::<String>().unwrap();
+
+            let session = {
+                let mut trickle = trickle.lock().unwrap();
+                match &trickle.session {
+                    Some(session) => session.clone(),
+                    None => {
+                        trickle.pending.push((mline_index, candidate));
+                        return None;
+                    }
+                }
+            };
+
+            handle.spawn(send_candidates(client.clone(), token.clone(), session, vec![(mline_index, candidate)]));
+            None
+        });
+
+        match self.negotiate(element, endpoint).await {
+            Ok(()) => {
+                let _ = self.candidate_handler.lock().unwrap().insert(handler);
+                Ok(())
+            }
+            Err(e) => {
+                element.disconnect(handler);
+                Err(e)
+            }
+        }
+    }
+
+    async fn negotiate(&self, element: &WebRtcRedux, endpoint: Url) -> Result<(), ErrorMessage> {
+        let offer = element.create_offer(None).await?;
+        element.set_local_description(&offer, RTCSdpType::Offer).await?;
+
+        let response = self
+            .authorize(self.client.post(endpoint.clone()))
+            .header(CONTENT_TYPE, SDP_CONTENT_TYPE)
+            .body(offer.to_string(LineEnding::CRLF))
+            .send()
+            .await
+            .map_err(|e| {
+                gst::error_msg!(
+                    gst::ResourceError::Write,
+                    [&format!("Failed to send offer to WHIP endpoint: {:?}", e)]
+                )
+            })?;
+
+        if response.status() != StatusCode::CREATED {
+            return Err(gst::error_msg!(
+                gst::ResourceError::Failed,
+                [&format!("WHIP endpoint rejected the offer: {}", response.status())]
+            ));
+        }
+
+        // The location may be relative to the endpoint
+        let resource = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| endpoint.join(location).ok())
+            .ok_or_else(|| {
+                gst::error_msg!(
+                    gst::ResourceError::Failed,
+                    ["WHIP endpoint did not return a resource location"]
+                )
+            })?;
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+
+        let answer = response.text().await.map_err(|e| {
+            gst::error_msg!(
+                gst::ResourceError::Read,
+                [&format!("Failed to read answer from WHIP endpoint: {:?}", e)]
+            )
+        })?;
+        element.set_remote_description(&parse_sdp(&answer)?, RTCSdpType::Answer).await?;
+
+        debug!(CAT, obj: element, "Created WHIP resource {}", resource);
+
+        let (ice_ufrag, ice_pwd) = offer.ice_credentials().ok_or_else(|| {
+            gst::error_msg!(
+                gst::ResourceError::Failed,
+                ["Offer has no ICE credentials"]
+            )
+        })?;
+        let session = WhipSession {
+            resource,
+            etag,
+            ice_ufrag,
+            ice_pwd,
+            media: offer
+                .media_sections()
+                .map(|media| (media.mid().unwrap_or_default(), media_line(media)))
+                .collect(),
+        };
+
+        let pending = {
+            let mut trickle = self.trickle.lock().unwrap();
+            let _ = trickle.session.insert(session.clone());
+            std::mem::take(&mut trickle.pending)
+        };
+
+        if !pending.is_empty() {
+            send_candidates(self.client.clone(), self.token.clone(), session, pending).await;
+        }
+
+        Ok(())
+    }
+
+    /// Stops trickling candidates and deletes the resource on the server
+    pub async fn disconnect(&self, element: &WebRtcRedux) -> Result<(), ErrorMessage> {
+        self.stop_trickle(element);
+        self.delete().await
+    }
+
+    pub(crate) fn stop_trickle(&self, element: &WebRtcRedux) {
+        if let Some(handler) = self.candidate_handler.lock().unwrap().take() {
+            element.disconnect(handler);
+        }
+    }
+
+    pub(crate) async fn delete(&self) -> Result<(), ErrorMessage> {
+        let session = match self.trickle.lock().unwrap().session.take() {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+
+        let response = self
+            .authorize(self.client.delete(session.resource.clone()))
+            .send()
+            .await
+            .map_err(|e| {
+                gst::error_msg!(
+                    gst::ResourceError::Write,
+                    [&format!("Failed to delete WHIP resource {}: {:?}", session.resource, e)]
+                )
+            })?;
+
+        if !response.status().is_success() {
+            return Err(gst::error_msg!(
+                gst::ResourceError::Failed,
+                [&format!("Failed to delete WHIP resource {}: {}", session.resource, response.status())]
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        authorize(request, &self.token)
+    }
+}
+
+fn authorize(request: RequestBuilder, token: &Option<String>) -> RequestBuilder {
+    match token {
+        Some(token) => request.header(AUTHORIZATION, format!("Bearer {}", token)),
+        None => request,
+    }
+}
+
+/// The media line of a trickle fragment only identifies the section, the port is a placeholder
+fn media_line(media: &SdpProp) -> String {
+    match media {
+        SdpProp::Media { r#type, protocol, format, .. } => format!("m={} 9 {} {}", r#type.to_string(), protocol, format),
+        _ => unreachable!(),
+    }
+}
+
+/// Builds an `application/trickle-ice-sdpfrag` body (RFC 8840)
+fn trickle_fragment(session: &WhipSession, candidates: &[(u32, String)]) -> String {
+    let mut by_media = BTreeMap::<usize, Vec<&str>>::new();
+    for (mline_index, candidate) in candidates {
+        by_media.entry(*mline_index as usize).or_default().push(candidate);
+    }
+
+    let mut fragment = format!("a=ice-ufrag:{}\r\na=ice-pwd:{}\r\n", session.ice_ufrag, session.ice_pwd);
+    for (mline_index, candidates) in by_media {
+        let (mid, media_line) = match session.media.get(mline_index) {
+            Some(media) => media,
+            None => continue,
+        };
+
+        fragment.push_str(&format!("{}\r\na=mid:{}\r\n", media_line, mid));
+        for candidate in candidates {
+            fragment.push_str(&format!("a=candidate:{}\r\n", candidate.strip_prefix("candidate:").unwrap_or(candidate)));
+        }
+    }
+
+    fragment
+}
+
+async fn send_candidates(client: Client, token: Option<String>, session: WhipSession, candidates: Vec<(u32, String)>) {
+    let mut request = authorize(client.patch(session.resource.clone()), &token)
+        .header(CONTENT_TYPE, TRICKLE_CONTENT_TYPE)
+        .body(trickle_fragment(&session, &candidates));
+    if let Some(etag) = &session.etag {
+        request = request.header(IF_MATCH, etag);
+    }
+
+    match request.send().await {
+        Ok(response) if response.status().is_success() => {}
+        // Servers without trickle support answer 405 and gather candidates from the offer only
+        Ok(response) if response.status() == StatusCode::METHOD_NOT_ALLOWED => {}
+        Ok(response) => warning!(CAT, "WHIP resource {} rejected candidates: {}", session.resource, response.status()),
+        Err(e) => warning!(CAT, "Failed to send candidates to WHIP resource {}: {:?}", session.resource, e),
+    }
+}
+
@@ -1,20 +1,37 @@
 use std::fmt::{Display, Formatter};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
+use bytes::Bytes;
 use enum_dispatch::enum_dispatch;
+use futures::{SinkExt, StreamExt};
 use gst::glib::BoolError;
 use gst::prelude::*;
 use gst::{debug_bin_to_dot_data, DebugGraphDetails, Element};
 use indoc::indoc;
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
 use webrtcredux::sdp::LineEnding;
+use webrtc::rtp::packetizer::Payloader;
 use std::string::ToString;
 use strum::IntoEnumIterator;
 use strum_macros::Display;
 use strum_macros::EnumIter;
 
 use webrtcredux::webrtcredux::{
-    sdp::{AddressType, MediaProp, MediaType, NetworkType, SdpProp, SDP},
-    RTCIceServer, WebRtcRedux,
+    payloader::{Av1Payloader, H265Payloader},
+    signalling::{PeerMessage, SignallingClient},
+    sdp::{
+        AddressType, BandwidthType, Candidate, Direction, EncryptionKeyMethod, MediaProp, MediaType, NetworkType, ParseError,
+        ParseMode, SdpAttribute, SdpProp, SetupRole, TimeZoneAdjustment, SDP,
+    },
+    generate_certificate, Interceptors, RTCIceServer, RTCPFeedback, RTCRtpCodecCapability, RTCRtpCodecParameters, RTCSdpType,
+    WebRtcRedux,
 };
 
 //TODO: Implement a webrtc-rs server configured for receiving to test the plugin
@@ -68,6 +85,8 @@ enum VideoEncoder {
     H264,
     VP8,
     VP9,
+    H265,
+    AV1,
 }
 
 impl GstEncoder for VideoEncoder {
@@ -76,6 +95,8 @@ impl GstEncoder for VideoEncoder {
             VideoEncoder::H264 => gst::ElementFactory::make("x264enc", None),
             VideoEncoder::VP8 => gst::ElementFactory::make("vp8enc", None),
             VideoEncoder::VP9 => gst::ElementFactory::make("vp9enc", None),
+            VideoEncoder::H265 => gst::ElementFactory::make("x265enc", None),
+            VideoEncoder::AV1 => gst::ElementFactory::make("av1enc", None),
         }
     }
 }
@@ -111,6 +132,16 @@ fn pipeline_creation_vp9() {
     pipeline_creation_test(vec![Encoder::Video(VideoEncoder::VP9)]);
 }
 
+#[test]
+fn pipeline_creation_h265() {
+    pipeline_creation_test(vec![Encoder::Video(VideoEncoder::H265)]);
+}
+
+#[test]
+fn pipeline_creation_av1() {
+    pipeline_creation_test(vec![Encoder::Video(VideoEncoder::AV1)]);
+}
+
 #[test]
 fn pipeline_creation_opus() {
     pipeline_creation_test(vec![Encoder::Audio(AudioEncoder::Opus)]);
@@ -188,6 +219,85 @@ fn pipeline_creation_test(encoders: Vec<Encoder>) {
     .unwrap();
 }
 
+#[test]
+fn pipeline_creation_rtp() {
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let pipeline = gst::Pipeline::new(None);
+
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+    pipeline
+        .add(&webrtcredux)
+        .expect("Failed to add webrtcredux to the pipeline");
+
+    // The viewer only knows the default webrtc-rs codecs, so the H.264 profile has to be one of them
+    for (src, encoder, caps, payloader) in [
+        ("videotestsrc", "x264enc", "video/x-h264,profile=constrained-baseline", "rtph264pay"),
+        ("audiotestsrc", "opusenc", "audio/x-opus", "rtpopuspay"),
+    ] {
+        let src = gst::ElementFactory::make(src, None).unwrap();
+        src.set_property("is-live", true);
+        let encoder = gst::ElementFactory::make(encoder, None).unwrap();
+        let capsfilter = gst::ElementFactory::make("capsfilter", None).unwrap();
+        capsfilter.set_property("caps", gst::Caps::from_str(caps).unwrap());
+        let payloader = gst::ElementFactory::make(payloader, None).unwrap();
+
+        pipeline
+            .add_many(&[&src, &encoder, &capsfilter, &payloader])
+            .expect("Failed to add elements to the pipeline");
+        Element::link_many(&[&src, &encoder, &capsfilter, &payloader, webrtcredux.as_ref()])
+            .expect("Failed to link elements");
+    }
+
+    assert!(webrtcredux.static_pad("video_0").is_some());
+    assert!(webrtcredux.static_pad("audio_0").is_some());
+
+    pipeline.set_state(gst::State::Playing).expect("Failed to set pipeline state");
+    runtime.block_on(webrtcredux.wait_for_all_tracks());
+    let (viewer, mut tracks) = connect_viewer(&runtime, &webrtcredux);
+
+    // The payloaded packets have to make it through to the viewer, not only the tracks
+    let mut mime_types = runtime.block_on(async {
+        let mut mime_types = vec![];
+        for _ in 0..2 {
+            let track = tokio::time::timeout(Duration::from_secs(10), tracks.recv())
+                .await
+                .expect("No track received")
+                .unwrap();
+            let mut buf = vec![0u8; 1500];
+            let (n, _) = tokio::time::timeout(Duration::from_secs(10), track.read(&mut buf))
+                .await
+                .expect("No RTP received")
+                .expect("Failed to read RTP");
+            assert!(n > 12);
+            mime_types.push(track.codec().await.capability.mime_type.to_lowercase());
+        }
+        mime_types
+    });
+    mime_types.sort();
+    assert_eq!(mime_types, vec!["audio/opus", "video/h264"]);
+
+    runtime.block_on(viewer.close()).unwrap();
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+#[test]
+fn rtp_encoding_names() {
+    init();
+    let webrtcredux = WebRtcRedux::default();
+
+    for (template, caps, supported) in [
+        ("video_%u", "application/x-rtp,media=video,encoding-name=VP8", true),
+        ("video_%u", "application/x-rtp,media=video,encoding-name=JPEG", false),
+        ("audio_%u", "application/x-rtp,media=audio,encoding-name=OPUS", true),
+        ("audio_%u", "application/x-rtp,media=audio,encoding-name=L16", false),
+    ] {
+        let template_caps = webrtcredux.pad_template(template).unwrap().caps();
+        assert_eq!(template_caps.can_intersect(&gst::Caps::from_str(caps).unwrap()), supported, "{}", caps);
+    }
+}
+
 #[test]
 fn sdp_serialization() {
     let target = indoc!(
@@ -532,3 +642,2090 @@ fn sdp_symmetry() {
 
     assert_eq!(text, sdp.unwrap().to_string(LineEnding::CRLF));
 }
+
+#[test]
+fn receive_pad_templates() {
+    init();
+    let webrtcredux = WebRtcRedux::default();
+
+    for name in ["recv_video_%u", "recv_audio_%u"] {
+        let templ = webrtcredux.pad_template(name);
+        assert!(templ.is_some(), "Missing pad template {}", name);
+
+        let templ = templ.unwrap();
+        assert_eq!(templ.direction(), gst::PadDirection::Src);
+        assert_eq!(templ.presence(), gst::PadPresence::Sometimes);
+    }
+}
+
+#[test]
+fn receive_remote_track() {
+    use webrtc::api::media_engine::MIME_TYPE_VP8;
+    use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+    use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let pipeline = gst::Pipeline::new(None);
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+    pipeline
+        .add(&webrtcredux)
+        .expect("Failed to add webrtcredux to the pipeline");
+
+    // Every remote track shows up as a sometimes pad, read it with an appsink
+    let (pads_tx, pads_rx) = std::sync::mpsc::channel();
+    let pipeline_weak = pipeline.downgrade();
+    webrtcredux.connect_pad_added(move |_, pad| {
+        let pipeline = match pipeline_weak.upgrade() {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+        let appsink = gst::ElementFactory::make("appsink", None)
+            .unwrap()
+            .dynamic_cast::<gst_app::AppSink>()
+            .unwrap();
+        appsink.set_sync(false);
+        pipeline.add(&appsink).unwrap();
+        appsink.sync_state_with_parent().unwrap();
+        pad.link(&appsink.static_pad("sink").unwrap()).expect("Failed to link the received track");
+        let _ = pads_tx.send((pad.name().to_string(), appsink));
+    });
+
+    pipeline.set_state(gst::State::Playing).expect("Failed to set pipeline state");
+
+    let track = Arc::new(TrackLocalStaticSample::new(
+        RTCRtpCodecCapability {
+            mime_type: MIME_TYPE_VP8.to_string(),
+            clock_rate: 90000,
+            ..Default::default()
+        },
+        "video".to_string(),
+        "remote".to_string(),
+    ));
+
+    let (viewer, payload_type) = runtime.block_on(async {
+        let mut media_engine = webrtc::api::media_engine::MediaEngine::default();
+        media_engine.register_default_codecs().unwrap();
+        let (viewer, _) = new_viewer(media_engine).await;
+        viewer.add_track(Arc::clone(&track) as Arc<dyn webrtc::track::track_local::TrackLocal + Send + Sync>).await.unwrap();
+
+        let offer = viewer.create_offer(None).await.unwrap();
+        let mut gathering_complete = viewer.gathering_complete_promise().await;
+        viewer.set_local_description(offer).await.unwrap();
+        let _ = gathering_complete.recv().await;
+        let offer = SDP::from_str(&viewer.local_description().await.unwrap().sdp).expect("Offer should be valid SDP");
+
+        webrtcredux.set_remote_description(&offer, RTCSdpType::Offer).await.expect("Failed to apply offer");
+        let answer = webrtcredux.create_answer(None).await.expect("Failed to create answer");
+        let mut gathering_complete = webrtcredux.gathering_complete_promise().await.unwrap();
+        webrtcredux.set_local_description(&answer, RTCSdpType::Answer).await.expect("Failed to set local description");
+        let _ = gathering_complete.recv().await;
+        let answer = webrtcredux.local_description().await.unwrap().unwrap();
+
+        let mut description = RTCSessionDescription::default();
+        description.sdp = answer.to_string(LineEnding::CRLF);
+        description.sdp_type = RTCSdpType::Answer;
+        viewer.set_remote_description(description).await.expect("Viewer failed to apply the answer");
+
+        let payload_type = answer
+            .to_string(LineEnding::LF)
+            .lines()
+            .find_map(|line| line.strip_prefix("a=rtpmap:").filter(|rtpmap| rtpmap.contains("VP8/90000")).map(|rtpmap| rtpmap.split(' ').next().unwrap().parse::<i32>().unwrap()))
+            .expect("VP8 should be negotiated");
+
+        (viewer, payload_type)
+    });
+
+    // Frames content doesn't matter, the element doesn't decode them
+    let writer_track = Arc::clone(&track);
+    let writer = runtime.spawn(async move {
+        loop {
+            let _ = writer_track
+                .write_sample(&webrtc_media::Sample {
+                    data: Bytes::from_static(&[0x10, 0x02, 0x00, 0x9d, 0x01, 0x2a]),
+                    duration: Duration::from_millis(33),
+                    ..Default::default()
+                })
+                .await;
+            tokio::time::sleep(Duration::from_millis(33)).await;
+        }
+    });
+
+    let (name, appsink) = pads_rx.recv_timeout(Duration::from_secs(10)).expect("No pad added for the remote track");
+    assert_eq!(name, "recv_video_0");
+
+    let sample = appsink
+        .try_pull_sample(gst::ClockTime::from_seconds(10))
+        .expect("No RTP received from the remote track");
+    let caps = sample.caps().expect("Sample without caps");
+    let structure = caps.structure(0).unwrap();
+    assert_eq!(structure.name(), "application/x-rtp");
+    assert_eq!(structure.get::<&str>("media").unwrap(), "video");
+    assert_eq!(structure.get::<&str>("encoding-name").unwrap(), "VP8");
+    assert_eq!(structure.get::<i32>("clock-rate").unwrap(), 90000);
+    assert_eq!(structure.get::<i32>("payload").unwrap(), payload_type);
+
+    let buffer = sample.buffer().unwrap().map_readable().unwrap();
+    assert!(buffer.len() > 12, "Buffers should be whole RTP packets");
+    assert_eq!(buffer[0] >> 6, 2, "Buffers should be RTP version 2");
+    assert_eq!((buffer[1] & 0x7f) as i32, payload_type);
+
+    writer.abort();
+    runtime.block_on(async {
+        viewer.close().await.unwrap();
+    });
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+#[test]
+fn runtime_pad_request_release() {
+    init();
+    let pipeline = gst::Pipeline::new(None);
+
+    let webrtcredux = WebRtcRedux::default();
+    pipeline
+        .add(&webrtcredux)
+        .expect("Failed to add webrtcredux to the pipeline");
+
+    let src = gst::ElementFactory::make("videotestsrc", None).unwrap();
+    let encoder = gst::ElementFactory::make("vp8enc", None).unwrap();
+    pipeline.add_many(&[&src, &encoder]).unwrap();
+    Element::link_many(&[&src, &encoder, webrtcredux.as_ref()]).expect("Failed to link elements");
+
+    pipeline.set_state(gst::State::Playing).expect("Failed to set pipeline state");
+    let _ = pipeline.state(gst::ClockTime::from_seconds(5));
+
+    let audio_pad = webrtcredux
+        .request_pad_simple("audio_%u")
+        .expect("Pads should be requestable while playing");
+    assert_eq!(audio_pad.name(), "audio_0");
+
+    let data_pad = webrtcredux
+        .request_pad_simple("data_%u")
+        .expect("Data pads should be requestable while playing");
+
+    webrtcredux.release_request_pad(&audio_pad);
+    webrtcredux.release_request_pad(&data_pad);
+    assert!(webrtcredux.static_pad("audio_0").is_none());
+    assert!(webrtcredux.static_pad(&data_pad.name()).is_none());
+    assert!(webrtcredux.static_pad("video_0").is_some());
+
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+#[test]
+fn data_before_channel_open() {
+    init();
+    let pipeline = gst::Pipeline::new(None);
+
+    let webrtcredux = WebRtcRedux::default();
+    let appsrc = gst::ElementFactory::make("appsrc", None)
+        .unwrap()
+        .dynamic_cast::<gst_app::AppSrc>()
+        .unwrap();
+    pipeline.add_many(&[appsrc.upcast_ref(), webrtcredux.upcast_ref::<gst::Element>()]).unwrap();
+    let data_pad = webrtcredux.request_pad_simple("data_%u").unwrap();
+    appsrc.static_pad("src").unwrap().link(&data_pad).expect("Failed to link appsrc");
+
+    pipeline.set_state(gst::State::Playing).expect("Failed to set pipeline state");
+
+    // Nothing is negotiated, so the channel never opens
+    for i in 0..300u32 {
+        appsrc
+            .push_buffer(gst::Buffer::from_mut_slice(i.to_be_bytes().to_vec()))
+            .expect("Failed to push buffer");
+    }
+    let _ = appsrc.end_of_stream();
+
+    let bus = pipeline.bus().unwrap();
+    let error = bus.timed_pop_filtered(gst::ClockTime::from_seconds(2), &[gst::MessageType::Error]);
+    assert!(error.is_none(), "Data before the channel opens should not fail the pipeline: {:?}", error);
+
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+#[test]
+fn broadcast_peers() {
+    init();
+    let pipeline = gst::Pipeline::new(None);
+
+    let webrtcredux = WebRtcRedux::default();
+    pipeline
+        .add(&webrtcredux)
+        .expect("Failed to add webrtcredux to the pipeline");
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+
+    assert!(runtime.block_on(webrtcredux.add_peer("viewer-1")).is_err(), "Peers need a ready element");
+
+    pipeline.set_state(gst::State::Ready).expect("Failed to set pipeline state");
+
+    runtime.block_on(async {
+        let peer = webrtcredux.add_peer("viewer-1").await.expect("Failed to add peer");
+        assert_eq!(peer.id(), "viewer-1");
+        assert!(webrtcredux.add_peer("viewer-1").await.is_err(), "Peer ids should be unique");
+
+        let peer_2 = webrtcredux.add_peer("viewer-2").await.expect("Failed to add peer");
+        peer.create_offer(None).await.expect("Failed to create offer for peer");
+
+        // Restarting ICE of a peer asks for renegotiation and the next offer has fresh credentials
+        let ice_ufrag = |sdp: &SDP| {
+            sdp.to_string(LineEnding::LF).lines().find_map(|line| line.strip_prefix("a=ice-ufrag:").map(str::to_string))
+        };
+        let offer = peer_2.create_offer(None).await.expect("Failed to create offer for peer");
+        peer_2.set_local_description(&offer, RTCSdpType::Offer).await.expect("Failed to set local description");
+        let negotiations = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let handler_negotiations = negotiations.clone();
+        peer_2.on_negotiation_needed(Box::new(move || {
+            handler_negotiations.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Box::pin(async {})
+        })).await;
+        peer_2.restart_ice().await.expect("Failed to restart ICE of peer");
+        assert!(negotiations.load(std::sync::atomic::Ordering::SeqCst) > 0);
+        let restarted = peer_2.create_offer(None).await.expect("Failed to create offer for peer");
+        assert!(ice_ufrag(&restarted).is_some());
+        assert_ne!(ice_ufrag(&offer), ice_ufrag(&restarted));
+
+        webrtcredux.remove_peer("viewer-1").await.expect("Failed to remove peer");
+        assert!(webrtcredux.remove_peer("viewer-1").await.is_err());
+    });
+
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+#[test]
+fn broadcast_shared_track() {
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let pipeline = gst::Pipeline::new(None);
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+    let src = gst::ElementFactory::make("videotestsrc", None).unwrap();
+    src.set_property("is-live", true);
+    let encoder = gst::ElementFactory::make("vp8enc", None).unwrap();
+    pipeline.add_many(&[&src, &encoder, webrtcredux.upcast_ref()]).unwrap();
+    Element::link_many(&[&src, &encoder, webrtcredux.as_ref()]).expect("Failed to link elements");
+
+    pipeline.set_state(gst::State::Playing).expect("Failed to set pipeline state");
+    runtime.block_on(webrtcredux.wait_for_all_tracks());
+    let (viewer, mut tracks) = connect_viewer(&runtime, &webrtcredux);
+
+    // The peer is added after the track exists, so it has to get the same one
+    let (peer_viewer, mut peer_tracks) = runtime.block_on(async {
+        let peer = webrtcredux.add_peer("viewer-1").await.expect("Failed to add peer");
+        let offer = peer.create_offer(None).await.expect("Failed to create offer for peer");
+        let mut gathering_complete = peer.gathering_complete_promise().await;
+        peer.set_local_description(&offer, RTCSdpType::Offer).await.expect("Failed to set local description");
+        let _ = gathering_complete.recv().await;
+        let offer = peer.local_description().await.unwrap().unwrap();
+
+        let mut media_engine = webrtc::api::media_engine::MediaEngine::default();
+        media_engine.register_default_codecs().unwrap();
+        let (peer_viewer, peer_tracks) = new_viewer(media_engine).await;
+        let answer = answer_as_viewer(&peer_viewer, &offer).await;
+        peer.set_remote_description(&answer, RTCSdpType::Answer).await.expect("Failed to apply answer");
+
+        (peer_viewer, peer_tracks)
+    });
+
+    let mut received = runtime.block_on(async {
+        let mut received = vec![];
+        for tracks in [&mut tracks, &mut peer_tracks] {
+            let track = tokio::time::timeout(Duration::from_secs(10), tracks.recv())
+                .await
+                .expect("No track received")
+                .unwrap();
+            let mut buf = vec![0u8; 1500];
+            tokio::time::timeout(Duration::from_secs(10), track.read(&mut buf))
+                .await
+                .expect("No RTP received")
+                .expect("Failed to read RTP");
+            received.push(track);
+        }
+        received
+    });
+    let (track, peer_track) = (received.remove(0), received.remove(0));
+    runtime.block_on(async {
+        assert_eq!(
+            (track.stream_id().await, track.id().await),
+            (peer_track.stream_id().await, peer_track.id().await),
+            "Both connections should carry the same track"
+        );
+    });
+
+    // A viewer vanishing without being removed must not stop the stream for the others
+    runtime.block_on(async {
+        peer_viewer.close().await.unwrap();
+        let mut buf = vec![0u8; 1500];
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(3);
+        while tokio::time::Instant::now() < deadline {
+            tokio::time::timeout(Duration::from_secs(2), track.read(&mut buf))
+                .await
+                .expect("The remaining viewer stopped receiving RTP")
+                .expect("Failed to read RTP");
+        }
+    });
+    let bus = pipeline.bus().unwrap();
+    let error = bus.pop_filtered(&[gst::MessageType::Error]);
+    assert!(error.is_none(), "A vanished viewer should not fail the pipeline: {:?}", error);
+
+    runtime.block_on(async {
+        webrtcredux.remove_peer("viewer-1").await.expect("Failed to remove peer");
+        viewer.close().await.unwrap();
+    });
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl HttpRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn read_http_request(stream: &mut TcpStream) -> HttpRequest {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap().to_string();
+    let path = parts.next().unwrap().to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        let (key, value) = line.split_once(':').unwrap();
+        headers.push((key.trim().to_string(), value.trim().to_string()));
+    }
+
+    let length = headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+        .map_or(0, |(_, value)| value.parse::<usize>().unwrap());
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+
+    HttpRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8(body).unwrap(),
+    }
+}
+
+#[test]
+fn whip_client() {
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    // Stand-in WHIP server, a second element plays the media server and answers the offers
+    let answerer = WebRtcRedux::default();
+    answerer.set_tokio_runtime(runtime.handle().clone());
+    answerer.set_state(gst::State::Ready).expect("Failed to set answerer state");
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}/whip", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+
+    let server_requests = requests.clone();
+    let server_answerer = answerer.clone();
+    let server_runtime = runtime.handle().clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let request = read_http_request(&mut stream);
+
+            let response = match request.method.as_str() {
+                "POST" => {
+                    let offer = SDP::from_str(&request.body).expect("Failed to parse offer");
+                    let answer = server_runtime.block_on(async {
+                        server_answerer.set_remote_description(&offer, RTCSdpType::Offer).await.unwrap();
+                        let answer = server_answerer.create_answer(None).await.unwrap();
+                        server_answerer.set_local_description(&answer, RTCSdpType::Answer).await.unwrap();
+                        answer.to_string(LineEnding::CRLF)
+                    });
+                    format!(
+                        "HTTP/1.1 201 Created\r\nLocation: /whip/resource/1\r\nETag: \"1\"\r\nContent-Type: application/sdp\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        answer.len(),
+                        answer
+                    )
+                }
+                _ => "HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n".to_string(),
+            };
+
+            server_requests.lock().unwrap().push(request);
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    let pipeline = gst::Pipeline::new(None);
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+    pipeline
+        .add(&webrtcredux)
+        .expect("Failed to add webrtcredux to the pipeline");
+
+    let src = gst::ElementFactory::make("videotestsrc", None).unwrap();
+    let encoder = gst::ElementFactory::make("vp8enc", None).unwrap();
+    pipeline.add_many(&[&src, &encoder]).unwrap();
+    Element::link_many(&[&src, &encoder, webrtcredux.as_ref()]).expect("Failed to link elements");
+
+    pipeline.set_state(gst::State::Playing).expect("Failed to set pipeline state");
+    let _ = pipeline.state(gst::ClockTime::from_seconds(5));
+
+    runtime.block_on(async {
+        webrtcredux
+            .whip_connect(&endpoint, Some("secret"))
+            .await
+            .expect("Failed to connect to the WHIP endpoint");
+        assert!(webrtcredux.whip_connect(&endpoint, None).await.is_err(), "Only one WHIP session at a time");
+
+        webrtcredux.whip_disconnect().await.expect("Failed to delete the WHIP resource");
+    });
+
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+    answerer.set_state(gst::State::Null).expect("Failed to set answerer state");
+
+    let requests = requests.lock().unwrap();
+    let post = &requests[0];
+    assert_eq!(post.method, "POST");
+    assert_eq!(post.path, "/whip");
+    assert_eq!(post.header("authorization"), Some("Bearer secret"));
+    assert_eq!(post.header("content-type"), Some("application/sdp"));
+
+    for patch in requests.iter().filter(|request| request.method == "PATCH") {
+        assert_eq!(patch.path, "/whip/resource/1");
+        assert_eq!(patch.header("content-type"), Some("application/trickle-ice-sdpfrag"));
+        assert_eq!(patch.header("if-match"), Some("\"1\""));
+        assert!(patch.body.contains("a=ice-ufrag:"));
+        assert!(patch.body.contains("a=candidate:"));
+    }
+
+    let delete = requests
+        .iter()
+        .find(|request| request.method == "DELETE")
+        .expect("WHIP resource was not deleted");
+    assert_eq!(delete.path, "/whip/resource/1");
+    assert_eq!(delete.header("authorization"), Some("Bearer secret"));
+}
+
+#[cfg(feature = "whep")]
+#[test]
+fn whep_server() {
+    use webrtc::api::media_engine::MediaEngine;
+    use webrtc::api::APIBuilder;
+    use webrtc::peer_connection::configuration::RTCConfiguration;
+    use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+    use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+    use webrtc::rtp_transceiver::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+    use webrtc::rtp_transceiver::RTCRtpTransceiverInit;
+
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let pipeline = gst::Pipeline::new(None);
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+    pipeline
+        .add(&webrtcredux)
+        .expect("Failed to add webrtcredux to the pipeline");
+
+    let src = gst::ElementFactory::make("videotestsrc", None).unwrap();
+    let encoder = gst::ElementFactory::make("vp8enc", None).unwrap();
+    pipeline.add_many(&[&src, &encoder]).unwrap();
+    Element::link_many(&[&src, &encoder, webrtcredux.as_ref()]).expect("Failed to link elements");
+
+    pipeline.set_state(gst::State::Playing).expect("Failed to set pipeline state");
+    let _ = pipeline.state(gst::ClockTime::from_seconds(5));
+
+    let addr = webrtcredux
+        .whep_serve("127.0.0.1:0".parse().unwrap())
+        .expect("Failed to start WHEP server");
+    let endpoint = format!("http://{}/whep", addr);
+
+    runtime.block_on(async {
+        // Plays the browser, a receive-only viewer
+        let mut media_engine = MediaEngine::default();
+        media_engine.register_default_codecs().unwrap();
+        let api = APIBuilder::new().with_media_engine(media_engine).build();
+        let viewer = api.new_peer_connection(RTCConfiguration::default()).await.unwrap();
+        viewer
+            .add_transceiver_from_kind(
+                RTPCodecType::Video,
+                &[RTCRtpTransceiverInit {
+                    direction: RTCRtpTransceiverDirection::Recvonly,
+                    send_encodings: vec![],
+                }],
+            )
+            .await
+            .unwrap();
+
+        let offer = viewer.create_offer(None).await.unwrap();
+        let mut gathering_complete = viewer.gathering_complete_promise().await;
+        viewer.set_local_description(offer).await.unwrap();
+        let _ = gathering_complete.recv().await;
+        let offer = viewer.local_description().await.unwrap();
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(&endpoint)
+            .header("Content-Type", "application/sdp")
+            .body(offer.sdp)
+            .send()
+            .await
+            .expect("Failed to post offer");
+        assert_eq!(response.status(), reqwest::StatusCode::CREATED);
+        assert_eq!(response.headers()["content-type"], "application/sdp");
+
+        let location = response.headers()["location"].to_str().unwrap().to_string();
+        assert!(location.starts_with("/whep/"));
+
+        let answer = response.text().await.unwrap();
+        let parsed = SDP::from_str(&answer).expect("Answer should be valid SDP");
+        assert_eq!(parsed.media_sections().count(), 1);
+
+        let mut description = RTCSessionDescription::default();
+        description.sdp = answer;
+        description.sdp_type = RTCSdpType::Answer;
+        viewer.set_remote_description(description).await.expect("Failed to apply answer");
+
+        let resource = format!("http://{}{}", addr, location);
+        let response = client.delete(&resource).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let response = client.delete(&resource).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        let response = client.post(&endpoint).body("not sdp").send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        viewer.close().await.unwrap();
+    });
+
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+#[cfg(feature = "whep")]
+#[test]
+fn whep_viewer_vanishes() {
+    use webrtc::api::media_engine::MediaEngine;
+    use webrtc::api::setting_engine::SettingEngine;
+    use webrtc::api::APIBuilder;
+    use webrtc::peer_connection::configuration::RTCConfiguration;
+    use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+    use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+    use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+    use webrtc::rtp_transceiver::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+    use webrtc::rtp_transceiver::RTCRtpTransceiverInit;
+
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let pipeline = gst::Pipeline::new(None);
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+    // Short timeouts so the vanished viewer is noticed quickly
+    let mut setting_engine = SettingEngine::default();
+    setting_engine.set_ice_timeouts(
+        Some(Duration::from_secs(1)),
+        Some(Duration::from_secs(2)),
+        Some(Duration::from_millis(200)),
+    );
+    webrtcredux.set_setting_engine(setting_engine);
+
+    let src = gst::ElementFactory::make("videotestsrc", None).unwrap();
+    src.set_property("is-live", true);
+    let encoder = gst::ElementFactory::make("vp8enc", None).unwrap();
+    pipeline.add_many(&[&src, &encoder, webrtcredux.upcast_ref()]).unwrap();
+    Element::link_many(&[&src, &encoder, webrtcredux.as_ref()]).expect("Failed to link elements");
+
+    pipeline.set_state(gst::State::Playing).expect("Failed to set pipeline state");
+    let _ = pipeline.state(gst::ClockTime::from_seconds(5));
+
+    let addr = webrtcredux
+        .whep_serve("127.0.0.1:0".parse().unwrap())
+        .expect("Failed to start WHEP server");
+
+    runtime.block_on(async {
+        let mut media_engine = MediaEngine::default();
+        media_engine.register_default_codecs().unwrap();
+        let api = APIBuilder::new().with_media_engine(media_engine).build();
+        let viewer = api.new_peer_connection(RTCConfiguration::default()).await.unwrap();
+        viewer
+            .add_transceiver_from_kind(
+                RTPCodecType::Video,
+                &[RTCRtpTransceiverInit {
+                    direction: RTCRtpTransceiverDirection::Recvonly,
+                    send_encodings: vec![],
+                }],
+            )
+            .await
+            .unwrap();
+
+        let (connected_tx, mut connected) = tokio::sync::mpsc::unbounded_channel();
+        viewer.on_peer_connection_state_change(Box::new(move |state| {
+            if state == RTCPeerConnectionState::Connected {
+                let _ = connected_tx.send(());
+            }
+            Box::pin(async {})
+        }));
+
+        let offer = viewer.create_offer(None).await.unwrap();
+        let mut gathering_complete = viewer.gathering_complete_promise().await;
+        viewer.set_local_description(offer).await.unwrap();
+        let _ = gathering_complete.recv().await;
+        let offer = viewer.local_description().await.unwrap();
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/whep", addr))
+            .header("Content-Type", "application/sdp")
+            .body(offer.sdp)
+            .send()
+            .await
+            .expect("Failed to post offer");
+        assert_eq!(response.status(), reqwest::StatusCode::CREATED);
+        let location = response.headers()["location"].to_str().unwrap().to_string();
+        let session = location.rsplit('/').next().unwrap().to_string();
+
+        let mut description = RTCSessionDescription::default();
+        description.sdp = response.text().await.unwrap();
+        description.sdp_type = RTCSdpType::Answer;
+        viewer.set_remote_description(description).await.expect("Failed to apply answer");
+
+        tokio::time::timeout(Duration::from_secs(10), connected.recv())
+            .await
+            .expect("Viewer didn't connect");
+        assert!(webrtcredux.get_stats().await.unwrap().peers.contains_key(&session));
+
+        // Gone without a DELETE, the session has to be cleaned up once ICE fails, without deadlocking the handler
+        viewer.close().await.unwrap();
+        let removed = tokio::time::timeout(Duration::from_secs(15), async {
+            while webrtcredux.get_stats().await.unwrap().peers.contains_key(&session) {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await;
+        assert!(removed.is_ok(), "Session {} was not removed", session);
+
+        let response = reqwest::Client::new()
+            .delete(format!("http://{}{}", addr, location))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    });
+
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+/// Minimal in-process version of gst-examples' simple signalling server
+async fn signalling_server(listener: tokio::net::TcpListener) {
+    use std::collections::HashMap;
+    use tokio::sync::mpsc;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let peers = Arc::new(Mutex::new(HashMap::<String, mpsc::UnboundedSender<Message>>::new()));
+    let sessions = Arc::new(Mutex::new(HashMap::<String, String>::new()));
+
+    while let Ok((stream, _)) = listener.accept().await {
+        let peers = peers.clone();
+        let sessions = sessions.clone();
+        tokio::spawn(async move {
+            let (mut sink, mut stream) = tokio_tungstenite::accept_async(stream).await.unwrap().split();
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            tokio::spawn(async move {
+                while let Some(message) = rx.recv().await {
+                    if sink.send(message).await.is_err() {
+                        break;
+                    }
+                }
+            });
+
+            let mut our_id = String::new();
+            while let Some(Ok(Message::Text(text))) = stream.next().await {
+                if let Some(id) = text.strip_prefix("HELLO ") {
+                    our_id = id.to_string();
+                    peers.lock().unwrap().insert(our_id.clone(), tx.clone());
+                    tx.send(Message::Text("HELLO".to_string())).unwrap();
+                } else if let Some(peer_id) = text.strip_prefix("SESSION ") {
+                    let mut sessions = sessions.lock().unwrap();
+                    sessions.insert(our_id.clone(), peer_id.to_string());
+                    sessions.insert(peer_id.to_string(), our_id.clone());
+                    tx.send(Message::Text("SESSION_OK".to_string())).unwrap();
+                } else {
+                    let peer_id = sessions.lock().unwrap().get(&our_id).cloned();
+                    let peer = peer_id.and_then(|peer_id| peers.lock().unwrap().get(&peer_id).cloned());
+                    match peer {
+                        Some(peer) => peer.send(Message::Text(text)).unwrap(),
+                        None => tx.send(Message::Text("ERROR peer not found".to_string())).unwrap(),
+                    }
+                }
+            }
+
+            // Like the real server, the session ends for both peers when one leaves
+            peers.lock().unwrap().remove(&our_id);
+            let peer_id = sessions.lock().unwrap().remove(&our_id);
+            if let Some(peer) = peer_id.and_then(|peer_id| peers.lock().unwrap().get(&peer_id).cloned()) {
+                let _ = peer.send(Message::Close(None));
+            }
+        });
+    }
+}
+
+#[test]
+fn signalling_messages() {
+    let sdp: PeerMessage = serde_json::from_str(r#"{"sdp": {"type": "offer", "sdp": "v=0"}}"#).unwrap();
+    assert_eq!(sdp, PeerMessage::Sdp { r#type: "offer".to_string(), sdp: "v=0".to_string() });
+
+    let ice = PeerMessage::Ice { candidate: "candidate:1 1 udp 1 127.0.0.1 5000 typ host".to_string(), sdp_mline_index: 0 };
+    assert_eq!(
+        serde_json::to_string(&ice).unwrap(),
+        r#"{"ice":{"candidate":"candidate:1 1 udp 1 127.0.0.1 5000 typ host","sdpMLineIndex":0}}"#
+    );
+}
+
+#[test]
+fn signalling_session() {
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let listener = runtime.block_on(tokio::net::TcpListener::bind("127.0.0.1:0")).unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    runtime.spawn(signalling_server(listener));
+
+    let pipeline = gst::Pipeline::new(None);
+    let sender = WebRtcRedux::default();
+    sender.set_tokio_runtime(runtime.handle().clone());
+    pipeline.add(&sender).expect("Failed to add webrtcredux to the pipeline");
+
+    let src = gst::ElementFactory::make("videotestsrc", None).unwrap();
+    let encoder = gst::ElementFactory::make("vp8enc", None).unwrap();
+    pipeline.add_many(&[&src, &encoder]).unwrap();
+    Element::link_many(&[&src, &encoder, sender.as_ref()]).expect("Failed to link elements");
+
+    let receiver = WebRtcRedux::default();
+    receiver.set_tokio_runtime(runtime.handle().clone());
+    receiver.set_state(gst::State::Ready).expect("Failed to set receiver state");
+
+    pipeline.set_state(gst::State::Playing).expect("Failed to set pipeline state");
+    let _ = pipeline.state(gst::ClockTime::from_seconds(5));
+
+    // Connecting from outside of any runtime, the client has to use the elements' runtime
+    let (answering, offering) = futures::executor::block_on(async {
+        let answering = SignallingClient::connect(&receiver, &url, "receiver", None)
+            .await
+            .expect("Failed to connect receiver");
+        let offering = SignallingClient::connect(&sender, &url, "sender", Some("receiver"))
+            .await
+            .expect("Failed to connect sender");
+        (answering, offering)
+    });
+
+    // The answer comes back through the server asynchronously
+    let mut negotiated = false;
+    for _ in 0..100 {
+        if sender.property::<Option<String>>("remote-description").is_some() {
+            negotiated = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(negotiated, "Offer/answer exchange through the signalling server did not complete");
+    assert!(receiver.property::<Option<String>>("local-description").is_some());
+
+    offering.close();
+    futures::executor::block_on(answering.wait()).expect("Session should end cleanly");
+
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+    receiver.set_state(gst::State::Null).expect("Failed to set receiver state");
+}
+
+#[test]
+fn statistics() {
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let pipeline = gst::Pipeline::new(None);
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+    pipeline
+        .add(&webrtcredux)
+        .expect("Failed to add webrtcredux to the pipeline");
+
+    let src = gst::ElementFactory::make("videotestsrc", None).unwrap();
+    let encoder = gst::ElementFactory::make("vp8enc", None).unwrap();
+    pipeline.add_many(&[&src, &encoder]).unwrap();
+    Element::link_many(&[&src, &encoder, webrtcredux.as_ref()]).expect("Failed to link elements");
+
+    pipeline.set_state(gst::State::Playing).expect("Failed to set pipeline state");
+    let _ = pipeline.state(gst::ClockTime::from_seconds(5));
+
+    let stats = runtime.block_on(async {
+        webrtcredux.add_peer("viewer").await.expect("Failed to add peer");
+        webrtcredux.get_stats().await.expect("Failed to get stats")
+    });
+
+    assert_eq!(stats.connection.tracks.len(), 1);
+    let track = &stats.connection.tracks[0];
+    assert_eq!(track.pad, "video_0");
+    assert_eq!(track.kind, "video");
+    assert_eq!(track.packets_lost, 0);
+    assert!(track.round_trip_time.is_none(), "No receiver reports without a remote peer");
+
+    let peer = stats.peers.get("viewer").expect("Broadcast peers should be reported");
+    assert_eq!(peer.tracks.len(), 1);
+    assert_ne!(peer.tracks[0].ssrc, track.ssrc, "Every peer connection has its own SSRC");
+
+    let structure = webrtcredux.emit_by_name::<gst::Structure>("get-stats", &[]);
+    assert_eq!(structure.name(), "application/x-webrtcredux-stats");
+    let track = structure.get::<gst::Structure>("video_0").expect("Track stats should be keyed by pad");
+    assert_eq!(track.get::<String>("kind").unwrap(), "video");
+    assert!(track.has_field("bytes-sent"));
+    assert!(structure.has_field("available-outgoing-bitrate"));
+    let peers = structure.get::<gst::Structure>("peers").unwrap();
+    assert!(peers.has_field("viewer"));
+
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+#[test]
+fn statistics_messages() {
+    init();
+    let pipeline = gst::Pipeline::new(None);
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_property("stats-interval-ms", 100u32);
+    pipeline
+        .add(&webrtcredux)
+        .expect("Failed to add webrtcredux to the pipeline");
+
+    let src = gst::ElementFactory::make("videotestsrc", None).unwrap();
+    let encoder = gst::ElementFactory::make("vp8enc", None).unwrap();
+    pipeline.add_many(&[&src, &encoder]).unwrap();
+    Element::link_many(&[&src, &encoder, webrtcredux.as_ref()]).expect("Failed to link elements");
+
+    pipeline.set_state(gst::State::Playing).expect("Failed to set pipeline state");
+
+    let bus = pipeline.bus().unwrap();
+    let mut stats = None;
+    while let Some(message) = bus.timed_pop_filtered(gst::ClockTime::from_seconds(5), &[gst::MessageType::Element]) {
+        let structure = message.structure().unwrap();
+        if structure.name() == "application/x-webrtcredux-stats" {
+            assert_eq!(message.src().as_ref(), Some(webrtcredux.upcast_ref::<gst::Object>()));
+            stats = Some(structure.to_owned());
+            break;
+        }
+    }
+
+    let stats = stats.expect("No stats message was posted");
+    assert!(stats.has_field("video_0"));
+    assert!(stats.has_field("available-outgoing-bitrate"));
+
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+#[test]
+fn media_engine_configuration() {
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let pipeline = gst::Pipeline::new(None);
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+    webrtcredux.set_codecs(vec![RTCRtpCodecParameters {
+        capability: RTCRtpCodecCapability {
+            mime_type: "video/VP8".to_string(),
+            clock_rate: 90000,
+            channels: 0,
+            sdp_fmtp_line: "".to_string(),
+            rtcp_feedback: vec![RTCPFeedback { typ: "nack".to_string(), parameter: "pli".to_string() }],
+        },
+        payload_type: 120,
+        ..Default::default()
+    }]);
+    webrtcredux.set_interceptors(Interceptors {
+        nack: false,
+        twcc_receiver: false,
+        ..Default::default()
+    });
+    pipeline
+        .add(&webrtcredux)
+        .expect("Failed to add webrtcredux to the pipeline");
+
+    let src = gst::ElementFactory::make("videotestsrc", None).unwrap();
+    let encoder = gst::ElementFactory::make("vp8enc", None).unwrap();
+    pipeline.add_many(&[&src, &encoder]).unwrap();
+    Element::link_many(&[&src, &encoder, webrtcredux.as_ref()]).expect("Failed to link elements");
+
+    pipeline.set_state(gst::State::Playing).expect("Failed to set pipeline state");
+    let _ = pipeline.state(gst::ClockTime::from_seconds(5));
+
+    let offer = runtime
+        .block_on(webrtcredux.create_offer(None))
+        .expect("Failed to create offer")
+        .to_string(LineEnding::CRLF);
+
+    assert!(offer.contains("m=video 9 UDP/TLS/RTP/SAVPF 120\r\n"), "Only the configured codec should be offered: {}", offer);
+    assert!(offer.contains("a=rtpmap:120 VP8/90000"));
+    assert!(offer.contains("a=rtcp-fb:120 nack pli"));
+    assert!(!offer.contains("goog-remb"));
+    assert!(!offer.contains("transport-cc"));
+    assert!(!offer.contains("a=rtcp-fb:120 nack\r\n"));
+
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+/// Negotiates the element's main connection with a webrtc-rs peer playing the remote side, the tracks it receives
+/// are sent on the returned channel
+fn connect_viewer(
+    runtime: &tokio::runtime::Runtime,
+    webrtcredux: &WebRtcRedux,
+) -> (
+    Arc<webrtc::peer_connection::RTCPeerConnection>,
+    tokio::sync::mpsc::UnboundedReceiver<Arc<webrtc::track::track_remote::TrackRemote>>,
+) {
+    let mut media_engine = webrtc::api::media_engine::MediaEngine::default();
+    media_engine.register_default_codecs().unwrap();
+    connect_viewer_with(runtime, webrtcredux, media_engine)
+}
+
+fn connect_viewer_with(
+    runtime: &tokio::runtime::Runtime,
+    webrtcredux: &WebRtcRedux,
+    media_engine: webrtc::api::media_engine::MediaEngine,
+) -> (
+    Arc<webrtc::peer_connection::RTCPeerConnection>,
+    tokio::sync::mpsc::UnboundedReceiver<Arc<webrtc::track::track_remote::TrackRemote>>,
+) {
+    runtime.block_on(async {
+        let (viewer, rx) = new_viewer(media_engine).await;
+
+        let offer = webrtcredux.create_offer(None).await.expect("Failed to create offer");
+        let mut gathering_complete = webrtcredux.gathering_complete_promise().await.unwrap();
+        webrtcredux.set_local_description(&offer, RTCSdpType::Offer).await.expect("Failed to set local description");
+        let _ = gathering_complete.recv().await;
+        let offer = webrtcredux.local_description().await.unwrap().unwrap();
+
+        let answer = answer_as_viewer(&viewer, &offer).await;
+        webrtcredux.set_remote_description(&answer, RTCSdpType::Answer).await.expect("Failed to apply answer");
+
+        (viewer, rx)
+    })
+}
+
+/// A plain webrtc-rs peer connection that hands out the tracks it receives
+async fn new_viewer(
+    media_engine: webrtc::api::media_engine::MediaEngine,
+) -> (
+    Arc<webrtc::peer_connection::RTCPeerConnection>,
+    tokio::sync::mpsc::UnboundedReceiver<Arc<webrtc::track::track_remote::TrackRemote>>,
+) {
+    use webrtc::api::APIBuilder;
+    use webrtc::peer_connection::configuration::RTCConfiguration;
+
+    let api = APIBuilder::new().with_media_engine(media_engine).build();
+    let viewer = Arc::new(api.new_peer_connection(RTCConfiguration::default()).await.unwrap());
+
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    viewer.on_track(Box::new(move |track, _receiver| {
+        if let Some(track) = track {
+            let _ = tx.send(track);
+        }
+        Box::pin(async {})
+    }));
+
+    (viewer, rx)
+}
+
+/// Applies a complete offer to the viewer and returns its complete answer
+async fn answer_as_viewer(viewer: &webrtc::peer_connection::RTCPeerConnection, offer: &SDP) -> SDP {
+    use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+    let mut description = RTCSessionDescription::default();
+    description.sdp = offer.to_string(LineEnding::CRLF);
+    description.sdp_type = RTCSdpType::Offer;
+    viewer.set_remote_description(description).await.expect("Viewer failed to apply the offer");
+
+    let answer = viewer.create_answer(None).await.unwrap();
+    let mut gathering_complete = viewer.gathering_complete_promise().await;
+    viewer.set_local_description(answer).await.unwrap();
+    let _ = gathering_complete.recv().await;
+    SDP::from_str(&viewer.local_description().await.unwrap().sdp).expect("Answer should be valid SDP")
+}
+
+#[test]
+fn keyframe_requests() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use webrtc::rtcp::payload_feedbacks::full_intra_request::FullIntraRequest;
+    use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+    use webrtc::rtcp::receiver_report::ReceiverReport;
+
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let pipeline = gst::Pipeline::new(None);
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+    let src = gst::ElementFactory::make("videotestsrc", None).unwrap();
+    src.set_property("is-live", true);
+    let encoder = gst::ElementFactory::make("vp8enc", None).unwrap();
+    pipeline.add_many(&[&src, &encoder, webrtcredux.upcast_ref()]).unwrap();
+    Element::link_many(&[&src, &encoder, webrtcredux.as_ref()]).expect("Failed to link elements");
+
+    let requests = Arc::new(AtomicUsize::new(0));
+    let probe_requests = requests.clone();
+    encoder.static_pad("src").unwrap().add_probe(gst::PadProbeType::EVENT_UPSTREAM, move |_, info| {
+        if let Some(gst::PadProbeData::Event(event)) = &info.data {
+            if gst_video::UpstreamForceKeyUnitEvent::parse(event).is_ok() {
+                probe_requests.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        gst::PadProbeReturn::Ok
+    });
+
+    pipeline.set_state(gst::State::Playing).expect("Failed to set pipeline state");
+    runtime.block_on(webrtcredux.wait_for_all_tracks());
+    let (viewer, mut tracks) = connect_viewer(&runtime, &webrtcredux);
+
+    type Rtcp = Box<dyn webrtc::rtcp::packet::Packet + Send + Sync>;
+    let send_rtcp = |packets: Vec<Rtcp>| {
+        runtime.block_on(async {
+            viewer.write_rtcp(&packets).await.expect("Failed to send RTCP");
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        });
+    };
+
+    let ssrc = runtime.block_on(async {
+        let track = tokio::time::timeout(Duration::from_secs(10), tracks.recv())
+            .await
+            .expect("No track received")
+            .unwrap();
+        track.ssrc()
+    });
+    assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+    send_rtcp(vec![Box::new(ReceiverReport::default())]);
+    assert_eq!(requests.load(Ordering::SeqCst), 0, "Only PLI and FIR request keyframes");
+
+    // A burst, as when several broadcast peers lose the same packet
+    send_rtcp((0..5).map(|_| Box::new(PictureLossIndication { sender_ssrc: 0, media_ssrc: ssrc }) as Rtcp).collect());
+    send_rtcp(vec![Box::new(PictureLossIndication { sender_ssrc: 0, media_ssrc: ssrc })]);
+    assert_eq!(requests.load(Ordering::SeqCst), 1, "Requests within the minimum interval should be merged");
+
+    thread::sleep(Duration::from_millis(500));
+    send_rtcp(vec![Box::new(FullIntraRequest { sender_ssrc: 0, media_ssrc: ssrc, fir: vec![] })]);
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+    runtime.block_on(viewer.close()).unwrap();
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+#[test]
+fn receiver_bandwidth_estimate() {
+    use webrtc::rtcp::payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate;
+
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let pipeline = gst::Pipeline::new(None);
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+    webrtcredux.set_property("encoder-bitrate-property", "target-bitrate");
+    webrtcredux.set_property("encoder-bitrate-divisor", 1u32);
+    let src = gst::ElementFactory::make("videotestsrc", None).unwrap();
+    src.set_property("is-live", true);
+    let encoder = gst::ElementFactory::make("vp8enc", None).unwrap();
+    pipeline.add_many(&[&src, &encoder, webrtcredux.upcast_ref()]).unwrap();
+    Element::link_many(&[&src, &encoder, webrtcredux.as_ref()]).expect("Failed to link elements");
+
+    let targets = Arc::new(Mutex::new(Vec::new()));
+    let signal_targets = targets.clone();
+    webrtcredux.connect("on-target-bitrate", false, move |values| {
+        signal_targets.lock().unwrap().push(values[1].get::<u32>().unwrap());
+        None
+    });
+
+    pipeline.set_state(gst::State::Playing).expect("Failed to set pipeline state");
+    runtime.block_on(webrtcredux.wait_for_all_tracks());
+    let (viewer, mut tracks) = connect_viewer(&runtime, &webrtcredux);
+
+    runtime.block_on(async {
+        let track = tokio::time::timeout(Duration::from_secs(10), tracks.recv())
+            .await
+            .expect("No track received")
+            .unwrap();
+        let remb: Box<dyn webrtc::rtcp::packet::Packet + Send + Sync> = Box::new(ReceiverEstimatedMaximumBitrate {
+            bitrate: 500_000.0,
+            ssrcs: vec![track.ssrc()],
+            ..Default::default()
+        });
+        viewer.write_rtcp(&[remb]).await.expect("Failed to send RTCP");
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    });
+
+    assert_eq!(*targets.lock().unwrap(), vec![500_000], "The REMB estimate should cap the target");
+    assert_eq!(webrtcredux.property::<u32>("target-bitrate"), 500_000);
+    assert_eq!(encoder.property::<i32>("target-bitrate"), 500_000, "The encoder should follow the target");
+
+    runtime.block_on(viewer.close()).unwrap();
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+/// Offers a data channel and returns the address and port of every gathered host candidate
+fn gather_host_candidates(runtime: &tokio::runtime::Runtime, webrtcredux: &WebRtcRedux) -> Vec<(String, u16)> {
+    let description = runtime.block_on(async {
+        webrtcredux.create_data_channel("data", None).await.expect("Failed to create data channel");
+        let offer = webrtcredux.create_offer(None).await.expect("Failed to create offer");
+        let mut gathering_complete = webrtcredux.gathering_complete_promise().await.unwrap();
+        webrtcredux.set_local_description(&offer, RTCSdpType::Offer).await.expect("Failed to set local description");
+        let _ = gathering_complete.recv().await;
+        webrtcredux.local_description().await.unwrap().unwrap().to_string(LineEnding::LF)
+    });
+
+    description
+        .lines()
+        .filter_map(|line| line.strip_prefix("a=candidate:"))
+        .filter(|candidate| candidate.contains("typ host"))
+        .map(|candidate| {
+            let fields = candidate.split_whitespace().collect::<Vec<_>>();
+            (fields[4].to_string(), fields[5].parse().unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn network_settings() {
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let pipeline = gst::Pipeline::new(None);
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+    webrtcredux.set_property("min-port", 40100u32);
+    webrtcredux.set_property("max-port", 40110u32);
+    webrtcredux.set_property("nat-1to1-ips", "203.0.113.7");
+    assert_eq!(webrtcredux.property::<u32>("max-port"), 40110);
+    pipeline
+        .add(&webrtcredux)
+        .expect("Failed to add webrtcredux to the pipeline");
+
+    pipeline.set_state(gst::State::Ready).expect("Failed to set pipeline state");
+
+    let candidates = gather_host_candidates(&runtime, &webrtcredux);
+    assert!(!candidates.is_empty(), "No host candidates gathered");
+    for (address, port) in candidates {
+        assert_eq!(address, "203.0.113.7", "Host candidates should advertise the NAT IP");
+        assert!((40100..=40110).contains(&port), "Port {} is outside the configured range", port);
+    }
+
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+#[test]
+fn network_filters() {
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let gather_with = |properties: &[(&str, &str)]| {
+        let pipeline = gst::Pipeline::new(None);
+        let webrtcredux = WebRtcRedux::default();
+        webrtcredux.set_tokio_runtime(runtime.handle().clone());
+        for (name, value) in properties {
+            webrtcredux.set_property(name, *value);
+        }
+        webrtcredux.set_property("ice-disconnected-timeout-ms", 1000u32);
+        pipeline
+            .add(&webrtcredux)
+            .expect("Failed to add webrtcredux to the pipeline");
+
+        let candidates = pipeline
+            .set_state(gst::State::Ready)
+            .ok()
+            .map(|_| gather_host_candidates(&runtime, &webrtcredux));
+        pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+        candidates
+    };
+
+    let candidates = gather_with(&[("network-types", "udp4"), ("mdns-mode", "disabled")]).expect("Failed to start");
+    for (address, _) in &candidates {
+        assert!(address.parse::<std::net::Ipv4Addr>().is_ok(), "{} is not an IPv4 address", address);
+    }
+
+    assert_eq!(gather_with(&[("ip-filter", "192.0.2.1")]), Some(vec![]), "The IP filter should drop every local address");
+    assert_eq!(gather_with(&[("interface-filter", "nonexistent0")]), Some(vec![]), "The interface filter should drop every interface");
+
+    let candidates = gather_with(&[("nat-1to1-ips", "203.0.113.7"), ("nat-1to1-candidate-type", "srflx")]).expect("Failed to start");
+    assert!(
+        candidates.iter().all(|(address, _)| address != "203.0.113.7"),
+        "Server reflexive NAT IPs should leave the host candidates alone"
+    );
+
+    assert_eq!(gather_with(&[("network-types", "udp4,sctp")]), None, "An invalid network type should fail the state change");
+    assert_eq!(gather_with(&[("network-types", "tcp4")]), None, "TCP candidates can't be gathered by webrtc-rs");
+
+    // A bad value can be corrected on the same element
+    let pipeline = gst::Pipeline::new(None);
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+    webrtcredux.set_property("mdns-mode", "sometimes");
+    pipeline
+        .add(&webrtcredux)
+        .expect("Failed to add webrtcredux to the pipeline");
+    assert!(pipeline.set_state(gst::State::Ready).is_err(), "An invalid mode should fail the state change");
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+
+    webrtcredux.set_property("mdns-mode", "disabled");
+    pipeline.set_state(gst::State::Ready).expect("Ready should succeed once the property is fixed");
+    assert!(!gather_host_candidates(&runtime, &webrtcredux).is_empty(), "No host candidates gathered");
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+#[test]
+fn shared_udp_mux() {
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let pipeline = gst::Pipeline::new(None);
+    let elements = (0..2)
+        .map(|_| {
+            let webrtcredux = WebRtcRedux::default();
+            webrtcredux.set_tokio_runtime(runtime.handle().clone());
+            webrtcredux.set_property("udp-mux-port", 40200u32);
+            pipeline
+                .add(&webrtcredux)
+                .expect("Failed to add webrtcredux to the pipeline");
+            webrtcredux
+        })
+        .collect::<Vec<_>>();
+
+    pipeline.set_state(gst::State::Ready).expect("Failed to set pipeline state");
+
+    for webrtcredux in &elements {
+        let candidates = gather_host_candidates(&runtime, webrtcredux);
+        assert!(!candidates.is_empty(), "No host candidates gathered");
+        assert!(candidates.iter().all(|(_, port)| *port == 40200), "All elements should share the mux port: {:?}", candidates);
+    }
+
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+
+    // The socket is closed once the last element using it stops
+    let released = (0..50).any(|_| {
+        thread::sleep(Duration::from_millis(20));
+        std::net::UdpSocket::bind(("0.0.0.0", 40200)).is_ok()
+    });
+    assert!(released, "The mux port should be released");
+}
+
+#[test]
+fn dtls_certificate() {
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let certificate = rcgen::generate_simple_self_signed(vec!["webrtcredux".to_string()]).expect("Failed to generate certificate");
+    let key_pem = certificate.serialize_private_key_pem();
+    let cert_pem = certificate.serialize_pem().expect("Failed to serialize certificate");
+
+    let pipeline = gst::Pipeline::new(None);
+    let elements = (0..3)
+        .map(|i| {
+            let webrtcredux = WebRtcRedux::default();
+            webrtcredux.set_tokio_runtime(runtime.handle().clone());
+            // The last element keeps the generated certificate
+            if i < 2 {
+                webrtcredux.set_certificate_pem(&key_pem, &cert_pem).expect("Failed to set certificate");
+            }
+            pipeline
+                .add(&webrtcredux)
+                .expect("Failed to add webrtcredux to the pipeline");
+            webrtcredux
+        })
+        .collect::<Vec<_>>();
+
+    assert!(WebRtcRedux::default().set_certificate_pem(&key_pem, "not a certificate").is_err());
+    let other_key_pem = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256).unwrap().serialize_pem();
+    assert!(WebRtcRedux::default().set_certificate_pem(&other_key_pem, &cert_pem).is_err(), "The key should have to match the certificate");
+    let mut expired_params = rcgen::CertificateParams::new(vec!["webrtcredux".to_string()]);
+    expired_params.not_before = rcgen::date_time_ymd(2000, 1, 1);
+    expired_params.not_after = rcgen::date_time_ymd(2001, 1, 1);
+    let expired = rcgen::Certificate::from_params(expired_params).unwrap();
+    assert!(
+        WebRtcRedux::default().set_certificate_pem(&expired.serialize_private_key_pem(), &expired.serialize_pem().unwrap()).is_err(),
+        "Expired certificates should be refused"
+    );
+    assert_eq!(elements[0].property::<Option<String>>("dtls-fingerprint"), None);
+
+    pipeline.set_state(gst::State::Ready).expect("Failed to set pipeline state");
+
+    let fingerprints = elements
+        .iter()
+        .map(|webrtcredux| {
+            let fingerprint = webrtcredux.property::<Option<String>>("dtls-fingerprint").expect("No fingerprint");
+            let offer = runtime.block_on(async {
+                webrtcredux.create_data_channel("data", None).await.expect("Failed to create data channel");
+                webrtcredux.create_offer(None).await.expect("Failed to create offer").to_string(LineEnding::LF)
+            });
+            assert!(offer.contains(&format!("a=fingerprint:{}", fingerprint)), "The offer should carry the fingerprint {}", fingerprint);
+            fingerprint
+        })
+        .collect::<Vec<_>>();
+
+    assert!(fingerprints[0].starts_with("sha-256 "));
+    assert_eq!(fingerprints[0], fingerprints[1], "The same certificate should give the same fingerprint");
+    assert_ne!(fingerprints[0], fingerprints[2]);
+    assert!(
+        elements[0].set_certificates(vec![generate_certificate().unwrap()]).is_err(),
+        "Certificates set after Ready would never be used"
+    );
+
+    let local = runtime.block_on(elements[0].local_fingerprints()).expect("Failed to get fingerprints");
+    assert_eq!(format!("{} {}", local[0].algorithm, local[0].value), fingerprints[0]);
+
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+
+    // Certificates set directly are only checked by webrtc-rs, the state change fails instead of panicking
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+    let dtls_certificate = webrtc::dtls::crypto::Certificate::generate_self_signed(vec!["webrtcredux".to_string()]).unwrap();
+    webrtcredux
+        .set_certificates(vec![webrtc::peer_connection::certificate::RTCCertificate::from_existing(dtls_certificate, std::time::SystemTime::UNIX_EPOCH)])
+        .expect("Failed to set certificates");
+    assert!(webrtcredux.set_state(gst::State::Ready).is_err());
+    webrtcredux.set_state(gst::State::Null).expect("Failed to set state");
+}
+
+#[test]
+fn ice_restart() {
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let pipeline = gst::Pipeline::new(None);
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+    webrtcredux.set_property("ice-restart-timeout-ms", 2000u32);
+    assert_eq!(webrtcredux.property::<u32>("ice-restart-timeout-ms"), 2000);
+    pipeline
+        .add(&webrtcredux)
+        .expect("Failed to add webrtcredux to the pipeline");
+
+    assert!(runtime.block_on(webrtcredux.restart_ice()).is_err(), "ICE can only be restarted once the element is ready");
+
+    pipeline.set_state(gst::State::Ready).expect("Failed to set pipeline state");
+
+    let restarts = Arc::new(Mutex::new(Vec::new()));
+    let signal_restarts = restarts.clone();
+    webrtcredux.connect("on-ice-restart", false, move |values| {
+        signal_restarts
+            .lock()
+            .unwrap()
+            .push((values[1].get::<bool>().unwrap(), values[2].get::<Option<String>>().unwrap()));
+        None
+    });
+
+    let (first, second, third) = runtime.block_on(async {
+        webrtcredux.create_data_channel("data", None).await.expect("Failed to create data channel");
+        let offer = webrtcredux.create_offer(None).await.expect("Failed to create offer");
+        webrtcredux.set_local_description(&offer, RTCSdpType::Offer).await.expect("Failed to set local description");
+
+        webrtcredux.restart_ice().await.expect("Failed to restart ICE");
+        let restarted = webrtcredux.create_offer(None).await.expect("Failed to create offer");
+        let unchanged = webrtcredux.create_offer(None).await.expect("Failed to create offer");
+
+        (offer.ice_credentials(), restarted.ice_credentials(), unchanged.ice_credentials())
+    });
+
+    assert_ne!(first, second, "A restart offer should have fresh ICE credentials");
+    assert_eq!(second, third, "Only the next offer restarts ICE");
+    assert_eq!(*restarts.lock().unwrap(), vec![(false, None)]);
+
+    webrtcredux.emit_by_name::<()>("restart-ice", &[]);
+    assert_eq!(*restarts.lock().unwrap(), vec![(false, None), (false, None)]);
+
+    // Broadcast peers report their restarts through the same signal
+    runtime.block_on(async {
+        let peer = webrtcredux.add_peer("viewer-1").await.expect("Failed to add peer");
+        peer.restart_ice().await.expect("Failed to restart ICE of peer");
+    });
+    assert_eq!(restarts.lock().unwrap().last(), Some(&(false, Some("viewer-1".to_string()))));
+
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+#[test]
+fn h264_profiles() {
+    init();
+    let webrtcredux = WebRtcRedux::default();
+    let templ = webrtcredux.pad_template("video_%u").unwrap();
+
+    for profile in ["constrained-baseline", "baseline", "main", "high"] {
+        let caps = gst::Caps::builder("video/x-h264")
+            .field("stream-format", "byte-stream")
+            .field("profile", profile)
+            .build();
+        assert!(templ.caps().can_intersect(&caps), "Profile {} not accepted", profile);
+    }
+
+    let caps = gst::Caps::builder("video/x-h264")
+        .field("stream-format", "byte-stream")
+        .field("profile", "high-4:4:4")
+        .build();
+    assert!(!templ.caps().can_intersect(&caps));
+}
+
+#[test]
+fn h264_main_profile_negotiation() {
+    use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_H264};
+    use webrtc::rtp_transceiver::rtp_codec::{RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType};
+
+    init();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let pipeline = gst::Pipeline::new(None);
+    let webrtcredux = WebRtcRedux::default();
+    webrtcredux.set_tokio_runtime(runtime.handle().clone());
+    let src = gst::ElementFactory::make("videotestsrc", None).unwrap();
+    src.set_property("is-live", true);
+    let encoder = gst::ElementFactory::make("x264enc", None).unwrap();
+    let capsfilter = gst::ElementFactory::make("capsfilter", None).unwrap();
+    capsfilter.set_property("caps", gst::Caps::from_str("video/x-h264,profile=main,level=(string)3.1").unwrap());
+    pipeline.add_many(&[&src, &encoder, &capsfilter, webrtcredux.upcast_ref()]).unwrap();
+    Element::link_many(&[&src, &encoder, &capsfilter, webrtcredux.as_ref()]).expect("Failed to link elements");
+
+    pipeline.set_state(gst::State::Playing).expect("Failed to set pipeline state");
+    runtime.block_on(webrtcredux.wait_for_all_tracks());
+
+    // A viewer that only takes main profile, like a hardware decoder might
+    let mut media_engine = MediaEngine::default();
+    media_engine
+        .register_codec(
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_H264.to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=4d001f".to_owned(),
+                    rtcp_feedback: vec![],
+                },
+                payload_type: 35,
+                ..Default::default()
+            },
+            RTPCodecType::Video,
+        )
+        .unwrap();
+    let (viewer, mut tracks) = connect_viewer_with(&runtime, &webrtcredux, media_engine);
+
+    let answer = runtime.block_on(viewer.local_description()).unwrap().sdp;
+    assert!(answer.contains("a=fmtp:35 level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=4d001f"), "{}", answer);
+
+    let codec = runtime.block_on(async {
+        let track = tokio::time::timeout(Duration::from_secs(10), tracks.recv())
+            .await
+            .expect("No track received")
+            .unwrap();
+        track.codec().await
+    });
+    assert_eq!(codec.payload_type, 35);
+    assert!(codec.capability.sdp_fmtp_line.contains("profile-level-id=4d001f"));
+
+    runtime.block_on(viewer.close()).unwrap();
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+#[test]
+fn data_pad_request() {
+    init();
+    let webrtcredux = WebRtcRedux::default();
+
+    let pad = webrtcredux.request_pad_simple("data_%u");
+    assert!(pad.is_some(), "Failed to request data pad");
+    assert_eq!(pad.unwrap().name(), "data_0");
+
+    let templ = webrtcredux.pad_template("recv_data_%u");
+    assert!(templ.is_some(), "Missing pad template recv_data_%u");
+}
+
+#[test]
+fn element_properties() {
+    init();
+    let webrtcredux = WebRtcRedux::default();
+
+    webrtcredux.set_property("stun-server", "stun://stun.l.google.com:19302");
+    assert_eq!(
+        webrtcredux.property::<Option<String>>("stun-server").as_deref(),
+        Some("stun://stun.l.google.com:19302")
+    );
+
+    webrtcredux.set_property("bundle-policy", "max-bundle");
+    assert_eq!(
+        webrtcredux.property::<Option<String>>("bundle-policy").as_deref(),
+        Some("max-bundle")
+    );
+
+    webrtcredux.set_property("ice-transport-policy", "relay");
+    assert_eq!(
+        webrtcredux.property::<Option<String>>("ice-transport-policy").as_deref(),
+        Some("relay")
+    );
+
+    // Typos keep the previous value instead of silently becoming unspecified
+    webrtcredux.set_property("bundle-policy", "balancd");
+    assert_eq!(
+        webrtcredux.property::<Option<String>>("bundle-policy").as_deref(),
+        Some("max-bundle")
+    );
+    webrtcredux.set_property("ice-transport-policy", "rely");
+    assert_eq!(
+        webrtcredux.property::<Option<String>>("ice-transport-policy").as_deref(),
+        Some("relay")
+    );
+
+    for signal in ["create-offer", "create-answer", "set-local-description", "set-remote-description", "add-ice-candidate", "on-ice-candidate", "on-negotiation-needed"] {
+        assert!(
+            gst::glib::subclass::SignalId::lookup(signal, WebRtcRedux::static_type()).is_some(),
+            "Missing signal {}",
+            signal
+        );
+    }
+}
+
+#[test]
+fn unknown_description_type() {
+    init();
+    let pipeline = gst::Pipeline::new(None);
+    let webrtcredux = WebRtcRedux::default();
+    pipeline
+        .add(&webrtcredux)
+        .expect("Failed to add webrtcredux to the pipeline");
+    pipeline.set_state(gst::State::Ready).expect("Failed to set pipeline state");
+
+    let sdp = "v=0\r\no=- 0 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n";
+    let promise = gst::Promise::new();
+    webrtcredux.emit_by_name::<()>("set-remote-description", &[&"offr", &sdp, &promise]);
+    assert_eq!(promise.wait(), gst::PromiseResult::Replied);
+    let error = promise.get_reply().and_then(|reply| reply.get::<String>("error").ok());
+    assert!(
+        error.map_or(false, |error| error.contains("Unknown description type offr")),
+        "Unknown description types should be rejected"
+    );
+
+    let promise = gst::Promise::new();
+    webrtcredux.emit_by_name::<()>("set-remote-description", &[&None::<String>, &None::<String>, &promise]);
+    assert_eq!(promise.wait(), gst::PromiseResult::Replied);
+    assert!(
+        promise.get_reply().map_or(false, |reply| reply.has_field("error")),
+        "NULL descriptions should be rejected"
+    );
+
+    // Must not panic
+    webrtcredux.emit_by_name::<()>("add-ice-candidate", &[&0u32, &None::<String>]);
+
+    pipeline.set_state(gst::State::Null).expect("Failed to set pipeline state");
+}
+
+#[test]
+fn ipv6_multicast_connection() {
+    // IPv6 has no TTL, a single option is the number of addresses (RFC 4566 section 5.7)
+    let connection = SdpProp::from_str("c=IN IP6 FF15::101/3").unwrap();
+    assert_eq!(
+        connection,
+        SdpProp::Connection {
+            net_type: NetworkType::Internet,
+            address_type: AddressType::IPv6,
+            address: "FF15::101".to_string(),
+            ttl: None,
+            num_addresses: Some(3),
+            suffix: None,
+        }
+    );
+    assert_eq!(connection.to_string(), "c=IN IP6 FF15::101/3");
+
+    let connection = MediaProp::from_str("c=IN IP4 224.2.1.1/127/3").unwrap();
+    assert!(matches!(connection, MediaProp::Connection { ttl: Some(127), num_addresses: Some(3), .. }));
+    assert!(MediaProp::from_str("c=IN IP6 FF15::101/127/3").is_err());
+}
+
+#[test]
+fn sdp_typed_attributes() {
+    let text = "v=0\r\no=- 8488083020976882093 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\na=group:BUNDLE 0 1\r\na=msid-semantic: WMS\r\nm=video 55395 UDP/TLS/RTP/SAVPF 96 97\r\nc=IN IP4 2.39.73.41\r\na=candidate:842163049 1 udp 1677729535 2.39.73.41 55395 typ srflx raddr 0.0.0.0 rport 0 generation 0 network-cost 999\r\na=ice-ufrag:nVwA\r\na=ice-pwd:tyR7PZVvcMN4/aqQLrcBFuU5\r\na=fingerprint:sha-256 62:E4:9A:F9:6A:F5:B4:E3:52:07:4F:8E:C4:9F:27:16:9B:DA:D1:18:00:19:5F:8A:69:E2:D9:F6:AC:F0:64:51\r\na=setup:actpass\r\na=mid:0\r\na=extmap:1 urn:ietf:params:rtp-hdrext:toffset\r\na=extmap:2/sendonly http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time\r\na=sendrecv\r\na=msid:- aef93e5f-0aeb-4c4d-807e-fadaf721fc63\r\na=rtcp-mux\r\na=rtpmap:96 VP8/90000\r\na=rtcp-fb:96 nack pli\r\na=rtpmap:97 rtx/90000\r\na=fmtp:97 apt=96\r\na=rid:hi send max-width=1280\r\na=simulcast:send hi;lo\r\na=ssrc-group:FID 2188188946 3056071260\r\na=ssrc:2188188946 cname:QGl7AJpaZdNMdnjK\r\na=ssrc:2188188946 msid:- aef93e5f-0aeb-4c4d-807e-fadaf721fc63\r\nm=audio 34179 UDP/TLS/RTP/SAVPF 111\r\na=rtpmap:111 opus/48000/2\r\na=fmtp:111 minptime=10;useinbandfec=1\r\n";
+
+    let sdp = SDP::from_str(text).unwrap();
+
+    let mut attributes = vec![];
+    for prop in &sdp.props {
+        if let Some(attribute) = prop.attribute() {
+            attributes.push((attribute, prop.clone()));
+        }
+
+        if let SdpProp::Media { props, .. } = prop {
+            for media_prop in props {
+                if let Some(attribute) = media_prop.attribute() {
+                    assert_eq!(MediaProp::from(attribute.clone()), *media_prop);
+                    assert_eq!(SdpAttribute::from_str(&attribute.to_string()).unwrap(), attribute);
+                }
+            }
+        }
+    }
+
+    for (attribute, prop) in attributes {
+        assert_eq!(SdpProp::from(attribute), prop);
+    }
+
+    let video_props = match &sdp.props[6] {
+        SdpProp::Media { props, .. } => props.iter().filter_map(|prop| prop.attribute()).collect::<Vec<_>>(),
+        _ => panic!("Expected a media section"),
+    };
+
+    assert!(video_props.contains(&SdpAttribute::Candidate(Candidate {
+        foundation: "842163049".to_string(),
+        component: 1,
+        transport: "udp".to_string(),
+        priority: 1677729535,
+        address: "2.39.73.41".to_string(),
+        port: 55395,
+        typ: "srflx".to_string(),
+        related_address: Some("0.0.0.0".to_string()),
+        related_port: Some(0),
+        extensions: vec![
+            ("generation".to_string(), "0".to_string()),
+            ("network-cost".to_string(), "999".to_string()),
+        ],
+        related_position: 0,
+    })));
+    assert!(video_props.contains(&SdpAttribute::Setup(SetupRole::ActPass)));
+    assert!(video_props.contains(&SdpAttribute::Direction(Direction::SendRecv)));
+    assert!(video_props.contains(&SdpAttribute::Extmap {
+        id: 2,
+        direction: Some(Direction::SendOnly),
+        uri: "http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time".to_string(),
+        extension_attributes: None,
+    }));
+    assert!(video_props.contains(&SdpAttribute::Rtpmap {
+        payload_type: 96,
+        encoding_name: "VP8".to_string(),
+        clock_rate: 90000,
+        encoding_params: None,
+    }));
+    assert!(video_props.contains(&SdpAttribute::Generic {
+        key: "rtcp-mux".to_string(),
+        value: None,
+    }));
+
+    assert_eq!(
+        sdp.props[4].attribute(),
+        Some(SdpAttribute::Group {
+            semantics: "BUNDLE".to_string(),
+            mids: vec!["0".to_string(), "1".to_string()],
+        })
+    );
+
+    assert_eq!(text, sdp.to_string(LineEnding::CRLF));
+}
+
+#[test]
+fn candidate_token_order() {
+    for text in [
+        "842163049 1 udp 1677729535 2.39.73.41 55395 typ srflx raddr 0.0.0.0 rport 0 generation 0",
+        "842163049 1 udp 1677729535 2.39.73.41 55395 typ srflx generation 0 raddr 0.0.0.0 rport 0 network-cost 999",
+        "842163049 1 udp 1677729535 2.39.73.41 55395 typ srflx generation 0 network-cost 999 rport 0 raddr 0.0.0.0",
+        "3859917557 1 udp 2113937151 192.168.1.2 34179 typ host generation 0",
+    ] {
+        assert_eq!(Candidate::from_str(text).unwrap().to_string(), text.replace("rport 0 raddr 0.0.0.0", "raddr 0.0.0.0 rport 0"));
+    }
+
+    let candidate = Candidate::from_str("842163049 1 udp 1677729535 2.39.73.41 55395 typ srflx generation 0 raddr 0.0.0.0 rport 0").unwrap();
+    assert_eq!(candidate.related_position, 1);
+    assert_eq!(candidate.related_address.as_deref(), Some("0.0.0.0"));
+    assert_eq!(candidate.related_port, Some(0));
+}
+
+#[test]
+fn sdp_editing() {
+    let text = "v=0\r\no=- 8488083020976882093 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\na=group:BUNDLE 0 1\r\nm=video 9 UDP/TLS/RTP/SAVPF 96 97 98 99 127\r\nc=IN IP4 0.0.0.0\r\na=candidate:842163049 1 udp 1677729535 2.39.73.41 55395 typ srflx raddr 0.0.0.0 rport 0 generation 0\r\na=end-of-candidates\r\na=ice-ufrag:nVwA\r\na=ice-pwd:tyR7PZVvcMN4/aqQLrcBFuU5\r\na=mid:0\r\na=sendrecv\r\na=rtpmap:96 VP8/90000\r\na=rtcp-fb:96 nack pli\r\na=rtpmap:97 rtx/90000\r\na=fmtp:97 apt=96\r\na=rtpmap:98 VP9/90000\r\na=fmtp:98 profile-id=0\r\na=rtpmap:99 rtx/90000\r\na=fmtp:99 apt=98\r\na=rtpmap:127 H264/90000\r\na=fmtp:127 level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42001f\r\nm=audio 9 UDP/TLS/RTP/SAVPF 111\r\nc=IN IP4 0.0.0.0\r\na=mid:1\r\na=rtpmap:111 opus/48000/2\r\n";
+
+    let mut sdp = SDP::from_str(text).unwrap();
+
+    assert_eq!(sdp.media_sections().count(), 2);
+    assert!(sdp.media_by_mid("1").is_some());
+    assert!(sdp.media_by_mid("2").is_none());
+    assert_eq!(
+        sdp.ice_credentials(),
+        Some(("nVwA".to_string(), "tyR7PZVvcMN4/aqQLrcBFuU5".to_string()))
+    );
+
+    let codecs = sdp.codecs_for("0");
+    assert_eq!(
+        codecs.iter().map(|codec| codec.encoding_name.as_str()).collect::<Vec<_>>(),
+        vec!["VP8", "rtx", "VP9", "rtx", "H264"]
+    );
+    assert_eq!(codecs[0].rtcp_fb, vec!["nack pli".to_string()]);
+    assert_eq!(codecs[2].fmtp.as_deref(), Some("profile-id=0"));
+
+    // RTX streams follow the codec they repair
+    let mut reordered = SDP::from_str(text).unwrap();
+    assert!(reordered.reorder_codecs("0", &["VP9"]));
+    assert_eq!(
+        reordered.codecs_for("0").iter().map(|codec| codec.payload_type).collect::<Vec<_>>(),
+        vec![98, 99, 96, 97, 127]
+    );
+
+    // The only audio codec can't go, the m-line would be left without formats
+    assert!(!sdp.remove_codec("1", "opus"));
+    assert_eq!(sdp.codecs_for("1").len(), 1);
+
+    // VP9 and the RTX stream repairing it go away, VP8's RTX stays
+    assert!(sdp.remove_codec("0", "vp9"));
+    assert!(!sdp.remove_codec("0", "AV1"));
+    assert_eq!(
+        sdp.codecs_for("0").iter().map(|codec| codec.payload_type).collect::<Vec<_>>(),
+        vec![96, 97, 127]
+    );
+
+    assert!(sdp.reorder_codecs("0", &["H264"]));
+    assert_eq!(
+        sdp.codecs_for("0").iter().map(|codec| codec.payload_type).collect::<Vec<_>>(),
+        vec![127, 96, 97]
+    );
+
+    assert!(sdp.set_bandwidth("0", 2500));
+    assert!(sdp.set_bandwidth("0", 1500));
+    assert!(sdp.set_direction("0", Direction::SendOnly));
+
+    sdp.strip_candidates();
+    assert!(sdp.add_candidate(
+        "1",
+        Candidate::from_str("3859917557 1 udp 2113937151 192.168.1.2 34179 typ host generation 0").unwrap()
+    ));
+
+    let video_props = match sdp.media_by_mid("0") {
+        Some(SdpProp::Media { props, .. }) => props.clone(),
+        _ => panic!("Expected a media section"),
+    };
+    assert_eq!(
+        video_props[1],
+        MediaProp::Bandwidth {
+            r#type: BandwidthType::ApplicationSpecific,
+            bandwidth: 1500,
+        }
+    );
+    assert_eq!(
+        video_props.iter().filter(|prop| matches!(prop, MediaProp::Bandwidth { .. })).count(),
+        1
+    );
+    assert!(video_props.contains(&MediaProp::from(SdpAttribute::Direction(Direction::SendOnly))));
+    assert!(!video_props.contains(&MediaProp::from(SdpAttribute::Direction(Direction::SendRecv))));
+    assert!(!video_props.iter().any(|prop| matches!(prop.attribute(), Some(SdpAttribute::Candidate(_)))));
+
+    let serialized = sdp.to_string(LineEnding::CRLF);
+    assert!(serialized.contains("m=video 9 UDP/TLS/RTP/SAVPF 127 96 97\r\nc=IN IP4 0.0.0.0\r\nb=AS:1500\r\n"));
+    assert!(!serialized.contains("end-of-candidates"));
+    assert!(serialized.contains("a=candidate:3859917557 1 udp 2113937151 192.168.1.2 34179 typ host generation 0\r\n"));
+}
+
+#[test]
+fn sdp_malformed_input() {
+    let malformed = [
+        "v=0\r\no=- 1\r\n",
+        "v=0\r\nc=IN IP4 1.2.3.4/1/2/3\r\n",
+        "v=0\r\nc=IN\r\n",
+        "v=0\r\nb=AS\r\n",
+        "v=0\r\nt=0\r\n",
+        "v=0\r\nz=2882844526\r\n",
+        "v=0\r\nm=\r\n",
+        "v=0\r\nm=video\r\n",
+        "v=0\r\nm=video 9 UDP/TLS/RTP/SAVPF 96\r\nc=IN IP4\r\n",
+        "v=0\r\n=no key\r\n",
+        "v=0\r\nno equals sign\r\n",
+        "v=0\r\nvv=0\r\n",
+        "=",
+        "v=256",
+    ];
+
+    for text in malformed {
+        assert!(SDP::from_str(text).is_err(), "{text:?} should not parse");
+    }
+
+    match SDP::from_str("v=0\r\ns=-\r\nt=0\r\n") {
+        Err(ParseError::Line { number, text, reason }) => {
+            assert_eq!(number, 3);
+            assert_eq!(text, "t=0");
+            assert_eq!(*reason, ParseError::MissingToken("stop time"));
+        }
+        other => panic!("Unexpected result {other:?}"),
+    }
+
+    let text = "v=0\nx=unknown\ns=-\nnot a line\nm=audio 9 UDP/TLS/RTP/SAVPF 111\ny=also unknown\na=mid:0\n";
+
+    match SDP::parse(text, ParseMode::Strict) {
+        Err(ParseError::Line { number, reason, .. }) => {
+            assert_eq!(number, 2);
+            assert_eq!(*reason, ParseError::UnknownKey('x', "unknown".to_string()));
+        }
+        other => panic!("Unexpected result {other:?}"),
+    }
+
+    let sdp = SDP::parse(text, ParseMode::Lenient).unwrap();
+    assert_eq!(sdp.props.len(), 3);
+    assert_eq!(
+        sdp.props[2],
+        SdpProp::Media {
+            r#type: MediaType::Audio,
+            ports: vec![9],
+            protocol: "UDP/TLS/RTP/SAVPF".to_string(),
+            format: "111".to_string(),
+            props: vec![MediaProp::Attribute {
+                key: "mid".to_string(),
+                value: Some("0".to_string()),
+            }],
+        }
+    );
+}
+
+#[test]
+fn payloader_h265() {
+    let mut idr = vec![0x26, 0x01];
+    idr.extend((0..3000).map(|i| (i % 251) as u8 + 1));
+
+    let mut access_unit = vec![0, 0, 0, 1, 0x46, 0x01, 0x50, 0, 0, 0, 1, 0x40, 0x01, 0x0c, 0x01, 0, 0, 1];
+    access_unit.extend(&idr);
+
+    let payloads = H265Payloader::default()
+        .payload(1188, &Bytes::from(access_unit))
+        .unwrap();
+
+    // The access unit delimiter is dropped, the VPS fits in a single NAL unit packet
+    assert_eq!(payloads[0], Bytes::from_static(&[0x40, 0x01, 0x0c, 0x01]));
+
+    let fragments = &payloads[1..];
+    assert_eq!(fragments.len(), 3);
+    for (i, fragment) in fragments.iter().enumerate() {
+        assert!(fragment.len() <= 1188);
+        assert_eq!(&fragment[..2], &[49 << 1, 0x01]);
+
+        let expected_fu_header = match i {
+            0 => 0x80 | 19,
+            2 => 0x40 | 19,
+            _ => 19,
+        };
+        assert_eq!(fragment[2], expected_fu_header);
+    }
+
+    let reassembled = fragments.iter().flat_map(|fragment| fragment[3..].to_vec()).collect::<Vec<_>>();
+    assert_eq!(reassembled, idr[2..]);
+}
+
+#[test]
+fn payloader_av1() {
+    let frame = (0..2500).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+    // Temporal delimiter, sequence header and frame, all with size fields
+    let mut temporal_unit = vec![0x12, 0x00, 0x0a, 0x03, 1, 2, 3, 0x32, 0xc4, 0x13];
+    temporal_unit.extend(&frame);
+
+    let payloads = Av1Payloader::default()
+        .payload(1188, &Bytes::from(temporal_unit))
+        .unwrap();
+
+    assert_eq!(payloads.len(), 3);
+    // N is only set on the first packet, Z and Y mark the fragmented frame
+    assert_eq!(payloads[0][0], 0b0100_1000);
+    assert_eq!(payloads[1][0], 0b1100_0000);
+    assert_eq!(payloads[2][0], 0b1000_0000);
+
+    let mut obus: Vec<Vec<u8>> = Vec::new();
+    let mut continuation = false;
+    for payload in &payloads {
+        assert!(payload.len() <= 1188);
+
+        let mut data = &payload[1..];
+        let mut first = true;
+        while !data.is_empty() {
+            let (mut size, mut size_len) = (0usize, 0);
+            loop {
+                let byte = data[size_len];
+                size |= ((byte & 0x7f) as usize) << (7 * size_len);
+                size_len += 1;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+
+            let element = data[size_len..size_len + size].to_vec();
+            if first && continuation {
+                obus.last_mut().unwrap().extend(element);
+            } else {
+                obus.push(element);
+            }
+
+            data = &data[size_len + size..];
+            first = false;
+        }
+        continuation = payload[0] & 0b0100_0000 != 0;
+    }
+
+    let mut expected_frame = vec![0x30];
+    expected_frame.extend(&frame);
+    assert_eq!(obus, vec![vec![0x08, 1, 2, 3], expected_frame]);
+}
+
+fn sdp_token() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9+.#_-]{1,16}"
+}
+
+fn sdp_line_text() -> impl Strategy<Value = String> {
+    "[^\r\n]*"
+}
+
+fn sdp_words() -> impl Strategy<Value = String> {
+    vec(sdp_token(), 1..4).prop_map(|words| words.join(" "))
+}
+
+fn connection_strategy() -> impl Strategy<Value = (NetworkType, AddressType, String, Option<usize>, Option<usize>, Option<String>)> {
+    (
+        Just(NetworkType::Internet),
+        prop_oneof![Just(AddressType::IPv4), Just(AddressType::IPv6)],
+        sdp_token(),
+        option::of((any::<usize>(), option::of(any::<usize>()))),
+        option::of(sdp_words()),
+    )
+        .prop_map(|(net_type, address_type, address, options, suffix)| {
+            let (ttl, num_addresses) = match address_type {
+                // IPv6 has no TTL, the only option is the number of addresses
+                AddressType::IPv6 => (None, options.map(|(num, _)| num)),
+                // The number of addresses can only be given along with a TTL
+                AddressType::IPv4 => (options.map(|(ttl, _)| ttl), options.and_then(|(_, num)| num)),
+            };
+            (net_type, address_type, address, ttl, num_addresses, suffix)
+        })
+}
+
+fn bandwidth_type_strategy() -> impl Strategy<Value = BandwidthType> {
+    prop_oneof![Just(BandwidthType::ConferenceTotal), Just(BandwidthType::ApplicationSpecific)]
+}
+
+fn encryption_key_strategy() -> impl Strategy<Value = EncryptionKeyMethod> {
+    prop_oneof![
+        sdp_line_text().prop_map(EncryptionKeyMethod::Clear),
+        sdp_line_text().prop_map(EncryptionKeyMethod::Base64),
+        sdp_line_text().prop_map(EncryptionKeyMethod::Uri),
+        Just(EncryptionKeyMethod::Prompt),
+    ]
+}
+
+fn attribute_strategy() -> impl Strategy<Value = (String, Option<String>)> {
+    ("[a-z0-9-]{1,16}", option::of(sdp_line_text()))
+}
+
+fn media_prop_strategy() -> impl Strategy<Value = MediaProp> {
+    prop_oneof![
+        sdp_line_text().prop_map(MediaProp::Title),
+        connection_strategy().prop_map(|(net_type, address_type, address, ttl, num_addresses, suffix)| {
+            MediaProp::Connection { net_type, address_type, address, ttl, num_addresses, suffix }
+        }),
+        (bandwidth_type_strategy(), any::<usize>())
+            .prop_map(|(r#type, bandwidth)| MediaProp::Bandwidth { r#type, bandwidth }),
+        encryption_key_strategy().prop_map(MediaProp::EncryptionKeys),
+        attribute_strategy().prop_map(|(key, value)| MediaProp::Attribute { key, value }),
+    ]
+}
+
+fn session_prop_strategy() -> impl Strategy<Value = SdpProp> {
+    prop_oneof![
+        any::<u8>().prop_map(SdpProp::Version),
+        (sdp_token(), sdp_token(), any::<usize>(), prop_oneof![Just(AddressType::IPv4), Just(AddressType::IPv6)], sdp_token())
+            .prop_map(|(username, session_id, session_version, address_type, address)| SdpProp::Origin {
+                username,
+                session_id,
+                session_version,
+                net_type: NetworkType::Internet,
+                address_type,
+                address,
+            }),
+        sdp_line_text().prop_map(SdpProp::SessionName),
+        sdp_line_text().prop_map(SdpProp::SessionInformation),
+        sdp_line_text().prop_map(SdpProp::Uri),
+        sdp_line_text().prop_map(SdpProp::Email),
+        sdp_line_text().prop_map(SdpProp::Phone),
+        connection_strategy().prop_map(|(net_type, address_type, address, ttl, num_addresses, suffix)| {
+            SdpProp::Connection { net_type, address_type, address, ttl, num_addresses, suffix }
+        }),
+        (bandwidth_type_strategy(), any::<usize>())
+            .prop_map(|(r#type, bandwidth)| SdpProp::Bandwidth { r#type, bandwidth }),
+        (any::<usize>(), any::<usize>()).prop_map(|(start, stop)| SdpProp::Timing { start, stop }),
+        (sdp_token(), sdp_token(), vec(sdp_token(), 0..4)).prop_map(|(interval, active_duration, start_offsets)| {
+            SdpProp::RepeatTimes { interval, active_duration, start_offsets }
+        }),
+        vec((any::<usize>(), sdp_token()), 1..4).prop_map(|adjustments| SdpProp::TimeZone(
+            adjustments
+                .into_iter()
+                .map(|(time, offset)| TimeZoneAdjustment { time, offset })
+                .collect()
+        )),
+        encryption_key_strategy().prop_map(SdpProp::EncryptionKeys),
+        attribute_strategy().prop_map(|(key, value)| SdpProp::Attribute { key, value }),
+    ]
+}
+
+fn media_section_strategy() -> impl Strategy<Value = SdpProp> {
+    (
+        prop_oneof![
+            Just(MediaType::Audio),
+            Just(MediaType::Video),
+            Just(MediaType::Text),
+            Just(MediaType::Application)
+        ],
+        vec(any::<u16>(), 1..3),
+        "[a-zA-Z0-9/]{1,20}",
+        vec(sdp_token(), 0..4),
+        vec(media_prop_strategy(), 0..6),
+    )
+        .prop_map(|(r#type, ports, protocol, format, props)| SdpProp::Media {
+            r#type,
+            ports,
+            protocol,
+            format: format.join(" "),
+            props,
+        })
+}
+
+proptest! {
+    #[test]
+    fn sdp_media_prop_round_trip(prop in media_prop_strategy()) {
+        let text = prop.to_string();
+        prop_assert_eq!(MediaProp::from_str(&text), Ok(prop));
+    }
+
+    #[test]
+    fn sdp_prop_round_trip(prop in prop_oneof![session_prop_strategy(), media_section_strategy()]) {
+        for ending in [LineEnding::CRLF, LineEnding::LF] {
+            let text = prop.to_string(ending);
+            prop_assert_eq!(SdpProp::from_str(&text), Ok(prop.clone()));
+        }
+    }
+
+    #[test]
+    fn sdp_round_trip(session in vec(session_prop_strategy(), 0..8), media in vec(media_section_strategy(), 0..4)) {
+        let sdp = SDP { props: session.into_iter().chain(media).collect() };
+
+        for ending in [LineEnding::CRLF, LineEnding::LF] {
+            let parsed = SDP::from_str(&sdp.to_string(ending));
+            prop_assert_eq!(parsed.as_ref(), Ok(&sdp));
+        }
+    }
+}